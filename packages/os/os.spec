@@ -14,6 +14,7 @@ Source3: eni-max-pods
 
 Source4: root.json
 Source5: updog-toml
+Source6: api-access-policy.toml
 
 # 1xx sources: systemd units
 Source100: apiserver.service
@@ -132,6 +133,14 @@ Summary: Bottlerocket GPT priority querier/switcher
 %description -n %{_cross_os}signpost
 %{summary}.
 
+%package -n %{_cross_os}healthdog
+Summary: Checks that a boot is healthy before marking it successful
+Requires: %{_cross_os}apiserver = %{version}-%{release}
+Requires: %{_cross_os}signpost = %{version}-%{release}
+Requires: %{_cross_os}thar-be-updates = %{version}-%{release}
+%description -n %{_cross_os}healthdog
+%{summary}.
+
 %package -n %{_cross_os}updog
 Summary: Bottlerocket updater CLI
 %description -n %{_cross_os}updog
@@ -176,6 +185,7 @@ mkdir bin
     -p settings-committer \
     -p migrator \
     -p signpost \
+    -p healthdog \
     -p updog \
     -p logdog \
     -p growpart \
@@ -193,6 +203,11 @@ for crate in $(find %{_builddir}/sources/api/migration/migrations -name 'Cargo.t
     %cargo_build_static --manifest-path "${crate}"
 done
 
+# Build the runner for declarative migrations, which rpm2migrations combines with each description
+%cargo_build_static --manifest-path %{_builddir}/sources/api/migration/migration-helpers/Cargo.toml \
+    --bin declarative-migration \
+    %{nil}
+
 %install
 install -d %{buildroot}%{_cross_bindir}
 for p in \
@@ -201,7 +216,7 @@ for p in \
   thar-be-settings thar-be-updates servicedog host-containers \
   storewolf settings-committer \
   migrator \
-  signpost updog logdog \
+  signpost healthdog updog logdog \
 %if "%{_cross_variant}" == "aws-ecs-1"
   ecs-settings-applier \
 %endif
@@ -225,6 +240,16 @@ for version_path in %{_builddir}/sources/api/migration/migrations/*; do
     [ -e "${migration_path}" ] || continue
 
     version="${version_path##*/}"
+
+    # Declarative migrations are installed as descriptions, and built by rpm2migrations.
+    if [[ "${migration_path}" == *.toml ]] ; then
+      description_name="${migration_path##*/}"
+      description_name="${description_name%.toml}"
+      target_path="%{buildroot}%{_cross_datadir}/migrations/migrate_${version}_${description_name#migrate-}.toml"
+      install -m 0444 "${migration_path}" "${target_path}"
+      continue
+    fi
+
     crate_name="${migration_path##*/}"
     migration_binary_name="migrate_${version}_${crate_name#migrate-}"
     built_path="${HOME}/.cache/.static/%{__cargo_target_static}/release/${crate_name}"
@@ -234,11 +259,19 @@ for version_path in %{_builddir}/sources/api/migration/migrations/*; do
   done
 done
 
+install -d %{buildroot}%{_cross_libexecdir}/migrations
+install -m 0555 \
+  ${HOME}/.cache/.static/%{__cargo_target_static}/release/declarative-migration \
+  %{buildroot}%{_cross_libexecdir}/migrations
+
 install -d %{buildroot}%{_cross_datadir}/bottlerocket
 
 install -d %{buildroot}%{_cross_sysusersdir}
 install -p -m 0644 %{S:2} %{buildroot}%{_cross_sysusersdir}/api.conf
 
+install -d %{buildroot}%{_cross_datadir}/apiserver
+install -p -m 0644 %{S:6} %{buildroot}%{_cross_datadir}/apiserver/access-policy.toml
+
 install -d %{buildroot}%{_cross_datadir}/eks
 install -p -m 0644 %{S:3} %{buildroot}%{_cross_datadir}/eks
 
@@ -270,6 +303,8 @@ install -p -m 0644 %{S:202} %{buildroot}%{_cross_tmpfilesdir}/thar-be-updates.co
 %{_cross_unitdir}/apiserver.service
 %{_cross_unitdir}/migrator.service
 %{_cross_sysusersdir}/api.conf
+%dir %{_cross_datadir}/apiserver
+%{_cross_datadir}/apiserver/access-policy.toml
 
 %files -n %{_cross_os}apiclient
 %{_cross_bindir}/apiclient
@@ -323,6 +358,8 @@ install -p -m 0644 %{S:202} %{buildroot}%{_cross_tmpfilesdir}/thar-be-updates.co
 %files -n %{_cross_os}migrations
 %dir %{_cross_datadir}/migrations
 %{_cross_datadir}/migrations
+%dir %{_cross_libexecdir}/migrations
+%{_cross_libexecdir}/migrations/declarative-migration
 
 %files -n %{_cross_os}settings-committer
 %{_cross_bindir}/settings-committer
@@ -332,6 +369,9 @@ install -p -m 0644 %{S:202} %{buildroot}%{_cross_tmpfilesdir}/thar-be-updates.co
 
 %files -n %{_cross_os}signpost
 %{_cross_bindir}/signpost
+
+%files -n %{_cross_os}healthdog
+%{_cross_bindir}/healthdog
 %{_cross_unitdir}/mark-successful-boot.service
 
 %files -n %{_cross_os}updog
@@ -50,6 +50,7 @@ Requires: %{_cross_os}netdog
 Requires: %{_cross_os}selinux-policy
 Requires: %{_cross_os}policycoreutils
 Requires: %{_cross_os}signpost
+Requires: %{_cross_os}healthdog
 Requires: %{_cross_os}sundog
 Requires: %{_cross_os}pluto
 Requires: %{_cross_os}storewolf
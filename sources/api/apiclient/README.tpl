@@ -9,6 +9,68 @@ The `apiclient` binary helps you talk to an HTTP API over a Unix-domain socket.
 It talks to the Bottlerocket socket by default.
 It can be pointed to another socket using `--socket-path`, for example for local testing.
 
+It has commands for common tasks, and can also make requests to any URI directly.
+
+### Settings
+
+Use `set` to change settings.
+Give each setting as a dotted key name and a value; `apiclient` makes the changes in a new transaction of its own, then commits and applies them for you.
+If that fails, the transaction is deleted, so nothing is left pending.
+
+```
+apiclient set motd="my own value!"
+apiclient set kubernetes.node-labels.foo=bar kubernetes.node-labels.count=1
+```
+
+Values are strings, so `count=1` sets the string "1".
+To set booleans, numbers, or lists, use `--json` to have values parsed as JSON; then strings must be quoted, for example:
+
+```
+apiclient set --json host-containers.admin.enabled=true motd='"my own value!"'
+```
+
+Key names that contain dots, like some Kubernetes labels, can be quoted too: `kubernetes.node-labels."example.com/role"=worker`
+
+Use `get` to see settings.
+With no keys, it prints all settings; otherwise, it prints the keys you give, in the shape the API uses.
+
+```
+apiclient get
+apiclient get settings.motd kubernetes.node-labels
+```
+
+The `settings.` prefix is optional; you can also get `services`, `configuration-files`, and `os`, for example `apiclient get services.chronyd`.
+
+### Transactions
+
+To see how a pending transaction compares with live settings, use `diff`.
+It lists each key that committing would add (`+`), change (`~`), or remove (`-`), with its old and new values:
+
+```
+apiclient diff
+```
+
+Use `--tx FOO` to review a transaction other than the default, for example `--tx bottlerocket-launch`.
+
+### Updates
+
+`update check` refreshes the list of available updates and prints the update status.
+`update apply` downloads the chosen update and marks it to be used on the next boot; add `--reboot` to reboot right away.
+`update cancel` undoes `update apply` if you haven't rebooted yet.
+Each command waits for the update step to finish, and fails if the step fails.
+
+```
+apiclient update check
+apiclient update apply --reboot
+```
+
+You can also reboot with `apiclient reboot`.
+If `settings.updates.maintenance-windows` is set, the API refuses to apply updates or reboot outside of those windows, whether from `update apply`, `update apply --reboot`, or `reboot`.
+Add `--ignore-maintenance-windows` to any of those commands to go ahead anyway.
+
+### Raw requests
+
+For anything else, you can make requests to the API directly.
 The URI path is specified with `-u` or `--uri`, for example `-u /settings`.
 This should include the query string, if any.
 
@@ -19,8 +81,6 @@ Specify the data after `-d` or `--data`.
 
 To see verbose response data, including the HTTP status code, use `-v` or `--verbose`.
 
-### Example usage
-
 Getting settings:
 
 ```
@@ -0,0 +1,100 @@
+//! The diff module fetches and renders the changes that committing a transaction would make to
+//! live settings, so they can be reviewed before committing.
+
+use crate::{json_request, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+/// The old and new value of a key, in serialized form; None means the key isn't set.
+#[derive(Debug, Deserialize)]
+pub struct Change {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// The changes that committing a transaction would make, as returned by `/tx/diff`.
+#[derive(Debug, Deserialize)]
+pub struct TransactionDiff {
+    pub added: HashMap<String, String>,
+    pub changed: HashMap<String, Change>,
+    pub removed: HashMap<String, String>,
+}
+
+impl TransactionDiff {
+    /// Returns true if committing the transaction wouldn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Renders one line per key, sorted by key, marked with '+' for added keys, '-' for removed keys,
+/// and '~' for changed keys.
+impl fmt::Display for TransactionDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = BTreeMap::new();
+        for (key, new) in &self.added {
+            lines.insert(key, format!("+ {} = {}", key, new));
+        }
+        for (key, old) in &self.removed {
+            lines.insert(key, format!("- {} = {}", key, old));
+        }
+        for (key, change) in &self.changed {
+            let old = change.old.as_deref().unwrap_or("(unset)");
+            let new = change.new.as_deref().unwrap_or("(unset)");
+            lines.insert(key, format!("~ {} = {} -> {}", key, old, new));
+        }
+        for line in lines.values() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches the changes that committing the given transaction would make to live settings.
+pub fn get_transaction_diff<P, S>(socket_path: P, transaction: S) -> Result<TransactionDiff>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    json_request(socket_path, diff_uri(transaction.as_ref()), "GET", None)
+}
+
+/// Returns the API URI for the diff of the given transaction, escaping its name for the query.
+fn diff_uri(transaction: &str) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("tx", transaction)
+        .finish();
+    format!("/tx/diff?{}", query)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_diff() {
+        let diff: TransactionDiff = serde_json::from_str(
+            r#"{
+                "added": {"settings.b": "\"new\""},
+                "changed": {"settings.a": {"old": "\"x\"", "new": "\"y\""}},
+                "removed": {"settings.c": "\"gone\""}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            diff.to_string(),
+            "~ settings.a = \"x\" -> \"y\"\n+ settings.b = \"new\"\n- settings.c = \"gone\"\n"
+        );
+    }
+
+    #[test]
+    fn escaped_uri() {
+        assert_eq!(
+            diff_uri("bottlerocket-launch"),
+            "/tx/diff?tx=bottlerocket-launch"
+        );
+        assert_eq!(diff_uri("a b&c=d#e"), "/tx/diff?tx=a+b%26c%3Dd%23e");
+    }
+}
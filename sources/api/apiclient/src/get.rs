@@ -0,0 +1,82 @@
+//! The get module fetches the values of dotted key names like "settings.motd" or
+//! "services.chronyd".
+//!
+//! Keys that don't start with one of the top-level sections of the API model ("settings",
+//! "services", "configuration-files", or "os") are assumed to be settings, so "motd" is the same
+//! as "settings.motd".  Values are returned in the shape of the model, so the result can be sent
+//! back to the API.
+
+use crate::{error, json_request, split_key, Result};
+use serde_json::{Map, Value};
+use snafu::OptionExt;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The top-level sections of the model and the URIs that return them.
+const SECTIONS: &[(&str, &str)] = &[
+    ("settings", "/settings"),
+    ("services", "/services"),
+    ("configuration-files", "/configuration-files"),
+    ("os", "/os"),
+];
+
+/// Fetches the values of the given keys.  If no keys are given, returns all settings.
+pub fn get<P, S>(socket_path: P, keys: &[S]) -> Result<Value>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let socket_path = socket_path.as_ref();
+    if keys.is_empty() {
+        return get(socket_path, &["settings"]);
+    }
+
+    // Fetch each section we need once, no matter how many keys are in it.
+    let mut sections: HashMap<&str, Value> = HashMap::new();
+    let mut result = Map::new();
+    for key in keys {
+        let key = key.as_ref();
+        let mut segments = split_key(key)?;
+        let (section, uri) = match SECTIONS.iter().find(|(name, _)| *name == segments[0]) {
+            Some(section) => *section,
+            None => {
+                segments.insert(0, "settings".to_string());
+                SECTIONS[0]
+            }
+        };
+
+        let section_value = match sections.get(section) {
+            Some(value) => value,
+            None => {
+                let value = json_request(socket_path, uri, "GET", None)?;
+                sections.entry(section).or_insert(value)
+            }
+        };
+
+        let mut value = section_value;
+        for segment in &segments[1..] {
+            value = value.get(segment).context(error::MissingKey { key })?;
+        }
+        insert(&mut result, &segments, value.clone());
+    }
+    Ok(Value::Object(result))
+}
+
+/// Inserts a value at the path given by `segments`, creating objects along the way.  Values at
+/// overlapping paths are merged.
+fn insert(map: &mut Map<String, Value>, segments: &[String], value: Value) {
+    let (first, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if rest.is_empty() {
+        map.insert(first.clone(), value);
+        return;
+    }
+    let inner = map
+        .entry(first.clone())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(inner) = inner {
+        insert(inner, rest, value);
+    }
+}
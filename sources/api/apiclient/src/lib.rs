@@ -7,8 +7,12 @@
 //! socket, and requires you to specify the socket path, the URI (including query string), the
 //! HTTP method, and any request body data.
 //!
-//! In the future, we intend to add methods that understand the Bottlerocket API and help more with common
-//! types of requests.
+//! The other modules understand the Bottlerocket API and help with common types of requests:
+//! * `get` fetches the values of dotted key names like "settings.motd".
+//! * `set` changes settings given as "key=value" pairs, then commits and applies them.
+//! * `diff` renders the changes a pending transaction would make so you can review them before
+//!   committing.
+//! * `update` checks for, applies, and cancels OS updates, waiting for each step to finish.
 
 // Think "reqwest" but for Unix-domain sockets.  Would be nice to use the simpler reqwest instead
 // of hyper, but it lacks Unix-domain socket support:
@@ -21,6 +25,11 @@ use snafu::{ensure, ResultExt};
 use std::path::Path;
 use tokio::runtime::Runtime;
 
+pub mod diff;
+pub mod get;
+pub mod set;
+pub mod update;
+
 mod error {
     use snafu::Snafu;
     use std::io;
@@ -50,6 +59,39 @@ mod error {
 
         #[snafu(display("Response was not UTF-8: {}", source))]
         NonUtf8Response { source: std::string::FromUtf8Error },
+
+        #[snafu(display("Unable to parse response from {}: {}", uri, source))]
+        ResponseJson {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Invalid key '{}': {}", key, reason))]
+        InvalidKey { key: String, reason: String },
+
+        #[snafu(display("Expected 'key=value', got '{}'", input))]
+        MissingValue { input: String },
+
+        #[snafu(display("Value of '{}' isn't valid JSON: {}", key, source))]
+        JsonValue {
+            key: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Key '{}' is given more than once, or along with a key under it", key))]
+        ConflictingKeys { key: String },
+
+        #[snafu(display("Unable to serialize settings: {}", source))]
+        SettingsSerialization { source: serde_json::Error },
+
+        #[snafu(display("Key '{}' not found", key))]
+        MissingKey { key: String },
+
+        #[snafu(display("Update command '{}' failed: {}", command, stderr))]
+        UpdateCommand { command: String, stderr: String },
+
+        #[snafu(display("Timed out after {} seconds waiting for update command '{}'", seconds, command))]
+        UpdateTimeout { command: String, seconds: u64 },
     }
 }
 pub use error::Error;
@@ -133,3 +175,72 @@ where
 
     Ok((head.status, body))
 }
+
+/// Makes a request to the given URI on the socket and parses the response body as JSON.
+pub(crate) fn json_request<P, S1, S2, T>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+) -> Result<T>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+    T: serde::de::DeserializeOwned,
+{
+    let (_status, body) = raw_request(socket_path, &uri, method, data)?;
+    serde_json::from_str(&body).context(error::ResponseJson {
+        uri: uri.as_ref(),
+    })
+}
+
+/// Splits a dotted key name like "settings.motd" into its segments.  Segments that contain dots,
+/// like some Kubernetes label names, can be quoted, as in the data store:
+/// `settings.kubernetes.node-labels."example.com/role"`
+pub(crate) fn split_key(key: &str) -> Result<Vec<String>> {
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut in_quotes = false;
+    for c in key.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '.' if !in_quotes => segments.push(std::mem::take(&mut segment)),
+            _ => segment.push(c),
+        }
+    }
+    segments.push(segment);
+
+    ensure!(
+        !in_quotes,
+        error::InvalidKey {
+            key,
+            reason: "unbalanced quotes"
+        }
+    );
+    ensure!(
+        segments.iter().all(|s| !s.is_empty()),
+        error::InvalidKey {
+            key,
+            reason: "empty segment"
+        }
+    );
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_key_works() {
+        assert_eq!(split_key("settings.motd").unwrap(), vec!["settings", "motd"]);
+        assert_eq!(
+            split_key("kubernetes.node-labels.\"example.com/role\"").unwrap(),
+            vec!["kubernetes", "node-labels", "example.com/role"]
+        );
+        assert!(split_key("settings..motd").is_err());
+        assert!(split_key("settings.\"motd").is_err());
+        assert!(split_key("").is_err());
+    }
+}
@@ -7,24 +7,67 @@ const DEFAULT_API_SOCKET: &str = "/run/api.sock";
 struct Args {
     verbosity: usize,
     socket_path: String,
-    method: String,
-    uri: String,
-    data: Option<String>,
+    mode: Mode,
+}
+
+/// The kinds of request the user can make.
+enum Mode {
+    /// Sends a request to the given URI and prints the response body.
+    Raw {
+        method: String,
+        uri: String,
+        data: Option<String>,
+    },
+    /// Changes settings given as "key=value" pairs, and commits and applies them.
+    Set { pairs: Vec<String>, json: bool },
+    /// Prints the values of the given keys, or all settings.
+    Get { keys: Vec<String> },
+    /// Shows the changes that committing a transaction would make.
+    Diff { transaction: String },
+    /// Runs an update step.
+    Update {
+        action: UpdateAction,
+        reboot: bool,
+        ignore_maintenance_windows: bool,
+    },
+    /// Reboots the host.
+    Reboot { ignore_maintenance_windows: bool },
+}
+
+enum UpdateAction {
+    Check,
+    Apply,
+    Cancel,
 }
 
 /// Informs the user about proper usage of the program and exits.
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
     eprintln!(
-        r"Usage: {}
-            (-u | --uri) URI
-            [ (-X | -m | --method) METHOD ]
-            [ (-d | --data) DATA ]
-            [ (-s | --socket-path) PATH ]
-            [ -v | --verbose ... ]
+        r"Usage: {0} [ (-s | --socket-path) PATH ] [ -v | --verbose ... ] COMMAND
 
+  Commands:
+    set [ --json ] KEY=VALUE ...
+                                Change settings, then commit and apply them
+    get [ KEY ... ]             Print the given keys, or all settings
+    diff [ --tx TRANSACTION ]   Show what committing a transaction would change
+    update check                Refresh the list of available updates and print the status
+    update apply [ --reboot ] [ --ignore-maintenance-windows ]
+                                Download and activate the chosen update, then optionally reboot;
+                                outside of update maintenance windows, only if told to ignore them
+    update cancel               Deactivate an applied update
+    reboot [ --ignore-maintenance-windows ]
+                                Reboot the host; outside of update maintenance windows, only
+                                if told to ignore them
+
+  Requests can also be made directly:
+    {0} (-u | --uri) URI [ (-X | -m | --method) METHOD ] [ (-d | --data) DATA ]
+
+    Keys are dotted names like 'settings.motd'; the 'settings.' prefix is optional
+    Values are strings, unless --json is given to parse them as JSON
     Method defaults to GET
-    Socket path defaults to {}",
+    Transaction defaults to 'default'
+    Socket path defaults to {1}",
         program_name, DEFAULT_API_SOCKET
     );
     process::exit(2);
@@ -43,6 +86,13 @@ fn parse_args(args: env::Args) -> Args {
     let mut method = None;
     let mut uri = None;
     let mut data = None;
+    let mut transaction = None;
+    let mut reboot = false;
+    let mut ignore_maintenance_windows = false;
+    let mut json = false;
+    // The first positional argument is the command; any others are its arguments.
+    let mut command: Option<String> = None;
+    let mut command_args = Vec::new();
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -77,30 +127,179 @@ fn parse_args(args: env::Args) -> Args {
                 )
             }
 
-            _ => usage(),
+            "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --tx")),
+                )
+            }
+
+            "--reboot" => reboot = true,
+
+            "--ignore-maintenance-windows" => ignore_maintenance_windows = true,
+
+            "--json" => json = true,
+
+            x if x.starts_with('-') => usage_msg(format!("Unknown option '{}'", x)),
+
+            _ if command.is_none() => command = Some(arg),
+            _ => command_args.push(arg),
         }
     }
 
+    // Options that only make sense for certain commands.
+    let raw = method.is_some() || uri.is_some() || data.is_some();
+    if raw && command.is_some() {
+        usage_msg("-u, -m, and -d can't be used with a command");
+    }
+    if transaction.is_some() && command.as_deref() != Some("diff") {
+        usage_msg("--tx is only used with diff; add a 'tx' parameter to the URI instead");
+    }
+    if json && command.as_deref() != Some("set") {
+        usage_msg("--json is only used with set");
+    }
+    if reboot && command.as_deref() != Some("update") {
+        usage_msg("--reboot is only used with 'update apply'");
+    }
+    if ignore_maintenance_windows && !matches!(command.as_deref(), Some("reboot") | Some("update"))
+    {
+        usage_msg("--ignore-maintenance-windows is only used with reboot and 'update apply'");
+    }
+
+    let mode = match command.as_deref() {
+        None => Mode::Raw {
+            method: method.unwrap_or_else(|| "GET".to_string()),
+            uri: uri.unwrap_or_else(|| usage()),
+            data,
+        },
+
+        Some("set") => {
+            if command_args.is_empty() {
+                usage_msg("set requires at least one KEY=VALUE");
+            }
+            Mode::Set {
+                pairs: command_args,
+                json,
+            }
+        }
+
+        Some("get") => Mode::Get { keys: command_args },
+
+        Some("diff") => {
+            no_args("diff", &command_args);
+            Mode::Diff {
+                transaction: transaction.unwrap_or_else(|| "default".to_string()),
+            }
+        }
+
+        Some("update") => {
+            let action = match command_args.first().map(|s| s.as_str()) {
+                Some("check") => UpdateAction::Check,
+                Some("apply") => UpdateAction::Apply,
+                Some("cancel") => UpdateAction::Cancel,
+                _ => usage_msg("update requires one of: check, apply, cancel"),
+            };
+            no_args("update", &command_args[1..]);
+            if reboot && !matches!(action, UpdateAction::Apply) {
+                usage_msg("--reboot is only used with 'update apply'");
+            }
+            if ignore_maintenance_windows && !matches!(action, UpdateAction::Apply) {
+                usage_msg(
+                    "--ignore-maintenance-windows is only used with reboot and 'update apply'",
+                );
+            }
+            Mode::Update {
+                action,
+                reboot,
+                ignore_maintenance_windows,
+            }
+        }
+
+        Some("reboot") => {
+            no_args("reboot", &command_args);
+            Mode::Reboot {
+                ignore_maintenance_windows,
+            }
+        }
+
+        Some(x) => usage_msg(format!("Unknown command '{}'", x)),
+    };
+
     Args {
         verbosity,
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
-        method: method.unwrap_or_else(|| "GET".to_string()),
-        uri: uri.unwrap_or_else(|| usage()),
-        data,
+        mode,
     }
 }
 
+/// Exits through usage_msg() if a command was given arguments it doesn't take.
+fn no_args(command: &str, args: &[String]) {
+    if !args.is_empty() {
+        usage_msg(format!("Unexpected arguments to {}: {}", command, args.join(" ")));
+    }
+}
+
+/// Prints a JSON value for the user.
+fn print_json(value: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
 fn run() -> Result<(), Box<dyn std::error::Error>> {
     let args = parse_args(env::args());
 
-    let (status, body) =
-        apiclient::raw_request(args.socket_path, args.uri, args.method, args.data)?;
+    match args.mode {
+        Mode::Raw { method, uri, data } => {
+            let (status, body) = apiclient::raw_request(args.socket_path, uri, method, data)?;
 
-    if args.verbosity > 3 {
-        eprintln!("{}", status);
-    }
-    if !body.is_empty() {
-        println!("{}", body);
+            if args.verbosity > 3 {
+                eprintln!("{}", status);
+            }
+            if !body.is_empty() {
+                println!("{}", body);
+            }
+        }
+
+        Mode::Set { pairs, json } => {
+            let settings = apiclient::set::settings_from_pairs(&pairs, json)?;
+            apiclient::set::set(args.socket_path, &settings)?;
+        }
+
+        Mode::Get { keys } => {
+            let values = apiclient::get::get(args.socket_path, &keys)?;
+            print_json(&values)?;
+        }
+
+        Mode::Diff { transaction } => {
+            let diff = apiclient::diff::get_transaction_diff(args.socket_path, &transaction)?;
+            if diff.is_empty() {
+                println!("No changes in transaction '{}'", transaction);
+            } else {
+                print!("{}", diff);
+            }
+        }
+
+        Mode::Update {
+            action,
+            reboot,
+            ignore_maintenance_windows,
+        } => {
+            let status = match action {
+                UpdateAction::Check => apiclient::update::check(&args.socket_path)?,
+                UpdateAction::Apply => {
+                    apiclient::update::apply(&args.socket_path, ignore_maintenance_windows)?
+                }
+                UpdateAction::Cancel => apiclient::update::cancel(&args.socket_path)?,
+            };
+            print_json(&status)?;
+            if reboot {
+                apiclient::update::reboot(&args.socket_path, ignore_maintenance_windows)?;
+            }
+        }
+
+        Mode::Reboot {
+            ignore_maintenance_windows,
+        } => apiclient::update::reboot(&args.socket_path, ignore_maintenance_windows)?,
     }
     Ok(())
 }
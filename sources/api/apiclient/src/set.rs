@@ -0,0 +1,172 @@
+//! The set module changes settings given as "key=value" pairs, like
+//! "kubernetes.node-labels.foo=bar", and then commits and applies them.
+//!
+//! Keys are the dotted names of settings, with or without the leading "settings." segment.
+//! Values are strings, so `motd=42` sets the string "42".  To give booleans, numbers, or lists,
+//! ask for values to be parsed as JSON; then strings must be quoted, as in `motd='"hi"'`.
+
+use crate::{error, raw_request, split_key, Result};
+use serde_json::{Map, Value};
+use snafu::{OptionExt, ResultExt};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// We make changes in our own transaction so we don't commit anything else the user has pending.
+/// Each call gets a new one, so concurrent calls don't commit each other's changes.
+fn transaction_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("apiclient-set-{}-{}", process::id(), nanos)
+}
+
+/// Builds a Settings-shaped JSON object from "key=value" pairs.  If `json` is true, values are
+/// parsed as JSON; otherwise they're strings.
+pub fn settings_from_pairs<S: AsRef<str>>(pairs: &[S], json: bool) -> Result<Value> {
+    let mut settings = Map::new();
+    for pair in pairs {
+        let pair = pair.as_ref();
+        let mut split = pair.splitn(2, '=');
+        let key = split.next().unwrap_or_default();
+        let value = split.next().context(error::MissingValue { input: pair })?;
+
+        let mut segments = split_key(key)?;
+        if segments.len() > 1 && segments[0] == "settings" {
+            segments.remove(0);
+        }
+        let value = if json {
+            serde_json::from_str(value).context(error::JsonValue { key })?
+        } else {
+            Value::from(value)
+        };
+        insert(&mut settings, key, &segments, value)?;
+    }
+    Ok(Value::Object(settings))
+}
+
+/// Inserts a value at the path given by `segments`, creating objects along the way.
+fn insert(
+    map: &mut Map<String, Value>,
+    key: &str,
+    segments: &[String],
+    value: Value,
+) -> Result<()> {
+    let (first, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => unreachable!("split_key returned no segments for '{}'", key),
+    };
+    if rest.is_empty() {
+        if map.insert(first.clone(), value).is_some() {
+            return error::ConflictingKeys { key }.fail();
+        }
+        return Ok(());
+    }
+
+    match map
+        .entry(first.clone())
+        .or_insert_with(|| Value::Object(Map::new()))
+    {
+        Value::Object(inner) => insert(inner, key, rest, value),
+        _ => error::ConflictingKeys { key }.fail(),
+    }
+}
+
+/// Changes the given settings, then commits and applies them.  `settings` should be in the form
+/// of the API's Settings, for example as returned by `settings_from_pairs`.
+pub fn set<P: AsRef<Path>>(socket_path: P, settings: &Value) -> Result<()> {
+    let socket_path = socket_path.as_ref();
+    let data = serde_json::to_string(settings).context(error::SettingsSerialization)?;
+    let transaction = transaction_name();
+
+    let result = set_in_transaction(socket_path, &transaction, data);
+    if result.is_err() {
+        // Don't leave our changes pending for someone else to commit.  The original error is more
+        // useful than any error from cleaning up.
+        let uri = format!("/tx?tx={}", transaction);
+        let _ = raw_request(socket_path, &uri, "DELETE", None);
+    }
+    result
+}
+
+/// Changes settings in the given transaction, then commits and applies it.
+fn set_in_transaction(socket_path: &Path, transaction: &str, data: String) -> Result<()> {
+    let uri = format!("/settings?tx={}", transaction);
+    raw_request(socket_path, &uri, "PATCH", Some(data))?;
+
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    raw_request(socket_path, &uri, "POST", None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pairs_to_settings() {
+        let settings = settings_from_pairs(
+            &[
+                "motd=hi there",
+                "settings.kubernetes.node-labels.\"example.com/role\"=worker",
+                "kubernetes.node-labels.foo=bar",
+                "kubernetes.node-labels.count=1",
+                "kubernetes.node-labels.enabled=true",
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            json!({
+                "motd": "hi there",
+                "kubernetes": {"node-labels": {
+                    "example.com/role": "worker",
+                    "foo": "bar",
+                    "count": "1",
+                    "enabled": "true",
+                }},
+            })
+        );
+    }
+
+    #[test]
+    fn json_pairs_to_settings() {
+        let settings = settings_from_pairs(
+            &[
+                "motd=\"hi there\"",
+                "host-containers.admin.enabled=true",
+                "ntp.time-servers=[\"a\", \"b\"]",
+            ],
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            json!({
+                "motd": "hi there",
+                "host-containers": {"admin": {"enabled": true}},
+                "ntp": {"time-servers": ["a", "b"]},
+            })
+        );
+
+        // Strings must be quoted.
+        assert!(settings_from_pairs(&["motd=hi there"], true).is_err());
+    }
+
+    #[test]
+    fn unique_transactions() {
+        assert_ne!(transaction_name(), transaction_name());
+    }
+
+    #[test]
+    fn bad_pairs() {
+        assert!(settings_from_pairs(&["motd"], false).is_err());
+        assert!(settings_from_pairs(&["motd=a", "motd=b"], false).is_err());
+        assert!(
+            settings_from_pairs(&["kubernetes=a", "kubernetes.cluster-name=b"], false).is_err()
+        );
+    }
+}
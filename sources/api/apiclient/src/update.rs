@@ -0,0 +1,116 @@
+//! The update module checks for, applies, and cancels OS updates through the API.
+//!
+//! The API's update actions return as soon as the update dispatcher has started the command, so
+//! each function here waits for the command to finish by watching the update status, and
+//! returns an error if the command failed.
+
+use crate::{error, json_request, raw_request, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often we check the update status while waiting for a command.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long we wait for commands that only talk to the update repository or the disk.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long we wait for an update image to be downloaded and written.
+const PREPARE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Refreshes the list of available updates, and returns the resulting update status.
+pub fn check<P: AsRef<Path>>(socket_path: P) -> Result<Value> {
+    run_command(socket_path, "refresh-updates", "refresh", COMMAND_TIMEOUT)
+}
+
+/// Downloads the chosen update to the staging partition set and marks it to be used on the next
+/// boot.  Returns the resulting update status.  Outside of the update maintenance windows, the API
+/// refuses to do either unless `ignore_maintenance_windows` is set.
+pub fn apply<P: AsRef<Path>>(socket_path: P, ignore_maintenance_windows: bool) -> Result<Value> {
+    let socket_path = socket_path.as_ref();
+    let query = maintenance_windows_query(ignore_maintenance_windows);
+    let prepare = format!("prepare-update{}", query);
+    run_command(socket_path, &prepare, "prepare", PREPARE_TIMEOUT)?;
+    let activate = format!("activate-update{}", query);
+    run_command(socket_path, &activate, "activate", COMMAND_TIMEOUT)
+}
+
+/// Undoes `apply`, so the next boot uses the current partition set.  Returns the resulting update
+/// status.
+pub fn cancel<P: AsRef<Path>>(socket_path: P) -> Result<Value> {
+    run_command(socket_path, "deactivate-update", "deactivate", COMMAND_TIMEOUT)
+}
+
+/// Reboots the host, for example to start using an applied update.  Outside of the update
+/// maintenance windows, the API refuses to reboot unless `ignore_maintenance_windows` is set.
+pub fn reboot<P: AsRef<Path>>(socket_path: P, ignore_maintenance_windows: bool) -> Result<()> {
+    let uri = format!(
+        "/actions/reboot{}",
+        maintenance_windows_query(ignore_maintenance_windows)
+    );
+    raw_request(socket_path, uri, "POST", None)?;
+    Ok(())
+}
+
+/// Returns the query string that tells an update action whether to ignore the update maintenance
+/// windows.
+fn maintenance_windows_query(ignore_maintenance_windows: bool) -> &'static str {
+    if ignore_maintenance_windows {
+        "?ignore-maintenance-windows=true"
+    } else {
+        ""
+    }
+}
+
+/// Starts the given update action, which can include a query string, then waits until the update status shows that the matching
+/// command finished, returning the status.
+fn run_command<P>(socket_path: P, action: &str, command: &str, timeout: Duration) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let socket_path = socket_path.as_ref();
+    // The status isn't available before the first command, or while a command holds the update
+    // lock; we know our command finished when the most recent command changes to match it.
+    let previous = get_status(socket_path)?.map(|status| status["most_recent_command"].clone());
+
+    let uri = format!("/actions/{}", action);
+    raw_request(socket_path, &uri, "POST", None)?;
+
+    let start = Instant::now();
+    loop {
+        if let Some(status) = get_status(socket_path)? {
+            let recent = &status["most_recent_command"];
+            if recent["cmd_type"] == command && Some(recent) != previous.as_ref() {
+                if recent["cmd_status"] != "Success" {
+                    return error::UpdateCommand {
+                        command,
+                        stderr: recent["stderr"].as_str().unwrap_or("unknown error"),
+                    }
+                    .fail();
+                }
+                return Ok(status);
+            }
+        }
+
+        if start.elapsed() >= timeout {
+            return error::UpdateTimeout {
+                command,
+                seconds: timeout.as_secs(),
+            }
+            .fail();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Returns the update status, or None if it's not available right now.
+fn get_status(socket_path: &Path) -> Result<Option<Value>> {
+    match json_request(socket_path, "/updates/status", "GET", None) {
+        Ok(status) => Ok(Some(status)),
+        Err(error::Error::ResponseStatus { code, .. })
+            if code == http::StatusCode::NOT_FOUND || code == http::StatusCode::LOCKED =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -1,11 +1,21 @@
-// Automatically generate README.md from rustdoc.
+// Automatically generate README.md from rustdoc, and generate the OpenAPI document for the
+// current variant by adding schemas from the model to the hand-written API description.
 
+use model::schema::JsonSchema;
+use model::{ConfigurationFiles, Model, Services, Settings};
+use serde_yaml::Value;
+use std::collections::HashSet;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
 
-fn main() {
+fn generate_readme() {
+    // Listing the sources of generated files means cargo only reruns us when they change, so we
+    // have to list the README sources too.
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=README.tpl");
+
     // Check for environment variable "SKIP_README". If it is set,
     // skip README generation
     if env::var_os("SKIP_README").is_some() {
@@ -30,3 +40,81 @@ fn main() {
     let mut readme = File::create("README.md").unwrap();
     readme.write_all(content.as_bytes()).unwrap();
 }
+
+/// The prefix of references to schemas in the document.
+const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+fn generate_openapi() {
+    // openapi.yaml describes the API surface and its own response types, and refers to the model
+    // types in "components" too; we fill those in from the model of the variant we're building,
+    // so the document always matches what the server accepts.
+    let source = "../openapi.yaml";
+    println!("cargo:rerun-if-changed={}", source);
+    let content = fs::read_to_string(source).unwrap();
+    let mut document: Value = serde_yaml::from_str(&content).unwrap();
+
+    let schemas = document
+        .get_mut("components")
+        .and_then(|components| components.get_mut("schemas"))
+        .and_then(Value::as_mapping_mut)
+        .expect("openapi.yaml has no components.schemas section");
+    let model_schemas = vec![
+        ("Settings", Settings::json_schema()),
+        ("Services", Services::json_schema()),
+        ("ConfigurationFiles", ConfigurationFiles::json_schema()),
+        ("Model", Model::json_schema()),
+    ];
+    for (name, schema) in model_schemas {
+        schemas.insert(name.into(), serde_yaml::to_value(schema).unwrap());
+    }
+
+    // Make sure the document is complete, so it can be used to validate clients.
+    let defined: HashSet<String> = schemas
+        .iter()
+        .filter_map(|(name, _)| name.as_str().map(str::to_string))
+        .collect();
+    let mut references = Vec::new();
+    find_refs(&document, &mut references);
+    for reference in references {
+        let name = reference
+            .strip_prefix(SCHEMA_REF_PREFIX)
+            .unwrap_or_else(|| panic!("openapi.yaml refers to '{}', not a schema", reference));
+        assert!(
+            defined.contains(name),
+            "openapi.yaml refers to undefined schema '{}'",
+            name
+        );
+    }
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(
+        out_dir.join("openapi.yaml"),
+        serde_yaml::to_string(&document).unwrap(),
+    )
+    .unwrap();
+}
+
+/// Collects the targets of all `$ref`s in the given part of the document.
+fn find_refs<'a>(value: &'a Value, references: &mut Vec<&'a str>) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value.as_str()) {
+                    (Some("$ref"), Some(reference)) => references.push(reference),
+                    _ => find_refs(value, references),
+                }
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                find_refs(value, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn main() {
+    generate_readme();
+    generate_openapi();
+}
@@ -39,6 +39,8 @@ mod error {
 
 /// Stores user-supplied arguments.
 struct Args {
+    access_policy: Option<String>,
+    audit_log: Option<String>,
     datastore_path: String,
     log_level: LevelFilter,
     socket_gid: Option<Gid>,
@@ -53,10 +55,14 @@ fn usage() -> ! {
             --datastore-path PATH
             [ --socket-path PATH ]
             [ --socket-gid GROUP_ID ]
+            [ --access-policy PATH ]
+            [ --audit-log PATH ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]
 
-    Socket path defaults to {}",
+    Socket path defaults to {}
+    Without an access policy, all clients may make any request
+    Without an audit log, requests aren't recorded",
         program_name, DEFAULT_BIND_PATH
     );
     process::exit(2);
@@ -70,6 +76,8 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 
 /// Parses user arguments into an Args structure.
 fn parse_args(args: env::Args) -> Args {
+    let mut access_policy = None;
+    let mut audit_log = None;
     let mut datastore_path = None;
     let mut log_level = None;
     let mut socket_gid = None;
@@ -114,11 +122,27 @@ fn parse_args(args: env::Args) -> Args {
                 socket_gid = Some(Gid::from_raw(gid));
             }
 
+            "--access-policy" => {
+                access_policy = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --access-policy")),
+                )
+            }
+
+            "--audit-log" => {
+                audit_log = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --audit-log")),
+                )
+            }
+
             _ => usage(),
         }
     }
 
     Args {
+        access_policy,
+        audit_log,
         socket_gid,
         datastore_path: datastore_path.unwrap_or_else(|| usage()),
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
@@ -158,6 +182,8 @@ async fn run() -> Result<()> {
         &args.datastore_path,
         threads,
         args.socket_gid,
+        args.access_policy.as_ref().map(Path::new),
+        args.audit_log.as_ref().map(Path::new),
     )
     .await
     .context(error::Server)
@@ -17,6 +17,18 @@ pub enum Error {
     #[snafu(display("Error serializing scalar {}: {} ", given, source))]
     SerializeScalar { given: String, source: ScalarError },
 
+    #[snafu(display("Error serializing generation {}: {}", id, source))]
+    SerializeGeneration { id: u64, source: serde_json::Error },
+
+    #[snafu(display("Error deserializing generation at '{}': {}", path.display(), source))]
+    DeserializeGeneration {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Generation {} not found in history", id))]
+    GenerationNotFound { id: u64 },
+
     #[snafu(display("Key would traverse outside data store: {}", name))]
     PathTraversal { name: String },
 
@@ -3,6 +3,12 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
+//!
+//! A transaction's removal of a key is kept the same way as metadata, as an empty marker file in
+//! the transaction's directory, e.g. a/b/c.deleted, so it's never read as a pending value.
+//!
+//! Generations, the records of commits to live data, are kept as JSON files named by their ID in
+//! a history directory next to the live and pending data.
 
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use snafu::{ensure, OptionExt, ResultExt};
@@ -13,10 +19,18 @@ use std::path::{self, Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
 use super::key::{Key, KeyType};
-use super::{error, Committed, DataStore, Result};
+use super::{error, Committed, DataStore, Generation, Result};
 
 const METADATA_KEY_PREFIX: &str = ".";
 
+/// The name of the marker that records a pending removal of a data key, in the form of a pending
+/// metadata key.  Metadata is otherwise only kept in live data, so this can't collide.
+const PENDING_DELETION_MARKER: &str = "deleted";
+
+/// The number of generations we keep in history; older generations are removed as new ones are
+/// recorded, and can no longer be used as rollback targets.
+const MAX_GENERATIONS: usize = 100;
+
 // This describes the set of characters we encode when making the filesystem path for a given key.
 // Any non-ASCII characters, plus these ones, will be encoded.
 // We start off very strict (anything not alphanumeric) and remove characters we'll allow.
@@ -28,6 +42,7 @@ const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-')
 pub struct FilesystemDataStore {
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    history_path: PathBuf,
 }
 
 impl FilesystemDataStore {
@@ -35,6 +50,7 @@ impl FilesystemDataStore {
         FilesystemDataStore {
             live_path: base_path.as_ref().join("live"),
             pending_base_path: base_path.as_ref().join("pending"),
+            history_path: base_path.as_ref().join("history"),
         }
     }
 
@@ -73,6 +89,41 @@ impl FilesystemDataStore {
         Ok(path)
     }
 
+    /// Returns the path on the filesystem for the record of the given generation.
+    fn generation_path(&self, id: u64) -> PathBuf {
+        self.history_path.join(id.to_string())
+    }
+
+    /// Returns the IDs of the generations recorded on the filesystem, in ascending order.
+    fn list_generation_ids(&self) -> Result<Vec<u64>> {
+        let entries = match fs::read_dir(&self.history_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                // If there's no history directory, nothing has been committed yet.
+                if e.kind() == io::ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                }
+                return Err(e).context(error::Io {
+                    path: &self.history_path,
+                });
+            }
+        };
+
+        let mut ids = Vec::new();
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.history_path,
+            })?;
+            // Anything not named by a generation ID isn't ours; skip it.
+            match entry.file_name().to_str().map(str::parse::<u64>) {
+                Some(Ok(id)) => ids.push(id),
+                _ => trace!("Skipping non-generation entry: {}", entry.path().display()),
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
     /// Returns the appropriate path on the filesystem for the given metadata key.
     fn metadata_path(
         &self,
@@ -101,6 +152,16 @@ impl FilesystemDataStore {
         Ok(path_str.into())
     }
 
+    /// Returns the path on the filesystem for the marker recording that the given transaction
+    /// removes the given data key.
+    fn pending_deletion_path(&self, data_key: &Key, transaction: &str) -> Result<PathBuf> {
+        let marker = Key::new(KeyType::Meta, PENDING_DELETION_MARKER)?;
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        self.metadata_path(&marker, data_key, &pending)
+    }
+
     /// Deletes the given path from the filesystem.  Also removes the parent directory if empty
     /// (repeatedly, up to the base path), so as to have consistent artifacts on the filesystem
     /// after adding and removing keys.
@@ -447,6 +508,12 @@ impl DataStore for FilesystemDataStore {
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        // A new pending value replaces any pending removal of the key.
+        if let Committed::Pending { tx } = committed {
+            let marker_path = self.pending_deletion_path(key, tx)?;
+            self.delete_key_path(marker_path, committed)?;
+        }
+
         let path = self.data_path(key, committed)?;
         write_file_mkdir(path, value)
     }
@@ -456,6 +523,37 @@ impl DataStore for FilesystemDataStore {
         self.delete_key_path(path, committed)
     }
 
+    fn set_pending_deletion(&mut self, key: &Key, transaction: &str) -> Result<()> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        self.unset_key(key, &pending)?;
+
+        let marker_path = self.pending_deletion_path(key, transaction)?;
+        write_file_mkdir(marker_path, "")
+    }
+
+    fn list_pending_deletions<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        transaction: &str,
+    ) -> Result<HashSet<Key>> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let key_paths = find_populated_key_paths(self, KeyType::Meta, prefix, &pending)?;
+        let keys = key_paths
+            .into_iter()
+            .filter(|kp| {
+                kp.metadata_key
+                    .as_ref()
+                    .map_or(false, |md| md.name() == PENDING_DELETION_MARKER)
+            })
+            .map(|kp| kp.data_key)
+            .collect();
+        Ok(keys)
+    }
+
     fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
         let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
         read_file_for_key(&metadata_key, &path)
@@ -482,23 +580,29 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
-        // Get data for changed keys
+        // Get data for changed and removed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
+        let pending_deletions = self.list_pending_deletions("settings.", &transaction)?;
 
         // Nothing to do if no keys are present in pending
-        if pending_data.is_empty() {
+        if pending_data.is_empty() && pending_deletions.is_empty() {
             return Ok(Default::default());
         }
 
         // Save Keys for return value
-        let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
+        let pending_keys: HashSet<Key> = pending_data
+            .keys()
+            .chain(pending_deletions.iter())
+            .cloned()
+            .collect();
 
         // Apply changes to live
         debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
+        self.write_pending_to_live(transaction, &pending_data, &pending_deletions)?;
 
         // Remove pending
         debug!("Removing old pending keys");
@@ -512,14 +616,20 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        let transaction: String = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
-        // Get changed keys so we can return the list
+        // Get changed and removed keys so we can return the list
         let pending_data = self.get_prefix("settings.", &pending)?;
+        let pending_deletions = self.list_pending_deletions("settings.", &transaction)?;
 
         // Pull out just the keys so we can log them and return them
-        let pending_keys = pending_data.into_iter().map(|(key, _val)| key).collect();
+        let pending_keys = pending_data
+            .into_iter()
+            .map(|(key, _val)| key)
+            .chain(pending_deletions)
+            .collect();
         debug!("Found pending keys: {:?}", &pending_keys);
 
         // Delete pending from the filesystem, same as a commit
@@ -577,11 +687,45 @@ impl DataStore for FilesystemDataStore {
 
         Ok(transactions)
     }
+
+    fn latest_generation_id(&self) -> Result<Option<u64>> {
+        Ok(self.list_generation_ids()?.last().copied())
+    }
+
+    fn list_generations(&self) -> Result<Vec<Generation>> {
+        let mut generations = Vec::new();
+        for id in self.list_generation_ids()? {
+            let path = self.generation_path(id);
+            let data = fs::read_to_string(&path).context(error::Io { path: &path })?;
+            let generation =
+                serde_json::from_str(&data).context(error::DeserializeGeneration { path })?;
+            generations.push(generation);
+        }
+        Ok(generations)
+    }
+
+    /// Writes the generation to the history directory, then removes the oldest generations
+    /// beyond MAX_GENERATIONS.
+    fn save_generation(&mut self, generation: &Generation) -> Result<()> {
+        let data = serde_json::to_string(generation)
+            .context(error::SerializeGeneration { id: generation.id })?;
+        write_file_mkdir(self.generation_path(generation.id), data)?;
+
+        let ids = self.list_generation_ids()?;
+        let excess = ids.len().saturating_sub(MAX_GENERATIONS);
+        for id in &ids[..excess] {
+            let path = self.generation_path(*id);
+            debug!("Removing old generation {}", id);
+            fs::remove_file(&path).context(error::DeleteKey { path })?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use maplit::hashset;
 
     #[test]
     fn data_path() {
@@ -622,6 +766,36 @@ mod test {
         assert_eq!(live.into_os_string(), "/base/live/a/b/c.my-metadata");
     }
 
+    #[test]
+    fn pending_deletion() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        let key = Key::new(KeyType::Data, "settings.a.b").unwrap();
+        f.set_key(&key, "\"live\"", &Committed::Live).unwrap();
+
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        f.set_key(&key, "\"pending\"", &pending).unwrap();
+        f.set_pending_deletion(&key, tx).unwrap();
+        assert_eq!(
+            f.pending_deletion_path(&key, tx).unwrap(),
+            dir.path()
+                .join("pending/test%20transaction/settings/a/b.deleted")
+        );
+
+        // The removal replaces the pending value and isn't read as one
+        assert!(f.get_prefix("", &pending).unwrap().is_empty());
+        assert_eq!(
+            f.list_pending_deletions("settings.", tx).unwrap(),
+            hashset!(key.clone())
+        );
+        assert_eq!(f.list_transactions().unwrap(), hashset!(tx.to_string()));
+
+        assert_eq!(f.commit_transaction(tx).unwrap(), hashset!(key.clone()));
+        assert_eq!(f.get_key(&key, &Committed::Live).unwrap(), None);
+        assert!(f.list_transactions().unwrap().is_empty());
+    }
+
     #[test]
     fn encode_path_component_works() {
         assert_eq!(encode_path_component("a-b_42"), "a-b_42");
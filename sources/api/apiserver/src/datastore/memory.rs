@@ -5,25 +5,31 @@
 
 use std::collections::{HashMap, HashSet};
 
-use super::{Committed, DataStore, Key, Result};
+use super::{Committed, DataStore, Generation, Key, Result};
 
 #[derive(Debug)]
 pub struct MemoryDataStore {
     // Transaction name -> (key -> data)
     pending: HashMap<String, HashMap<Key, String>>,
+    // Transaction name -> keys to remove from live data
+    pending_deletions: HashMap<String, HashSet<Key>>,
     // Committed (live) data.
     live: HashMap<Key, String>,
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
     metadata: HashMap<Key, HashMap<Key, String>>,
+    // Records of commits to live data, oldest first.
+    history: Vec<Generation>,
 }
 
 impl MemoryDataStore {
     pub fn new() -> Self {
         Self {
             pending: HashMap::new(),
+            pending_deletions: HashMap::new(),
             live: HashMap::new(),
             metadata: HashMap::new(),
+            history: Vec::new(),
         }
     }
 
@@ -100,6 +106,11 @@ impl DataStore for MemoryDataStore {
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        if let Committed::Pending { tx } = committed {
+            if let Some(deletions) = self.pending_deletions.get_mut(tx) {
+                deletions.remove(key);
+            }
+        }
         self.dataset_mut(committed)
             .insert(key.clone(), value.as_ref().to_owned());
         Ok(())
@@ -110,6 +121,37 @@ impl DataStore for MemoryDataStore {
         Ok(())
     }
 
+    fn set_pending_deletion(&mut self, key: &Key, transaction: &str) -> Result<()> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        // This also makes sure the transaction is listed, even if it has no pending data.
+        self.dataset_mut(&pending).remove(key);
+        self.pending_deletions
+            .entry(transaction.to_string())
+            .or_default()
+            .insert(key.clone());
+        Ok(())
+    }
+
+    fn list_pending_deletions<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        transaction: &str,
+    ) -> Result<HashSet<Key>> {
+        Ok(self
+            .pending_deletions
+            .get(transaction)
+            .map(|deletions| {
+                deletions
+                    .iter()
+                    .filter(|k| k.name().starts_with(prefix.as_ref()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
     fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
         let empty = HashMap::new();
         let dataset = self.dataset(committed).unwrap_or(&empty);
@@ -155,11 +197,15 @@ impl DataStore for MemoryDataStore {
         S: Into<String> + AsRef<str>,
     {
         // Remove anything pending for this transaction
+        let deletions = self
+            .pending_deletions
+            .remove(transaction.as_ref())
+            .unwrap_or_default();
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
+            // Apply pending changes to live, recording their effect
+            self.write_pending_to_live(transaction.as_ref(), &pending, &deletions)?;
             // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
+            Ok(pending.keys().chain(deletions.iter()).cloned().collect())
         } else {
             Ok(HashSet::new())
         }
@@ -170,9 +216,13 @@ impl DataStore for MemoryDataStore {
         S: Into<String> + AsRef<str>,
     {
         // Remove anything pending for this transaction
+        let deletions = self
+            .pending_deletions
+            .remove(transaction.as_ref())
+            .unwrap_or_default();
         if let Some(pending) = self.pending.remove(transaction.as_ref()) {
             // Return the old pending keys
-            Ok(pending.keys().cloned().collect())
+            Ok(pending.keys().chain(deletions.iter()).cloned().collect())
         } else {
             Ok(HashSet::new())
         }
@@ -181,6 +231,15 @@ impl DataStore for MemoryDataStore {
     fn list_transactions(&self) -> Result<HashSet<String>> {
         Ok(self.pending.keys().cloned().collect())
     }
+
+    fn list_generations(&self) -> Result<Vec<Generation>> {
+        Ok(self.history.clone())
+    }
+
+    fn save_generation(&mut self, generation: &Generation) -> Result<()> {
+        self.history.push(generation.clone());
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -248,6 +307,51 @@ mod test {
         assert!(m.key_populated(&k, &Committed::Live).unwrap());
     }
 
+    #[test]
+    fn commit_deletion() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        let v = "memvalue";
+        m.set_key(&k, v, &Committed::Live).unwrap();
+
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        m.set_pending_deletion(&k, tx).unwrap();
+        // The removal isn't a pending value
+        assert!(!m.key_populated(&k, &pending).unwrap());
+        assert_eq!(
+            m.list_pending_deletions("settings.", tx).unwrap(),
+            hashset!(k.clone())
+        );
+        assert_eq!(m.commit_transaction(tx).unwrap(), hashset!(k.clone()));
+        assert!(!m.key_populated(&k, &Committed::Live).unwrap());
+
+        // The removal is recorded, so it can be rolled back
+        let generation = m.list_generations().unwrap().pop().unwrap();
+        let change = &generation.changes["settings.a.b.c"];
+        assert_eq!(change.old.as_deref(), Some(v));
+        assert_eq!(change.new, None);
+    }
+
+    #[test]
+    fn set_clears_deletion() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a.b.c").unwrap();
+        m.set_key(&k, "old", &Committed::Live).unwrap();
+
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        m.set_pending_deletion(&k, tx).unwrap();
+        m.set_key(&k, "new", &pending).unwrap();
+        assert!(m.list_pending_deletions("", tx).unwrap().is_empty());
+
+        m.commit_transaction(tx).unwrap();
+        assert_eq!(
+            m.get_key(&k, &Committed::Live).unwrap(),
+            Some("new".to_string())
+        );
+    }
+
     #[test]
     fn delete_transaction() {
         let mut m = MemoryDataStore::new();
@@ -4,6 +4,13 @@
 //! There's also a common error type and some methods that implementations of DataStore should
 //! generally share, like scalar serialization.
 //!
+//! Each commit to the live datastore is recorded as a numbered Generation, listing the keys it
+//! changed along with their old and new values, so that commits can be reviewed and rolled back.
+//!
+//! A transaction can remove keys as well as set them.  Removals are recorded separately from
+//! pending values, so they're never mistaken for data; committing the transaction unsets the
+//! removed keys in live data.
+//!
 //! We represent scalars -- the actual values stored under a datastore key -- using JSON, just to
 //! have a convenient human-readable form.  (TOML doesn't allow raw scalars.  The JSON spec
 //! doesn't seem to either, but this works, and the format is so simple for scalars that it could
@@ -20,8 +27,9 @@ pub use error::{Error, Result};
 pub use filesystem::FilesystemDataStore;
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use snafu::OptionExt;
+use snafu::{ensure, OptionExt};
 use std::collections::{HashMap, HashSet};
 
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
@@ -35,6 +43,27 @@ pub enum Committed {
     },
 }
 
+/// Change represents the effect of a commit on a single data key.  Values are kept in their
+/// serialized form, as stored in the datastore; None means the key was not populated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Generation is the record of a single commit to the live datastore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    /// Generations are numbered sequentially, starting at 1.
+    pub id: u64,
+    /// The name of the committed transaction, or a description of the operation that changed
+    /// live data, like a rollback.
+    pub transaction: String,
+    pub timestamp: DateTime<Utc>,
+    /// Maps the name of each changed data key to its old and new values.
+    pub changes: HashMap<String, Change>,
+}
+
 pub trait DataStore {
     /// Returns whether a key is present (has a value) in the datastore.
     fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool>;
@@ -68,6 +97,18 @@ pub trait DataStore {
     /// or remove the key.
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()>;
 
+    /// Marks the given data key for removal from live data when the given transaction is
+    /// committed.  Any value the transaction set for the key is dropped; setting a value for the
+    /// key in the transaction afterward clears the mark.
+    fn set_pending_deletion(&mut self, key: &Key, transaction: &str) -> Result<()>;
+    /// Returns the data keys marked for removal by the given transaction whose names start with
+    /// the given prefix.
+    fn list_pending_deletions<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        transaction: &str,
+    ) -> Result<HashSet<Key>>;
+
     /// Retrieve the value for a single metadata key from the datastore.  Values will inherit from
     /// earlier in the tree, if more specific values are not found later.
     fn get_metadata(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
@@ -119,6 +160,139 @@ pub trait DataStore {
     /// Returns a list of the names of any pending transactions in the data store.
     fn list_transactions(&self) -> Result<HashSet<String>>;
 
+    /// Returns the recorded generations of the live datastore, oldest first.
+    fn list_generations(&self) -> Result<Vec<Generation>>;
+    /// Saves the record of a commit to the live datastore.  Implementations may discard the
+    /// oldest generations to bound the size of the history.
+    fn save_generation(&mut self, generation: &Generation) -> Result<()>;
+
+    /// Returns the ID of the latest recorded generation, or None if nothing has been committed.
+    /// Implementations should override this if they can find it without reading every
+    /// generation.
+    fn latest_generation_id(&self) -> Result<Option<u64>> {
+        Ok(self.list_generations()?.last().map(|g| g.id))
+    }
+
+    /// Retrieve a single generation by its ID, or None if it isn't in the recorded history.
+    fn get_generation(&self, id: u64) -> Result<Option<Generation>> {
+        Ok(self.list_generations()?.into_iter().find(|g| g.id == id))
+    }
+
+    /// Compares the given data and removals with the live datastore, returning the changes that
+    /// writing them would make.  Keys whose live value already matches are not included.
+    fn live_changes(
+        &self,
+        data: &HashMap<Key, String>,
+        deletions: &HashSet<Key>,
+    ) -> Result<HashMap<String, Change>> {
+        let mut changes = HashMap::new();
+        let updates = data
+            .iter()
+            .map(|(key, value)| (key, Some(value)))
+            .chain(deletions.iter().map(|key| (key, None)));
+        for (key, value) in updates {
+            let old = self.get_key(key, &Committed::Live)?;
+            let new = value.cloned();
+            if old != new {
+                changes.insert(key.name().clone(), Change { old, new });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Writes the given data and removals, taken from a transaction, to the live datastore.  The
+    /// effect is recorded as a generation under the transaction's name.
+    fn write_pending_to_live<S>(
+        &mut self,
+        transaction: S,
+        data: &HashMap<Key, String>,
+        deletions: &HashSet<Key>,
+    ) -> Result<()>
+    where
+        S: Into<String>,
+    {
+        // Find the effect on live data before we overwrite it, and record it in history first, so
+        // an interrupted write still leaves a generation that can be rolled back
+        let changes = self.live_changes(data, deletions)?;
+        if !changes.is_empty() {
+            self.record_generation(transaction, changes)?;
+        }
+
+        self.unset_keys(deletions, &Committed::Live)?;
+        self.set_keys(data, &Committed::Live)?;
+        Ok(())
+    }
+
+    /// Records the given changes to the live datastore as a new generation, and returns it.
+    fn record_generation<S>(
+        &mut self,
+        transaction: S,
+        changes: HashMap<String, Change>,
+    ) -> Result<Generation>
+    where
+        S: Into<String>,
+    {
+        let id = self.latest_generation_id()?.map_or(1, |latest| latest + 1);
+        let generation = Generation {
+            id,
+            transaction: transaction.into(),
+            timestamp: Utc::now(),
+            changes,
+        };
+        debug!("Recording generation {}", id);
+        self.save_generation(&generation)?;
+        Ok(generation)
+    }
+
+    /// Reverts the live datastore to its state as of the given generation by undoing the changes
+    /// of each later generation, newest first.  The rollback is itself recorded as a generation,
+    /// so it can be undone the same way.  Returns the list of changed keys.
+    fn rollback_to_generation(&mut self, id: u64) -> Result<HashSet<Key>> {
+        let generations = self.list_generations()?;
+        ensure!(
+            generations.iter().any(|g| g.id == id),
+            error::GenerationNotFound { id }
+        );
+
+        // Walking backward, the last change we see to each key holds the value it had as of
+        // the requested generation.
+        let mut targets = HashMap::new();
+        for generation in generations.iter().rev().take_while(|g| g.id > id) {
+            for (name, change) in &generation.changes {
+                targets.insert(name, &change.old);
+            }
+        }
+
+        let mut changes = HashMap::new();
+        let mut updates = HashMap::new();
+        for (name, target) in targets {
+            let key = Key::new(KeyType::Data, name)?;
+            let current = self.get_key(&key, &Committed::Live)?;
+            if current == *target {
+                continue;
+            }
+
+            let change = Change {
+                old: current,
+                new: target.clone(),
+            };
+            changes.insert(name.clone(), change);
+            updates.insert(key, target);
+        }
+
+        // As with commits, the generation is recorded before live data changes.
+        if !changes.is_empty() {
+            self.record_generation(format!("rollback-to-{}", id), changes)?;
+        }
+        for (key, target) in &updates {
+            match target {
+                Some(value) => self.set_key(key, value, &Committed::Live)?,
+                None => self.unset_key(key, &Committed::Live)?,
+            }
+        }
+        Ok(updates.into_iter().map(|(key, _target)| key).collect())
+    }
+
     /// Set multiple data keys at once in the data store.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
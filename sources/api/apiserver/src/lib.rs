@@ -18,17 +18,66 @@ The interface is documented in [OpenAPI format](https://swagger.io/docs/specific
 The Settings APIs are particularly important.
 You can GET settings from the `/settings` endpoint.
 You can also PATCH changes to the `/settings` endpoint.
+To remove settings, like a single entry in `kubernetes.node-labels`, send a DELETE to `/settings` with the keys to remove, like `/settings?keys=settings.kubernetes.node-labels.foo`.
+Giving a prefix, like `settings.host-containers.admin`, removes every setting under it.
 Settings are stored as a pending transaction until a commit API is called.
-Pending settings can be retrieved from `/tx` to see what will change.
+Pending settings can be retrieved from `/tx` to see what will change; pending removals aren't shown there, but are listed by `/tx/diff`.
+To see how they compare with live settings, GET `/tx/diff`, which lists the keys that would be added, changed, or removed, along with their old values.
+If you just want to know whether settings would be accepted, you can POST them to `/settings/validate` instead.
+It lists every invalid value with its key, or if they're valid, the changes they would make to live settings and the services and configuration files those changes affect, without creating a transaction.
 
 Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
 
+Each commit is recorded in the data store's history as a numbered "generation", listing the keys it changed with their old and new values.
+You can GET the history from `/history`, or a single generation from `/history/{id}`.
+If a commit turns out to be a mistake, a POST to `/history/{id}/rollback` restores live settings to their state as of generation `id` and applies the changes.
+The rollback is recorded as a generation of its own, so it can be undone the same way.
+
+Clients that want to react to settings changes can GET `/settings/watch`, which waits for a commit and returns the keys it changed.
+Pass the `latest` generation ID from the response as the `since` parameter of the next request so that no commits are missed.
+Only the most recent commits are kept in the history; if some commits after `since` are gone, the response has `truncated` set, and the client should re-read `/settings` rather than rely on the listed changes.
+You can limit the watch to keys under a `prefix`, and set how many seconds to wait with `timeout`.
+
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
 For example, if you want the name "FOO", you can `PATCH` to `/settings?tx=FOO` and `POST` to `/tx/commit_and_apply?tx=FOO`.
 
+## Access control
+
+Without an access policy, any client that can connect to the socket can make any request, and the server logs a warning at startup.
+To limit what clients can do, start the server with `--access-policy PATH`, pointing to a TOML file like this:
+
+```toml
+[[client]]
+name = "system"
+uids = [0]
+routes = ["* *"]
+
+[[client]]
+name = "monitoring"
+gids = [1000]
+routes = ["GET /settings", "GET /updates/status", "PATCH /settings", "POST /tx/commit_and_apply"]
+settings = ["settings.motd"]
+```
+
+The server identifies each client by the user and group ID of the connected process, which it gets from the socket with `SO_PEERCRED`.
+The first `client` whose `uids` or `gids` include the process's IDs applies; processes that don't match any client are denied.
+Each route is given as a method and a path, where `*` matches any method, and a trailing `*` matches the path and any path under it, so `/settings*` matches `/settings` and `/settings/keys` but not `/settingsfoo`.
+If `settings` is given, the client may only change keys under those prefixes, whether by PATCHing settings, committing or deleting a transaction, or rolling back history.
+Denied requests get a 403 response.
+
+Bottlerocket starts the server with the default policy in `/usr/share/apiserver/access-policy.toml`, which lets root and members of the `api` group make any request.
+
+## Audit log
+
+If the server is started with `--audit-log PATH`, it appends a line to that file for each request that could change the system: PATCHes to settings, transaction commits, applies, and deletions, history rollbacks, and actions.
+Each line is a JSON object with the time, the pid, uid, and gid of the client, the method and URI, the transaction and keys involved, and the response status, plus the error message if the request failed.
+Requests that are denied by the access policy are recorded too.
+
+When the log would grow past 4 MiB, it's renamed with a `.1` suffix, replacing any previous one, and a new log is started.
+
 Requests are directed by `server::router`.
 `server::controller` maps requests into our data model.
 
@@ -38,10 +87,24 @@ The API is driven by a data model (similar to a schema) defined in Rust.
 (See the [models](../../models) directory for model definitions and more documentation.)
 All input is deserialized into model types, and all output is serialized from model types, so we can be more confident that data is in the format we expect.
 
+The model of the current variant is also described as an OpenAPI 3.0 schema, which you can GET from `/schema` to validate settings before sending them.
+At build time, the schemas of the model types are added to [openapi.yaml](../openapi.yaml), next to the hand-written schemas of the API's own responses, to produce a complete OpenAPI document for the variant, which you can GET from `/openapi`.
+The build fails if any `$ref` in the document doesn't name one of its schemas.
+
 The data model describes system settings, services using those settings, and configuration files used by those services.
 It also has a more general structure for metadata.
 Metadata entries can be stored for any data field in the model.
 
+## Metrics
+
+`/metrics` returns metrics in the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/), so the state of a host can be scraped from a host container:
+
+* `apiserver_requests_total` and `apiserver_request_duration_seconds` count and time requests by method and route.
+* `apiserver_datastore_keys` counts the keys in live data and in all pending transactions, and `apiserver_datastore_pending_transactions` counts the transactions with pending changes.
+* `apiserver_commits_total` counts transactions committed to live data since the server started, and `apiserver_settings_generation` is the ID of the latest generation of live settings, which changes with each commit or rollback that changes a setting.
+* `bottlerocket_update_*` metrics describe the update status from `/updates/status`: the update state, the versions of the active partition set and of the chosen update, and the most recent update command and its result.
+  If the status can't be read, for example while an update command is running, only `bottlerocket_update_status_up` is given, with a value of 0.
+
 ## Data store
 
 Data from the model is stored in a key/value data store.
@@ -50,6 +113,8 @@ This naturally implies some grouping and hierarchy of the data, corresponding to
 
 The current data store implementation maps keys to filesystem paths and stores the value in a file.
 Metadata about a data key is stored in a file at the data key path + "." + the metadata key.
+Generations are stored as JSON files named by their ID in a `history` directory next to the live data.
+A generation is saved before its changes are written to live data, so a commit or rollback that's interrupted partway can still be rolled back.
 The default data store location is `/var/lib/bottlerocket/datastore/current`, and the filesystem format makes it fairly easy to inspect.
 
 ## Serialization and deserialization
@@ -61,7 +126,7 @@ The `datastore::deserialization` module provides code to deserialize datastore-a
 # Current limitations
 
 * Data store locking is coarse; read requests can happen in parallel, but a write request will block everything else.
-* There's no support for rolling back commits.
+* Only the most recent generations are kept in history, so older commits can't be rolled back.
 * There are no metrics.
 * `datastore::serialization` can't handle complex types under lists; it assumes lists can be serialized as scalars.
 
@@ -0,0 +1,395 @@
+//! The access module decides whether a client may make a request.  We identify clients by the
+//! credentials of the connected process, which we get from the Unix-domain socket with
+//! SO_PEERCRED, and an access policy file maps user and group IDs to the routes they may use and
+//! the settings they may change.
+//!
+//! Without a policy file, every client may make any request; Bottlerocket's apiserver.service
+//! passes a default policy, so this is mostly for development.
+
+use super::error::{self, Result};
+use actix_rt::net::UnixStream;
+use nix::sys::socket::{getsockopt, sockopt};
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// PeerCredentials identifies the process on the other end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PeerCredentials {
+    pub(crate) pid: i32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+}
+
+impl PeerCredentials {
+    /// Returns the credentials of the process connected to the given socket, or None if the
+    /// kernel won't tell us.
+    pub(crate) fn from_stream(stream: &UnixStream) -> Option<Self> {
+        match getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials) {
+            Ok(creds) => Some(Self {
+                pid: creds.pid(),
+                uid: creds.uid(),
+                gid: creds.gid(),
+            }),
+            Err(e) => {
+                warn!("Unable to get credentials of client: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for PeerCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pid {} (uid {}, gid {})", self.pid, self.uid, self.gid)
+    }
+}
+
+/// AccessPolicy is the structure of the access policy file, for example:
+///
+/// ```toml
+/// [[client]]
+/// name = "system"
+/// uids = [0]
+/// routes = ["* /*"]
+///
+/// [[client]]
+/// name = "monitoring"
+/// uids = [1000]
+/// routes = ["GET /settings", "GET /updates/status"]
+/// ```
+///
+/// Clients are checked in order, and the first one listing the user ID or group ID of the
+/// connected process applies.  Processes that don't match any client are denied.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccessPolicy {
+    #[serde(default, rename = "client")]
+    clients: Vec<Client>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Client {
+    /// A name for the client, used in error messages.
+    name: Option<String>,
+    #[serde(default)]
+    uids: Vec<u32>,
+    #[serde(default)]
+    gids: Vec<u32>,
+    /// The routes the client may use.
+    #[serde(default)]
+    routes: Vec<RoutePattern>,
+    /// If given, the client may only change settings under these prefixes, like
+    /// "settings.motd"; otherwise, the client may change any settings it has routes for.
+    settings: Option<Vec<String>>,
+}
+
+/// RoutePattern is given in the policy file as "METHOD PATH", like "GET /settings".  The method
+/// can be "*" to match any method, and the path can end with "*" to match the given path and any
+/// path under it; "/settings*" matches "/settings" and "/settings/keys", but not "/settingsfoo".
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "String")]
+struct RoutePattern {
+    method: Option<String>,
+    path: String,
+    prefix: bool,
+}
+
+impl TryFrom<String> for RoutePattern {
+    type Error = String;
+
+    fn try_from(input: String) -> std::result::Result<Self, Self::Error> {
+        let mut parts = input.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), None) => (method, path),
+            _ => return Err(format!("route '{}' should look like 'METHOD /path'", input)),
+        };
+
+        let method = match method {
+            "*" => None,
+            method => Some(method.to_uppercase()),
+        };
+        let (path, prefix) = match path.strip_suffix('*') {
+            Some(path) => (path.to_string(), true),
+            None => (path.to_string(), false),
+        };
+        // A bare "*" matches every path.
+        let any_path = prefix && path.is_empty();
+        if !(any_path || path.starts_with('/')) {
+            return Err(format!("path in route '{}' should start with '/'", input));
+        }
+
+        Ok(Self {
+            method,
+            path,
+            prefix,
+        })
+    }
+}
+
+impl RoutePattern {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        let method_matches = self
+            .method
+            .as_ref()
+            .map_or(true, |m| m.eq_ignore_ascii_case(method));
+        let path_matches = if self.prefix {
+            // Match whole segments, so "/settings*" doesn't allow "/settingsfoo"; a pattern
+            // ending in "/", or the bare "*", already ends at a segment boundary.
+            match path.strip_prefix(self.path.as_str()) {
+                Some(rest) => {
+                    self.path.is_empty()
+                        || self.path.ends_with('/')
+                        || rest.is_empty()
+                        || rest.starts_with('/')
+                        || rest.starts_with('?')
+                }
+                None => false,
+            }
+        } else {
+            path == self.path
+        };
+        method_matches && path_matches
+    }
+}
+
+impl Client {
+    fn describe(&self, peer: &PeerCredentials) -> String {
+        match &self.name {
+            Some(name) => format!("Client '{}'", name),
+            None => format!("Client {}", peer),
+        }
+    }
+}
+
+/// AccessControl applies the access policy, if any, to requests.
+#[derive(Debug, Default)]
+pub(crate) struct AccessControl {
+    policy: Option<AccessPolicy>,
+}
+
+impl AccessControl {
+    /// Loads the access policy from the given path.  If no path is given, all requests are
+    /// allowed.
+    pub(crate) fn from_path(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                warn!("No access policy given; any client that can connect may make any request");
+                return Ok(Self::default());
+            }
+        };
+        let data = fs::read_to_string(path).context(error::AccessPolicyRead { path })?;
+        let policy = toml::from_str(&data).context(error::AccessPolicyParse { path })?;
+        Ok(Self {
+            policy: Some(policy),
+        })
+    }
+
+    /// Finds the client in the policy that applies to the given peer.  Returns Ok(None) if there's
+    /// no policy, meaning everything is allowed.
+    fn client(&self, peer: Option<&PeerCredentials>) -> Result<Option<(&Client, PeerCredentials)>> {
+        let policy = match &self.policy {
+            Some(policy) => policy,
+            None => return Ok(None),
+        };
+        let peer = *peer.context(error::Forbidden {
+            reason: "Unable to identify client",
+        })?;
+        let client = policy
+            .clients
+            .iter()
+            .find(|c| c.uids.contains(&peer.uid) || c.gids.contains(&peer.gid))
+            .with_context(|| error::Forbidden {
+                reason: format!("Client {} is not in the access policy", peer),
+            })?;
+        Ok(Some((client, peer)))
+    }
+
+    /// Checks whether the given peer may make a request with the given method and path.
+    pub(crate) fn check_route(
+        &self,
+        peer: Option<&PeerCredentials>,
+        method: &str,
+        path: &str,
+    ) -> Result<()> {
+        if let Some((client, peer)) = self.client(peer)? {
+            ensure!(
+                client.routes.iter().any(|r| r.matches(method, path)),
+                error::Forbidden {
+                    reason: format!("{} may not {} {}", client.describe(&peer), method, path),
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks whether the given peer may change the given keys.
+    pub(crate) fn check_keys<I, S>(&self, peer: Option<&PeerCredentials>, keys: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        if let Some((client, peer)) = self.client(peer)? {
+            if let Some(prefixes) = &client.settings {
+                for key in keys {
+                    let key = key.as_ref();
+                    // Match whole segments, so "settings.updates" doesn't allow
+                    // "settings.updates-foo"
+                    let allowed = prefixes.iter().any(|prefix| {
+                        key == prefix
+                            || (key.starts_with(prefix.as_str())
+                                && key[prefix.len()..].starts_with('.'))
+                    });
+                    ensure!(
+                        allowed,
+                        error::Forbidden {
+                            reason: format!("{} may not change {}", client.describe(&peer), key),
+                        }
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datastore::memory::MemoryDataStore;
+    use crate::datastore::{Committed, DataStore, Key, KeyType};
+    use crate::server::controller;
+    use maplit::hashset;
+
+    fn access_control(policy: &str) -> AccessControl {
+        AccessControl {
+            policy: Some(toml::from_str(policy).unwrap()),
+        }
+    }
+
+    fn peer(uid: u32, gid: u32) -> PeerCredentials {
+        PeerCredentials { pid: 1, uid, gid }
+    }
+
+    const POLICY: &str = r#"
+        [[client]]
+        name = "system"
+        uids = [0]
+        routes = ["* /*"]
+
+        [[client]]
+        name = "monitoring"
+        gids = [1000]
+        routes = ["GET /settings", "GET /updates/*", "PATCH /settings", "POST /tx/*"]
+        settings = ["settings.motd", "settings.kubernetes.node-labels"]
+    "#;
+
+    #[test]
+    fn no_policy_allows_all() {
+        let access = AccessControl::default();
+        access.check_route(None, "POST", "/actions/reboot").unwrap();
+        access.check_keys(None, &["settings.updates.seed"]).unwrap();
+    }
+
+    #[test]
+    fn routes() {
+        let access = access_control(POLICY);
+        let root = peer(0, 0);
+        let monitoring = peer(1234, 1000);
+        let unknown = peer(1234, 1234);
+
+        access
+            .check_route(Some(&root), "POST", "/actions/reboot")
+            .unwrap();
+        access
+            .check_route(Some(&monitoring), "GET", "/settings")
+            .unwrap();
+        access
+            .check_route(Some(&monitoring), "post", "/tx/commit")
+            .unwrap();
+        assert!(access
+            .check_route(Some(&monitoring), "POST", "/actions/reboot")
+            .is_err());
+        access
+            .check_route(Some(&monitoring), "GET", "/updates/status")
+            .unwrap();
+        assert!(access
+            .check_route(Some(&monitoring), "GET", "/settings/watch")
+            .is_err());
+        assert!(access
+            .check_route(Some(&unknown), "GET", "/settings")
+            .is_err());
+        assert!(access.check_route(None, "GET", "/settings").is_err());
+    }
+
+    #[test]
+    fn prefix_routes_match_segments() {
+        let access = access_control(
+            r#"
+            [[client]]
+            uids = [0]
+            routes = ["GET /settings*"]
+            "#,
+        );
+        let root = peer(0, 0);
+
+        for path in &["/settings", "/settings/keys", "/settings/watch"] {
+            access.check_route(Some(&root), "GET", path).unwrap();
+        }
+        for path in &["/settingsfoo", "/settings-history", "/setting"] {
+            assert!(access.check_route(Some(&root), "GET", path).is_err());
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let access = access_control(POLICY);
+        let root = peer(0, 0);
+        let monitoring = peer(1234, 1000);
+
+        access
+            .check_keys(Some(&root), &["settings.updates.seed"])
+            .unwrap();
+        access
+            .check_keys(
+                Some(&monitoring),
+                &["settings.motd", "settings.kubernetes.node-labels.foo"],
+            )
+            .unwrap();
+        assert!(access
+            .check_keys(Some(&monitoring), &["settings.updates.seed"])
+            .is_err());
+        assert!(access
+            .check_keys(Some(&monitoring), &["settings.motdx"])
+            .is_err());
+    }
+
+    #[test]
+    fn pending_deletions_checked() {
+        // A transaction holding only the removal of a key the client may not change
+        let mut ds = MemoryDataStore::new();
+        let key = Key::new(KeyType::Data, "settings.updates.seed").unwrap();
+        ds.set_key(&key, "123", &Committed::Live).unwrap();
+        controller::delete_settings(&mut ds, &hashset!("settings.updates.seed"), "tx").unwrap();
+
+        // Committing checks every key in the transaction, as the handler does
+        let keys = controller::get_transaction_keys(&ds, "tx").unwrap();
+        let names = keys.iter().map(|k| k.name());
+        let access = access_control(POLICY);
+        assert!(access.check_keys(Some(&peer(1234, 1000)), names).is_err());
+    }
+
+    #[test]
+    fn bad_routes() {
+        for route in &["GET", "GET settings", "GET /settings extra"] {
+            let policy = format!("[[client]]\nroutes = [\"{}\"]", route);
+            assert!(toml::from_str::<AccessPolicy>(&policy).is_err());
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! The audit module keeps a log of requests that change the system, so we can tell who changed
+//! something and when.  Each line of the log is a JSON object describing one request: when it was
+//! made, the credentials of the client, the transaction and keys it touched, and the result.
+//!
+//! The log is only appended to.  When it would grow past a size limit, it's moved aside to a file
+//! with the suffix ".1", replacing any earlier one, and a new log is started, so at most two
+//! files' worth of history is kept.
+
+use super::access::PeerCredentials;
+use super::error::{self, Result};
+use actix_web::HttpMessage;
+use chrono::{DateTime, Utc};
+use http::{Method, StatusCode};
+use serde::Serialize;
+use snafu::ResultExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The size at which we move the log aside and start a new one.
+const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Returns whether requests with the given method and path should be audited.
+pub(crate) fn is_audited(method: &Method, path: &str) -> bool {
+    // Validation is a POST because it takes a body, but it doesn't change anything.
+    !(method == Method::GET || method == Method::HEAD || path == "/settings/validate")
+}
+
+/// AuditDetails are what handlers know about a request that the router doesn't.  Handlers store
+/// them in the request's extensions with `note`, and they're added to the audit entry when the
+/// request finishes.
+#[derive(Debug, Clone, Default)]
+struct AuditDetails {
+    transaction: Option<String>,
+    keys: Vec<String>,
+}
+
+/// Notes the transaction and keys touched by a request, for the audit log.  This should be called
+/// before any checks that could fail the request, so that failed attempts are fully recorded.
+pub(crate) fn note<M, I, S>(req: &M, transaction: Option<&str>, keys: I)
+where
+    M: HttpMessage,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut keys: Vec<String> = keys.into_iter().map(|k| k.as_ref().to_string()).collect();
+    keys.sort_unstable();
+    req.extensions_mut().insert(AuditDetails {
+        transaction: transaction.map(str::to_string),
+        keys,
+    });
+}
+
+/// AuditEntry is a single line of the audit log.
+#[derive(Debug, Serialize)]
+pub(crate) struct AuditEntry {
+    time: DateTime<Utc>,
+    pid: Option<i32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    method: String,
+    uri: String,
+    transaction: Option<String>,
+    keys: Vec<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Starts an entry for a request from the given client.  The result is filled in by
+    /// `finish` once the request is handled.
+    pub(crate) fn new(peer: Option<PeerCredentials>, method: &Method, uri: String) -> Self {
+        Self {
+            time: Utc::now(),
+            pid: peer.map(|p| p.pid),
+            uid: peer.map(|p| p.uid),
+            gid: peer.map(|p| p.gid),
+            method: method.to_string(),
+            uri,
+            transaction: None,
+            keys: Vec::new(),
+            status: 0,
+            error: None,
+        }
+    }
+
+    /// Fills in the result of the request, and any details noted by the handler.
+    pub(crate) fn finish<M, E>(&mut self, req: Option<&M>, status: StatusCode, error: Option<E>)
+    where
+        M: HttpMessage,
+        E: ToString,
+    {
+        if let Some(details) = req.and_then(|r| r.extensions().get::<AuditDetails>().cloned()) {
+            self.transaction = details.transaction;
+            self.keys = details.keys;
+        }
+        self.status = status.as_u16();
+        self.error = error.map(|e| e.to_string()).filter(|e| !e.is_empty());
+    }
+}
+
+/// AuditLog writes entries to the audit log file, if one was requested.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    log: Option<Mutex<LogFile>>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the given path, creating it if needed.  If no path is given,
+    /// entries aren't recorded.
+    pub(crate) fn open(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let log = LogFile::open(path, MAX_LOG_SIZE)?;
+        Ok(Self {
+            log: Some(Mutex::new(log)),
+        })
+    }
+
+    /// Appends an entry to the log.  The request has already been handled by the time we know
+    /// its result, so failures are logged rather than returned.
+    pub(crate) fn record(&self, entry: &AuditEntry) {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return,
+        };
+        let result = match log.lock() {
+            Ok(mut log) => log.append(entry),
+            Err(_) => error::AuditLogLock.fail(),
+        };
+        if let Err(e) = result {
+            error!("Failed to record request in audit log: {}", e);
+        }
+    }
+}
+
+impl LogFile {
+    fn open(path: &Path, max_size: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .context(error::AuditLogOpen { path })?;
+        let size = file.metadata().context(error::AuditLogOpen { path })?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn append(&mut self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry).context(error::AuditEntrySerialization)?;
+        line.push('\n');
+        let len = line.len() as u64;
+
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file
+            .write_all(line.as_bytes())
+            .context(error::AuditLogWrite { path: &self.path })?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Moves the current log aside and starts a new one.
+    fn rotate(&mut self) -> Result<()> {
+        let mut old_name = self.path.clone().into_os_string();
+        old_name.push(".1");
+        fs::rename(&self.path, &old_name).context(error::AuditLogWrite { path: &self.path })?;
+        *self = Self::open(&self.path, self.max_size)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use tempfile::TempDir;
+
+    fn entry(uri: &str) -> AuditEntry {
+        let peer = PeerCredentials {
+            pid: 42,
+            uid: 0,
+            gid: 0,
+        };
+        let mut entry = AuditEntry::new(Some(peer), &Method::PATCH, uri.to_string());
+        let req = TestRequest::default().to_http_request();
+        note(&req, Some("default"), &["settings.motd"]);
+        entry.finish(Some(&req), StatusCode::NO_CONTENT, None as Option<String>);
+        entry
+    }
+
+    #[test]
+    fn audited_requests() {
+        assert!(is_audited(&Method::PATCH, "/settings"));
+        assert!(is_audited(&Method::POST, "/tx/commit"));
+        assert!(is_audited(&Method::DELETE, "/tx"));
+        assert!(is_audited(&Method::POST, "/actions/reboot"));
+        assert!(!is_audited(&Method::GET, "/settings"));
+        assert!(!is_audited(&Method::POST, "/settings/validate"));
+    }
+
+    #[test]
+    fn entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::open(Some(&path)).unwrap();
+        log.record(&entry("/settings"));
+        log.record(&entry("/settings?tx=foo"));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["pid"], 42);
+        assert_eq!(lines[0]["uri"], "/settings");
+        assert_eq!(lines[0]["transaction"], "default");
+        assert_eq!(lines[0]["keys"], serde_json::json!(["settings.motd"]));
+        assert_eq!(lines[0]["status"], 204);
+        assert_eq!(lines[1]["uri"], "/settings?tx=foo");
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let line_len = serde_json::to_string(&entry("/settings")).unwrap().len() as u64 + 1;
+
+        // Room for two entries per file.
+        let mut log = LogFile::open(&path, line_len * 2).unwrap();
+        for _ in 0..5 {
+            log.append(&entry("/settings")).unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        let old = fs::read_to_string(dir.path().join("audit.log.1")).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert_eq!(old.lines().count(), 2);
+    }
+}
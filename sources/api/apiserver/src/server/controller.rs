@@ -2,7 +2,9 @@
 //! controller in the MVC model.
 
 use bottlerocket_release::BottlerocketRelease;
+use chrono::{DateTime, Utc};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::io::Write;
@@ -11,10 +13,12 @@ use std::process::{Command, Stdio};
 use crate::datastore::deserialization::{from_map, from_map_with_prefix};
 use crate::datastore::serialization::to_pairs;
 use crate::datastore::{
-    deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value,
+    deserialize_scalar, Change, Committed, DataStore, Generation, Key, KeyType, ScalarError, Value,
 };
 use crate::server::error::{self, Result};
+use crate::server::metrics::DataStoreMetrics;
 use actix_web::HttpResponse;
+use model::modeled_types::next_maintenance_window;
 use model::{ConfigurationFiles, Services, Settings};
 use num::FromPrimitive;
 use std::os::unix::process::ExitStatusExt;
@@ -57,6 +61,64 @@ pub(crate) fn delete_transaction<D: DataStore>(
         })
 }
 
+/// TransactionDiff describes what committing a transaction would do to live data.  Values are
+/// given in serialized form, as they're stored in the data store.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct TransactionDiff {
+    /// Keys that aren't set in live data, with their pending values.
+    pub(crate) added: HashMap<String, String>,
+    /// Keys whose pending values differ from live data.
+    pub(crate) changed: HashMap<String, Change>,
+    /// Keys the transaction would remove from live data, with their live values.
+    pub(crate) removed: HashMap<String, String>,
+}
+
+/// Compares the pending settings in a transaction with live data.  Pending keys that match their
+/// live value are not included.
+pub(crate) fn get_transaction_diff<D, S>(datastore: &D, transaction: S) -> Result<TransactionDiff>
+where
+    D: DataStore,
+    S: Into<String>,
+{
+    let transaction: String = transaction.into();
+    let pending = Committed::Pending {
+        tx: transaction.clone(),
+    };
+    let data = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStore { op: "get_prefix" })?;
+    let deletions = datastore
+        .list_pending_deletions("settings.", &transaction)
+        .context(error::DataStore {
+            op: "list_pending_deletions",
+        })?;
+    let changes = datastore
+        .live_changes(&data, &deletions)
+        .context(error::DataStore { op: "live_changes" })?;
+
+    let mut diff = TransactionDiff::default();
+    for (key, change) in changes {
+        match change {
+            Change {
+                old: None,
+                new: Some(new),
+            } => {
+                diff.added.insert(key, new);
+            }
+            Change {
+                old: Some(old),
+                new: None,
+            } => {
+                diff.removed.insert(key, old);
+            }
+            change => {
+                diff.changed.insert(key, change);
+            }
+        }
+    }
+    Ok(diff)
+}
+
 /// Build a Settings based on the data in the datastore.  Errors if no settings are found.
 pub(crate) fn get_settings<D: DataStore>(datastore: &D, committed: &Committed) -> Result<Settings> {
     get_prefix(datastore, committed, "settings.", None)
@@ -249,6 +311,79 @@ pub(crate) fn set_settings<D: DataStore>(
         .context(error::DataStore { op: "set_keys" })
 }
 
+/// Returns the keys that are given (Some) in a Settings, which are the keys set_settings would
+/// change.
+pub(crate) fn settings_keys(settings: &Settings) -> Result<HashSet<Key>> {
+    let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    Ok(pairs.into_iter().map(|(key, _value)| key).collect())
+}
+
+/// Removes settings in the given transaction.  Each name can be a single setting, like
+/// "settings.motd", or a prefix, like "settings.host-containers.admin", which removes every setting
+/// under it.  Returns the keys that committing the transaction will remove from live data.
+pub(crate) fn delete_settings<D, S>(
+    datastore: &mut D,
+    names: &HashSet<S>,
+    transaction: &str,
+) -> Result<HashSet<Key>>
+where
+    D: DataStore,
+    S: AsRef<str>,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let mut deleted = HashSet::new();
+    for name in names {
+        let name = name.as_ref();
+        ensure!(
+            name.starts_with("settings."),
+            error::DeleteNonSetting { name }
+        );
+        Key::new(KeyType::Data, name).context(error::NewKey {
+            key_type: "data",
+            name,
+        })?;
+
+        // Populated keys are found by string prefix, so make sure we only match whole segments;
+        // "settings.motd" shouldn't remove "settings.motd-extra".
+        let segment_prefix = format!("{}.", name);
+        let find_keys = |committed| -> Result<HashSet<Key>> {
+            Ok(datastore
+                .list_populated_keys(name, committed)
+                .context(error::DataStore {
+                    op: "list_populated_keys",
+                })?
+                .into_iter()
+                .filter(|k| k.name() == name || k.name().starts_with(&segment_prefix))
+                .collect())
+        };
+        let live_keys = find_keys(&Committed::Live)?;
+        let pending_keys = find_keys(&pending)?;
+        ensure!(
+            !live_keys.is_empty() || !pending_keys.is_empty(),
+            error::MissingData { prefix: name }
+        );
+
+        // Keys that were only set in this transaction have nothing to remove from live data, so
+        // we just drop their pending values.
+        let uncommitted = pending_keys.difference(&live_keys).cloned().collect();
+        datastore
+            .unset_keys(&uncommitted, &pending)
+            .context(error::DataStore { op: "unset_keys" })?;
+
+        for key in live_keys {
+            datastore
+                .set_pending_deletion(&key, transaction)
+                .context(error::DataStore {
+                    op: "set_pending_deletion",
+                })?;
+            deleted.insert(key);
+        }
+    }
+    Ok(deleted)
+}
+
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
 // data model and check types.
 /// Gets the value of a metadata key for the requested list of data keys.
@@ -326,6 +461,309 @@ where
         .context(error::DataStore { op: "commit" })
 }
 
+/// Returns the recorded history of commits to live data, oldest first.
+pub(crate) fn list_generations<D: DataStore>(datastore: &D) -> Result<Vec<Generation>> {
+    datastore.list_generations().context(error::DataStore {
+        op: "list_generations",
+    })
+}
+
+/// Returns the generation with the given ID.  Errors if it's not in the recorded history.
+pub(crate) fn get_generation<D: DataStore>(datastore: &D, id: u64) -> Result<Generation> {
+    datastore
+        .get_generation(id)
+        .context(error::DataStore {
+            op: "get_generation",
+        })?
+        .context(error::MissingGeneration { id })
+}
+
+/// Reverts live data to its state as of the given generation, returning the changed keys.
+pub(crate) fn rollback_to_generation<D: DataStore>(
+    datastore: &mut D,
+    id: u64,
+) -> Result<HashSet<Key>> {
+    // Check first so a bad ID is reported as such, rather than as a data store failure
+    get_generation(datastore, id)?;
+    datastore
+        .rollback_to_generation(id)
+        .context(error::DataStore { op: "rollback" })
+}
+
+/// Returns the keys with pending changes in the given transaction, including keys staged for
+/// removal.
+pub(crate) fn get_transaction_keys<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let mut keys = datastore
+        .list_populated_keys("", &pending)
+        .context(error::DataStore {
+            op: "list_populated_keys",
+        })?;
+    keys.extend(
+        datastore
+            .list_pending_deletions("", transaction)
+            .context(error::DataStore {
+                op: "list_pending_deletions",
+            })?,
+    );
+    Ok(keys)
+}
+
+/// Returns the keys changed by generations after the given one, which are the keys that rolling
+/// back to it could change.
+pub(crate) fn get_keys_changed_since<D: DataStore>(
+    datastore: &D,
+    id: u64,
+) -> Result<HashSet<String>> {
+    Ok(list_generations(datastore)?
+        .into_iter()
+        .filter(|g| g.id > id)
+        .flat_map(|g| g.changes.into_iter().map(|(key, _change)| key))
+        .collect())
+}
+
+/// Returns the ID of the latest generation, or 0 if nothing has been committed.
+pub(crate) fn latest_generation<D: DataStore>(datastore: &D) -> Result<u64> {
+    let latest = datastore.latest_generation_id().context(error::DataStore {
+        op: "latest_generation_id",
+    })?;
+    Ok(latest.unwrap_or(0))
+}
+
+/// Makes sure the given time is inside one of the update maintenance windows in live settings.
+/// Settings without any windows allow reboots at any time.
+pub(crate) fn check_maintenance_windows<D: DataStore>(
+    datastore: &D,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let settings = get_settings_prefix(datastore, "updates.maintenance-windows", &Committed::Live)?;
+    let windows = settings
+        .updates
+        .and_then(|updates| updates.maintenance_windows)
+        .unwrap_or_default();
+    if let Some(next) = next_maintenance_window(&windows, now).context(error::MaintenanceWindow)? {
+        ensure!(next <= now, error::RebootOutsideMaintenanceWindow { next });
+    }
+    Ok(())
+}
+
+/// Returns counts describing the contents of the data store, for metrics.
+pub(crate) fn get_datastore_metrics<D: DataStore>(datastore: &D) -> Result<DataStoreMetrics> {
+    let live_keys = datastore
+        .list_populated_keys("", &Committed::Live)
+        .context(error::DataStore {
+            op: "list_populated_keys",
+        })?
+        .len();
+    let transactions = list_transactions(datastore)?;
+    let mut pending_keys = 0;
+    for transaction in &transactions {
+        pending_keys += get_transaction_keys(datastore, transaction)?.len();
+    }
+    Ok(DataStoreMetrics {
+        live_keys,
+        pending_keys,
+        pending_transactions: transactions.len(),
+        generation: latest_generation(datastore)?,
+    })
+}
+
+/// SettingsChanges describes the commits made after a given generation, for clients watching for
+/// settings changes.
+#[derive(Debug, Serialize)]
+pub(crate) struct SettingsChanges {
+    /// The ID of the latest generation; clients should watch for changes after this next time.
+    pub(crate) latest: u64,
+    /// Whether commits after the requested generation have been dropped from the history, so
+    /// some changes aren't listed; clients should re-read settings rather than rely on `commits`.
+    pub(crate) truncated: bool,
+    pub(crate) commits: Vec<CommittedKeys>,
+}
+
+/// CommittedKeys lists the keys changed by a single commit.
+#[derive(Debug, Serialize)]
+pub(crate) struct CommittedKeys {
+    pub(crate) generation: u64,
+    pub(crate) transaction: String,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) keys: HashSet<String>,
+}
+
+/// Returns the keys changed by each commit after the generation `since`, limited to keys that
+/// start with the given prefix.  Commits that didn't change any matching keys are skipped.
+pub(crate) fn get_settings_changes<D: DataStore>(
+    datastore: &D,
+    since: u64,
+    prefix: &str,
+) -> Result<SettingsChanges> {
+    let generations = list_generations(datastore)?;
+    let latest = generations.last().map(|g| g.id).unwrap_or(0);
+    // Only the most recent generations are kept, so the ones right after `since` may be gone.
+    let truncated = generations
+        .first()
+        .map(|oldest| since + 1 < oldest.id)
+        .unwrap_or(false);
+
+    let mut commits = Vec::new();
+    for generation in generations.into_iter().filter(|g| g.id > since) {
+        let keys: HashSet<String> = generation
+            .changes
+            .into_iter()
+            .map(|(name, _change)| name)
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        if !keys.is_empty() {
+            commits.push(CommittedKeys {
+                generation: generation.id,
+                transaction: generation.transaction,
+                timestamp: generation.timestamp,
+                keys,
+            });
+        }
+    }
+
+    Ok(SettingsChanges {
+        latest,
+        truncated,
+        commits,
+    })
+}
+
+/// SettingsValidation reports whether proposed settings would be accepted, and if so, what they
+/// would change.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct SettingsValidation {
+    pub(crate) valid: bool,
+    pub(crate) errors: Vec<ValidationError>,
+    /// Changes the settings would make to live data, by key.
+    pub(crate) changes: HashMap<String, Change>,
+    pub(crate) affected_services: HashSet<String>,
+    pub(crate) configuration_files: HashSet<String>,
+}
+
+/// ValidationError describes a problem with the given value of a single key.
+#[derive(Debug, Serialize)]
+pub(crate) struct ValidationError {
+    pub(crate) key: String,
+    pub(crate) error: String,
+}
+
+/// Checks whether the given input would be accepted as Settings, without changing the data
+/// store.  If so, returns the changes it would make to live settings and the services and
+/// configuration files affected by those changes.  If not, returns every validation failure we
+/// can find, with the key it applies to.
+pub(crate) fn validate_settings<D: DataStore>(
+    datastore: &D,
+    input: &Value,
+) -> Result<SettingsValidation> {
+    let settings: Settings = match serde_json::from_value(input.clone()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            return Ok(SettingsValidation {
+                errors: find_validation_errors(input, e),
+                ..Default::default()
+            })
+        }
+    };
+
+    let pairs = to_pairs(&settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    let changes = datastore
+        .live_changes(&pairs, &HashSet::new())
+        .context(error::DataStore { op: "live_changes" })?;
+
+    let changed_keys = changes.keys().map(|k| k.as_str()).collect();
+    let affected = get_metadata_for_data_keys(datastore, "affected-services", &changed_keys)?;
+    let mut affected_services = HashSet::new();
+    for (key, value) in affected {
+        let services: Vec<String> =
+            serde_json::from_value(value).context(error::InvalidMetadata { key })?;
+        affected_services.extend(services);
+    }
+
+    // Metadata can name services that aren't defined in this variant, so we only look at the
+    // services we have.
+    let configuration_files = get_services(datastore)?
+        .into_iter()
+        .filter(|(name, _service)| affected_services.contains(name))
+        .flat_map(|(_name, service)| service.configuration_files)
+        .map(|file| file.to_string())
+        .collect();
+
+    Ok(SettingsValidation {
+        valid: true,
+        errors: Vec::new(),
+        changes,
+        affected_services,
+        configuration_files,
+    })
+}
+
+/// Deserialization stops at the first error, so to report all of them, we check each value in
+/// the input on its own.  Every field of Settings is optional, so a value is only rejected on its
+/// own if it's invalid.  If that doesn't explain the original error, we return it as-is.
+fn find_validation_errors(
+    input: &Value,
+    original: serde_json::Error,
+) -> Vec<ValidationError> {
+    let mut leaves = Vec::new();
+    find_leaves(input, Vec::new(), &mut leaves);
+
+    let mut errors = Vec::new();
+    for (path, value) in leaves {
+        // Rebuild the input with only this value, from the inside out.
+        let single = path.iter().rev().fold(value.clone(), |inner, segment| {
+            let mut outer = serde_json::Map::new();
+            outer.insert(segment.clone(), inner);
+            Value::Object(outer)
+        });
+        if let Err(e) = serde_json::from_value::<Settings>(single) {
+            let mut segments = vec!["settings".to_string()];
+            segments.extend(path);
+            // Invalid map keys can make invalid data keys; we still want to show them.
+            let key = match Key::from_segments(KeyType::Data, &segments) {
+                Ok(key) => key.name().clone(),
+                Err(_) => segments.join("."),
+            };
+            errors.push(ValidationError {
+                key,
+                error: e.to_string(),
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        errors.push(ValidationError {
+            key: "settings".to_string(),
+            error: original.to_string(),
+        });
+    }
+    errors
+}
+
+/// Collects the path to each value in the input that isn't a non-empty object.
+fn find_leaves<'a>(
+    value: &'a Value,
+    path: Vec<String>,
+    leaves: &mut Vec<(Vec<String>, &'a Value)>,
+) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (name, inner) in map {
+                let mut inner_path = path.clone();
+                inner_path.push(name.clone());
+                find_leaves(inner, inner_path, leaves);
+            }
+        }
+        _ => leaves.push((path, value)),
+    }
+}
+
 /// Launches the config applier to make appropriate changes to the system based on any settings
 /// that have been committed.  Can be called after a commit, with the keys that changed in that
 /// commit, or called on its own to reset configuration state with all known keys.
@@ -397,6 +835,7 @@ pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
         Some(TbuErrorStatus::DisallowCommand) => error::Error::DisallowCommand,
         Some(TbuErrorStatus::UpdateDoesNotExist) => error::Error::UpdateDoesNotExist,
         Some(TbuErrorStatus::NoStagedImage) => error::Error::NoStagedImage,
+        Some(TbuErrorStatus::OutsideMaintenanceWindow) => error::Error::OutsideMaintenanceWindow,
         // other errors
         _ => error::Error::UpdateError,
     };
@@ -410,6 +849,7 @@ mod test {
     use crate::datastore::{Committed, DataStore, Key, KeyType};
     use maplit::{hashmap, hashset};
     use model::Service;
+    use serde_json::json;
     use std::convert::TryInto;
 
     #[test]
@@ -450,6 +890,32 @@ mod test {
         assert_eq!(settings.motd, None);
     }
 
+    #[test]
+    fn check_maintenance_windows_works() {
+        let time = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        // Monday morning, and Monday afternoon.
+        let inside = time("2020-07-06T03:00:00Z");
+        let outside = time("2020-07-06T15:00:00Z");
+
+        // Without windows, any time is fine.
+        let mut ds = MemoryDataStore::new();
+        check_maintenance_windows(&ds, outside).unwrap();
+
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.updates.maintenance-windows").unwrap(),
+            "[\"Mon 02:00-04:00 UTC\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        check_maintenance_windows(&ds, inside).unwrap();
+        match check_maintenance_windows(&ds, outside) {
+            Err(error::Error::RebootOutsideMaintenanceWindow { next }) => {
+                assert_eq!(next, time("2020-07-13T02:00:00Z"))
+            }
+            other => panic!("expected reboot to be refused, got {:?}", other),
+        }
+    }
+
     #[test]
     fn get_settings_keys_works() {
         let mut ds = MemoryDataStore::new();
@@ -598,4 +1064,252 @@ mod test {
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(settings.motd, Some("json string".try_into().unwrap()));
     }
+
+    #[test]
+    fn rollback_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let region = Key::new(KeyType::Data, "settings.aws.region").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        // Generation 1 sets the motd
+        ds.set_key(&motd, "\"first\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        // Generation 2 changes the motd and adds a region
+        ds.set_key(&motd, "\"second\"", &pending).unwrap();
+        ds.set_key(&region, "\"us-west-2\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        let generations = list_generations(&ds).unwrap();
+        assert_eq!(generations.len(), 2);
+        assert_eq!(
+            generations[1].changes["settings.motd"].old,
+            Some("\"first\"".to_string())
+        );
+
+        // Roll back to generation 1; the region is removed and the motd restored
+        let changed = rollback_to_generation(&mut ds, 1).unwrap();
+        assert_eq!(changed, hashset!(motd, region));
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.motd, Some("first".try_into().unwrap()));
+        assert_eq!(settings.aws, None);
+
+        // The rollback is recorded as a new generation
+        let rollback = get_generation(&ds, 3).unwrap();
+        assert_eq!(rollback.transaction, "rollback-to-1");
+        assert_eq!(rollback.changes["settings.aws.region"].new, None);
+
+        // Unknown generations are rejected
+        rollback_to_generation(&mut ds, 42).unwrap_err();
+    }
+
+    #[test]
+    fn get_settings_changes_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        // No commits yet
+        let changes = get_settings_changes(&ds, 0, "settings.").unwrap();
+        assert_eq!(changes.latest, 0);
+        assert!(changes.commits.is_empty());
+
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "\"json string\"",
+            &pending,
+        )
+        .unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.aws.region").unwrap(),
+            "\"us-west-2\"",
+            &pending,
+        )
+        .unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        // All commits
+        let changes = get_settings_changes(&ds, 0, "settings.").unwrap();
+        assert_eq!(changes.latest, 2);
+        assert_eq!(changes.commits.len(), 2);
+        assert_eq!(changes.commits[0].keys, hashset!("settings.motd".to_string()));
+
+        // Commits after the first
+        let changes = get_settings_changes(&ds, 1, "settings.").unwrap();
+        assert_eq!(changes.commits.len(), 1);
+        assert_eq!(changes.commits[0].generation, 2);
+
+        // Commits that touched a prefix
+        let changes = get_settings_changes(&ds, 0, "settings.aws").unwrap();
+        assert_eq!(changes.commits.len(), 1);
+        assert_eq!(
+            changes.commits[0].keys,
+            hashset!("settings.aws.region".to_string())
+        );
+
+        // Commits that are no longer in the history
+        assert!(!changes.truncated);
+        let mut pruned = MemoryDataStore::new();
+        let latest = ds.get_generation(2).unwrap().unwrap();
+        pruned.save_generation(&latest).unwrap();
+        let changes = get_settings_changes(&pruned, 0, "settings.").unwrap();
+        assert!(changes.truncated);
+        assert_eq!(changes.commits.len(), 1);
+        let changes = get_settings_changes(&pruned, 1, "settings.").unwrap();
+        assert!(!changes.truncated);
+    }
+
+    #[test]
+    fn validate_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "\"hi\"",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &Key::new(KeyType::Data, "settings.ntp").unwrap(),
+            "[\"chronyd\"]",
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.chronyd.configuration-files").unwrap(),
+            "[\"chrony-conf\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.chronyd.restart-commands").unwrap(),
+            "[]",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Unchanged keys aren't listed, and services are found through inherited metadata
+        let input = json!({"motd": "hi", "ntp": {"time-servers": ["https://example.com"]}});
+        let validation = validate_settings(&ds, &input).unwrap();
+        assert!(validation.valid);
+        assert!(validation.errors.is_empty());
+        assert_eq!(
+            validation.changes.keys().collect::<Vec<_>>(),
+            vec!["settings.ntp.time-servers"]
+        );
+        assert_eq!(validation.affected_services, hashset!("chronyd".to_string()));
+        assert_eq!(
+            validation.configuration_files,
+            hashset!("chrony-conf".to_string())
+        );
+
+        // Each invalid value is reported with its key
+        let input = json!({
+            "motd": "hi",
+            "kubernetes": {"node-labels": {"ok": "fine", "bad label": "fine"}},
+            "aws": {"region": "us-west-2\nus-east-1"},
+        });
+        let validation = validate_settings(&ds, &input).unwrap();
+        assert!(!validation.valid);
+        assert!(validation.changes.is_empty());
+        let mut keys: Vec<_> = validation.errors.iter().map(|e| e.key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec!["settings.aws.region", "settings.kubernetes.node-labels.bad label"]
+        );
+        // The datastore isn't changed
+        assert!(ds.list_transactions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn get_transaction_diff_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        for (key, live, new) in &[
+            ("settings.motd", Some("\"hi\""), "\"hello\""),
+            ("settings.aws.region", Some("\"us-west-2\""), "\"us-west-2\""),
+            ("settings.kubernetes.cluster-name", None, "\"cluster\""),
+        ] {
+            let key = Key::new(KeyType::Data, key).unwrap();
+            if let Some(live) = live {
+                ds.set_key(&key, live, &Committed::Live).unwrap();
+            }
+            ds.set_key(&key, new, &pending).unwrap();
+        }
+
+        let diff = get_transaction_diff(&ds, tx).unwrap();
+        assert_eq!(
+            diff.added,
+            hashmap!("settings.kubernetes.cluster-name".to_string() => "\"cluster\"".to_string())
+        );
+        assert_eq!(
+            diff.changed,
+            hashmap!("settings.motd".to_string() => Change {
+                old: Some("\"hi\"".to_string()),
+                new: Some("\"hello\"".to_string()),
+            })
+        );
+        assert!(diff.removed.is_empty());
+
+        // Other transactions are unaffected
+        let diff = get_transaction_diff(&ds, "other").unwrap();
+        assert!(diff.added.is_empty() && diff.changed.is_empty());
+    }
+
+    #[test]
+    fn delete_settings_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        for key in &[
+            "settings.kubernetes.node-labels.a",
+            "settings.kubernetes.node-labels.b",
+            "settings.motd",
+            "settings.motd-extra",
+        ] {
+            let key = Key::new(KeyType::Data, key).unwrap();
+            ds.set_key(&key, "\"x\"", &Committed::Live).unwrap();
+        }
+        // Only set in the transaction, so removing it just drops the pending value
+        let new_label = Key::new(KeyType::Data, "settings.kubernetes.node-labels.c").unwrap();
+        ds.set_key(&new_label, "\"x\"", &pending).unwrap();
+
+        let names = hashset!("settings.kubernetes.node-labels", "settings.motd");
+        let deleted = delete_settings(&mut ds, &names, tx).unwrap();
+        let mut deleted: Vec<_> = deleted.iter().map(|k| k.name().as_str()).collect();
+        deleted.sort_unstable();
+        assert_eq!(
+            deleted,
+            vec![
+                "settings.kubernetes.node-labels.a",
+                "settings.kubernetes.node-labels.b",
+                "settings.motd",
+            ]
+        );
+        assert!(!ds.key_populated(&new_label, &pending).unwrap());
+
+        // Removals are pending changes of the transaction
+        let keys = get_transaction_keys(&ds, tx).unwrap();
+        let mut keys: Vec<_> = keys.iter().map(|k| k.name().as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, deleted);
+
+        // Removals show in the diff, but not in the pending settings
+        let diff = get_transaction_diff(&ds, tx).unwrap();
+        assert_eq!(diff.removed.len(), 3);
+        let settings = get_transaction(&ds, tx).unwrap();
+        assert!(settings.motd.is_none() && settings.kubernetes.is_none());
+
+        commit_transaction(&mut ds, tx).unwrap();
+        let live = ds.list_populated_keys("settings.", &Committed::Live).unwrap();
+        let live: Vec<_> = live.iter().map(|k| k.name().as_str()).collect();
+        assert_eq!(live, vec!["settings.motd-extra"]);
+
+        // Only settings can be removed, and they have to exist
+        assert!(delete_settings(&mut ds, &hashset!("services.foo"), tx).is_err());
+        assert!(delete_settings(&mut ds, &hashset!("settings.motd"), tx).is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use crate::datastore::{self, deserialization, serialization};
+use chrono::{DateTime, Utc};
 use nix::unistd::Gid;
 use snafu::Snafu;
 use std::io;
@@ -32,6 +33,36 @@ pub enum Error {
 
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+    // Access control errors
+    #[snafu(display("Unable to read access policy '{}': {}", path.display(), source))]
+    AccessPolicyRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse access policy '{}': {}", path.display(), source))]
+    AccessPolicyParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Forbidden: {}", reason))]
+    Forbidden { reason: String },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Audit log errors
+    #[snafu(display("Unable to open audit log '{}': {}", path.display(), source))]
+    AuditLogOpen { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to write audit log '{}': {}", path.display(), source))]
+    AuditLogWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to serialize audit log entry: {}", source))]
+    AuditEntrySerialization { source: serde_json::Error },
+
+    #[snafu(display("Another thread poisoned the audit log lock by panicking"))]
+    AuditLogLock,
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
     // Server errors
     #[snafu(display("Missing required input '{}'", input))]
     MissingInput { input: String },
@@ -39,6 +70,15 @@ pub enum Error {
     #[snafu(display("Input '{}' cannot be empty", input))]
     EmptyInput { input: String },
 
+    #[snafu(display("Input '{}' is not a valid number: {}", input, source))]
+    InvalidNumber {
+        input: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Only settings can be deleted, not '{}'", name))]
+    DeleteNonSetting { name: String },
+
     #[snafu(display("Another thread poisoned the data store lock by panicking"))]
     DataStoreLock,
 
@@ -108,6 +148,9 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Generation {} not found in history", id))]
+    MissingGeneration { id: u64 },
+
     #[snafu(display("Unable to start config applier: {} ", source))]
     ConfigApplierStart { source: io::Error },
 
@@ -127,6 +170,17 @@ pub enum Error {
     ))]
     Reboot { exit_code: i32, stderr: Vec<u8> },
 
+    #[snafu(display(
+        "Reboot not allowed outside of update maintenance windows; the next one opens at {}",
+        next
+    ))]
+    RebootOutsideMaintenanceWindow { next: DateTime<Utc> },
+
+    #[snafu(display("Failed to check update maintenance windows: {}", source))]
+    MaintenanceWindow {
+        source: model::modeled_types::error::Error,
+    },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Update related errors
@@ -151,6 +205,9 @@ pub enum Error {
     #[snafu(display("Update action not allowed according to update state"))]
     DisallowCommand,
 
+    #[snafu(display("Update action not allowed outside of update maintenance windows"))]
+    OutsideMaintenanceWindow,
+
     #[snafu(display("Update dispatcher failed"))]
     UpdateError,
 
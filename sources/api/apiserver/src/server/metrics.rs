@@ -0,0 +1,451 @@
+//! The metrics module keeps counts and timings of API requests, and renders them along with the
+//! state of the data store and of updates in the Prometheus text exposition format, so they can be
+//! scraped through the `/metrics` endpoint.
+//!
+//! https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use actix_web::dev::ResourceDef;
+use actix_web::HttpRequest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use thar_be_updates::status::UpdateStatus;
+
+/// The content type of the text exposition format.
+pub(crate) const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Upper bounds of the request duration histogram buckets, in seconds.  Settings watches can take
+/// minutes, so the top buckets are large.
+const DURATION_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0, 60.0, 300.0];
+
+/// Unmatched is stored in the extensions of requests that didn't match any route.
+#[derive(Debug)]
+pub(crate) struct Unmatched;
+
+/// The routes that take parameters, as registered with the server.  Requests to them are labeled
+/// with the pattern rather than the path.
+const PARAMETERIZED_ROUTES: &[&str] = &["/history/{id}", "/history/{id}/rollback"];
+
+/// Returns the route that handled a request, for use as a metric label.  We don't use the raw
+/// path so that clients can't create unlimited series by requesting made-up paths, or by looking
+/// at each generation of history.
+pub(crate) fn route_label(req: &HttpRequest) -> String {
+    if req.extensions().get::<Unmatched>().is_some() {
+        return "unmatched".to_string();
+    }
+    // This is the path the router matched, with any percent-encoding of unreserved characters
+    // removed, so routes without parameters match it exactly.
+    let path = req.match_info().get_ref().path();
+    if req.match_info().is_empty() {
+        return path.to_string();
+    }
+    PARAMETERIZED_ROUTES
+        .iter()
+        .find(|pattern| ResourceDef::new(**pattern).is_match(path))
+        .map(|pattern| pattern.to_string())
+        // Don't fall back to the path if someone adds a route with parameters and forgets to
+        // list it above.
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// RequestStats are the counts and timings of requests to one method and route.
+#[derive(Debug)]
+struct RequestStats {
+    /// Count of responses by status code.
+    statuses: BTreeMap<u16, u64>,
+    /// Count of requests that took at most the matching duration in DURATION_BUCKETS.
+    buckets: Vec<u64>,
+    count: u64,
+    seconds: f64,
+}
+
+impl Default for RequestStats {
+    fn default() -> Self {
+        Self {
+            statuses: BTreeMap::new(),
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            count: 0,
+            seconds: 0.0,
+        }
+    }
+}
+
+/// RequestMetrics records the requests handled by the server.
+#[derive(Debug, Default)]
+pub(crate) struct RequestMetrics {
+    /// Keyed by method and route.
+    requests: Mutex<BTreeMap<(String, String), RequestStats>>,
+    /// Count of transactions committed to live data.
+    commits: AtomicU64,
+}
+
+impl RequestMetrics {
+    /// Records a successful commit of a transaction to live data.
+    pub(crate) fn record_commit(&self) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a finished request.
+    pub(crate) fn record(&self, method: &str, route: String, status: u16, duration: Duration) {
+        // A poisoned lock just means a panic mid-update; the counts are still usable.
+        let mut requests = match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let stats = requests.entry((method.to_string(), route)).or_default();
+
+        let seconds = duration.as_secs_f64();
+        *stats.statuses.entry(status).or_insert(0) += 1;
+        for (bucket, bound) in stats.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        stats.count += 1;
+        stats.seconds += seconds;
+    }
+
+    /// Writes the request metrics in the text format.
+    pub(crate) fn render(&self, out: &mut MetricsText) {
+        let requests = match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        out.family(
+            "apiserver_requests_total",
+            "counter",
+            "Requests handled, by method, route, and response status.",
+        );
+        for ((method, route), stats) in requests.iter() {
+            for (status, count) in &stats.statuses {
+                let status = status.to_string();
+                out.sample(
+                    "apiserver_requests_total",
+                    &[("method", method), ("route", route), ("status", &status)],
+                    count,
+                );
+            }
+        }
+
+        out.family(
+            "apiserver_request_duration_seconds",
+            "histogram",
+            "Time taken to handle requests, by method and route.",
+        );
+        for ((method, route), stats) in requests.iter() {
+            let labels = [("method", method.as_str()), ("route", route.as_str())];
+            for (count, bound) in stats.buckets.iter().zip(DURATION_BUCKETS) {
+                let bound = bound.to_string();
+                out.sample(
+                    "apiserver_request_duration_seconds_bucket",
+                    &[labels[0], labels[1], ("le", &bound)],
+                    count,
+                );
+            }
+            out.sample(
+                "apiserver_request_duration_seconds_bucket",
+                &[labels[0], labels[1], ("le", "+Inf")],
+                stats.count,
+            );
+            out.sample(
+                "apiserver_request_duration_seconds_sum",
+                &labels,
+                stats.seconds,
+            );
+            out.sample(
+                "apiserver_request_duration_seconds_count",
+                &labels,
+                stats.count,
+            );
+        }
+
+        out.family(
+            "apiserver_commits_total",
+            "counter",
+            "Transactions committed to live data.",
+        );
+        out.sample(
+            "apiserver_commits_total",
+            &[],
+            self.commits.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// DataStoreMetrics describes the contents of the data store.
+#[derive(Debug)]
+pub(crate) struct DataStoreMetrics {
+    pub(crate) live_keys: usize,
+    /// Count of keys with pending changes, summed over transactions.  Clients like apiclient make
+    /// a transaction per change, so counting by transaction would make unbounded label values.
+    pub(crate) pending_keys: usize,
+    pub(crate) pending_transactions: usize,
+    /// ID of the latest settings generation, or 0 if nothing has been committed.
+    pub(crate) generation: u64,
+}
+
+impl DataStoreMetrics {
+    /// Writes the data store metrics in the text format.
+    pub(crate) fn render(&self, out: &mut MetricsText) {
+        out.family(
+            "apiserver_datastore_keys",
+            "gauge",
+            "Populated keys in the data store, in live data or in all pending transactions.",
+        );
+        out.sample(
+            "apiserver_datastore_keys",
+            &[("committed", "live")],
+            self.live_keys,
+        );
+        out.sample(
+            "apiserver_datastore_keys",
+            &[("committed", "pending")],
+            self.pending_keys,
+        );
+
+        out.family(
+            "apiserver_datastore_pending_transactions",
+            "gauge",
+            "Transactions with pending changes.",
+        );
+        out.sample(
+            "apiserver_datastore_pending_transactions",
+            &[],
+            self.pending_transactions,
+        );
+
+        out.family(
+            "apiserver_settings_generation",
+            "gauge",
+            "ID of the latest generation of live settings; commits that change nothing don't make one.",
+        );
+        out.sample("apiserver_settings_generation", &[], self.generation);
+    }
+}
+
+/// Writes the update metrics in the text format.  `status` is None if the update status isn't
+/// available, for example because no update command has run yet, or one is running now.
+pub(crate) fn render_update_status(status: Option<&UpdateStatus>, out: &mut MetricsText) {
+    out.family(
+        "bottlerocket_update_status_up",
+        "gauge",
+        "Whether the update status could be read.",
+    );
+    out.sample(
+        "bottlerocket_update_status_up",
+        &[],
+        if status.is_some() { 1 } else { 0 },
+    );
+    let status = match status {
+        Some(status) => status,
+        None => return,
+    };
+
+    out.family(
+        "bottlerocket_update_state",
+        "gauge",
+        "The current state of the update process; the state is given in the label.",
+    );
+    let state = label(status.update_state());
+    out.sample("bottlerocket_update_state", &[("state", &state)], 1);
+
+    if let Some(active) = status.active_partition() {
+        out.family(
+            "bottlerocket_update_active_version_info",
+            "gauge",
+            "The version of the OS in the active partition set.",
+        );
+        let version = active.image().version().to_string();
+        out.sample(
+            "bottlerocket_update_active_version_info",
+            &[("version", &version)],
+            1,
+        );
+    }
+
+    out.family(
+        "bottlerocket_update_available_updates",
+        "gauge",
+        "Updates listed in the update repository.",
+    );
+    out.sample(
+        "bottlerocket_update_available_updates",
+        &[],
+        status.available_updates().len(),
+    );
+
+    if let Some(chosen) = status.chosen_update() {
+        out.family(
+            "bottlerocket_update_available_version_info",
+            "gauge",
+            "The version of the update that would be applied.",
+        );
+        let version = chosen.version().to_string();
+        out.sample(
+            "bottlerocket_update_available_version_info",
+            &[("version", &version)],
+            1,
+        );
+    }
+
+    if let Some(command) = status.most_recent_command() {
+        let cmd_type = label(command.cmd_type());
+        let cmd_status = label(command.cmd_status());
+        out.family(
+            "bottlerocket_update_last_command_info",
+            "gauge",
+            "The most recent update command and its result.",
+        );
+        out.sample(
+            "bottlerocket_update_last_command_info",
+            &[("command", &cmd_type), ("status", &cmd_status)],
+            1,
+        );
+        out.family(
+            "bottlerocket_update_last_command_timestamp_seconds",
+            "gauge",
+            "When the most recent update command finished, in seconds since the epoch.",
+        );
+        out.sample(
+            "bottlerocket_update_last_command_timestamp_seconds",
+            &[("command", &cmd_type)],
+            command.timestamp().timestamp(),
+        );
+    }
+}
+
+/// Returns the serialized form of a unit enum variant, so metric labels match the values shown in
+/// the API.
+fn label<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => "unknown".to_string(),
+    }
+}
+
+/// MetricsText builds a document in the Prometheus text format.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsText(String);
+
+impl MetricsText {
+    /// Starts a metric family, which must be followed by its samples.
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        // Writing to a String can't fail.
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        let _ = write!(self.0, "{}", name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+impl Display for MetricsText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Escapes a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn route_labels() {
+        let req = TestRequest::with_uri("/settings").to_http_request();
+        assert_eq!(route_label(&req), "/settings");
+
+        for path in &["/history/3", "/history/made-up"] {
+            let req = TestRequest::with_uri(path)
+                .param("id", &path["/history/".len()..])
+                .to_http_request();
+            assert_eq!(route_label(&req), "/history/{id}");
+        }
+
+        let req = TestRequest::with_uri("/history/history/rollback")
+            .param("id", "history")
+            .to_http_request();
+        assert_eq!(route_label(&req), "/history/{id}/rollback");
+
+        let req = TestRequest::with_uri("/made-up").to_http_request();
+        req.extensions_mut().insert(Unmatched);
+        assert_eq!(route_label(&req), "unmatched");
+    }
+
+    #[test]
+    fn requests() {
+        let metrics = RequestMetrics::default();
+        metrics.record("GET", "/settings".into(), 200, Duration::from_millis(2));
+        metrics.record("GET", "/settings".into(), 200, Duration::from_millis(20));
+        metrics.record("GET", "/settings".into(), 404, Duration::from_secs(2));
+        metrics.record_commit();
+        metrics.record_commit();
+
+        let mut out = MetricsText::default();
+        metrics.render(&mut out);
+        let text = out.to_string();
+
+        for line in &[
+            "# TYPE apiserver_requests_total counter",
+            r#"apiserver_requests_total{method="GET",route="/settings",status="200"} 2"#,
+            r#"apiserver_requests_total{method="GET",route="/settings",status="404"} 1"#,
+            r#"apiserver_request_duration_seconds_bucket{method="GET",route="/settings",le="0.005"} 1"#,
+            r#"apiserver_request_duration_seconds_bucket{method="GET",route="/settings",le="0.05"} 2"#,
+            r#"apiserver_request_duration_seconds_bucket{method="GET",route="/settings",le="5"} 3"#,
+            r#"apiserver_request_duration_seconds_bucket{method="GET",route="/settings",le="+Inf"} 3"#,
+            r#"apiserver_request_duration_seconds_count{method="GET",route="/settings"} 3"#,
+            "# TYPE apiserver_commits_total counter",
+            "apiserver_commits_total 2",
+        ] {
+            assert!(text.lines().any(|l| l == *line), "missing '{}'", line);
+        }
+    }
+
+    #[test]
+    fn datastore() {
+        let metrics = DataStoreMetrics {
+            live_keys: 10,
+            pending_keys: 2,
+            pending_transactions: 1,
+            generation: 3,
+        };
+        let mut out = MetricsText::default();
+        metrics.render(&mut out);
+        let text = out.to_string();
+
+        assert!(text.contains("apiserver_datastore_keys{committed=\"live\"} 10\n"));
+        assert!(text.contains("apiserver_datastore_keys{committed=\"pending\"} 2\n"));
+        assert!(text.contains("apiserver_datastore_pending_transactions 1\n"));
+        assert!(text.contains("apiserver_settings_generation 3\n"));
+    }
+
+    #[test]
+    fn no_update_status() {
+        let mut out = MetricsText::default();
+        render_update_status(None, &mut out);
+        assert!(out
+            .to_string()
+            .contains("bottlerocket_update_status_up 0\n"));
+    }
+}
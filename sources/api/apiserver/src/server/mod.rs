@@ -1,20 +1,35 @@
 //! The server module owns the API surface.  It interfaces with the datastore through the
 //! server::controller module.
 
+mod access;
+mod audit;
 mod controller;
 mod error;
+mod metrics;
 pub use error::Error;
 
-use crate::datastore::{Committed, FilesystemDataStore, Key, Value};
+use crate::datastore::{Committed, FilesystemDataStore, Generation, Key, Value};
+use access::{AccessControl, PeerCredentials};
+use actix_http::{HttpService, Protocol};
+use actix_rt::net::UnixStream;
+use actix_rt::time::delay_for;
+use actix_server::Server;
+use actix_service::{map_config, pipeline_factory};
+use actix_web::dev::{AppConfig, Service, ServiceRequest};
 use actix_web::{
-    error::ResponseError, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder,
+    error::ResponseError, web, App, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+    Responder,
 };
+use audit::{AuditEntry, AuditLog};
+use metrics::{MetricsText, RequestMetrics};
 use bottlerocket_release::BottlerocketRelease;
+use chrono::Utc;
 use error::Result;
 use fs2::FileExt;
 use futures::future;
 use http::StatusCode;
 use log::info;
+use model::schema::JsonSchema;
 use model::{ConfigurationFiles, Model, Services, Settings};
 use nix::unistd::{chown, Gid};
 use snafu::{ensure, OptionExt, ResultExt};
@@ -26,8 +41,20 @@ use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::process::Command;
 use std::sync;
+use std::time::{Duration, Instant};
 use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE};
 
+/// How long a settings watch waits for a change if the client doesn't say.
+const DEFAULT_WATCH_TIMEOUT: Duration = Duration::from_secs(60);
+/// The longest a settings watch will wait for a change.
+const MAX_WATCH_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often a settings watch checks for new commits.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The OpenAPI description of the API, with schemas generated from the model of the current
+/// variant by build.rs.
+const OPENAPI_DOCUMENT: &str = include_str!(concat!(env!("OUT_DIR"), "/openapi.yaml"));
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // sd_notify helper
@@ -53,11 +80,17 @@ fn notify_unix_socket_ready() -> Result<()> {
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
 /// to interface with the controller.
+///
+/// If `access_policy` is given, requests are only allowed as described in the policy file; see
+/// the access module.  If `audit_log` is given, requests that change the system are recorded
+/// there; see the audit module.
 pub async fn serve<P1, P2>(
     socket_path: P1,
     datastore_path: P2,
     threads: usize,
     socket_gid: Option<Gid>,
+    access_policy: Option<&Path>,
+    audit_log: Option<&Path>,
 ) -> Result<()>
 where
     P1: AsRef<Path>,
@@ -66,8 +99,14 @@ where
     let shared_datastore = web::Data::new(SharedDataStore {
         ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
     });
-
-    let http_server = HttpServer::new(move || {
+    let access_control = web::Data::new(AccessControl::from_path(access_policy)?);
+    let audit_log = web::Data::new(AuditLog::open(audit_log)?);
+    let request_metrics = web::Data::new(RequestMetrics::default());
+
+    let app = move || {
+        let route_access = access_control.clone();
+        let audit_log = audit_log.clone();
+        let recorded_metrics = request_metrics.clone();
         App::new()
             // In our implementation of ResponseError on our own error type below, we include the
             // error message in the response for debugging purposes.  If actix rejects a request
@@ -81,6 +120,75 @@ where
             // This makes the data store available to API methods merely by having a Data
             // parameter.
             .app_data(shared_datastore.clone())
+            .app_data(access_control.clone())
+            .app_data(request_metrics.clone())
+
+            // Check that the client may use the requested route before routing the request;
+            // handlers that change settings also check the specific keys.
+            .wrap_fn(move |req: ServiceRequest, srv| {
+                let peer = peer_credentials(&req);
+                let allowed =
+                    route_access.check_route(peer.as_ref(), req.method().as_str(), req.path());
+                let response = allowed.map(|_| srv.call(req));
+                async move { Ok(response?.await?) }
+            })
+
+            // Record requests that change the system, once we know how they turned out.  This is
+            // registered last so it wraps the access check above, and records denied requests.
+            .wrap_fn(move |req: ServiceRequest, srv| {
+                let entry = if audit::is_audited(req.method(), req.path()) {
+                    Some(AuditEntry::new(
+                        peer_credentials(&req),
+                        req.method(),
+                        req.uri().to_string(),
+                    ))
+                } else {
+                    None
+                };
+                let audit_log = audit_log.clone();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await;
+                    if let Some(mut entry) = entry {
+                        match &response {
+                            Ok(res) => entry.finish(
+                                Some(res.request()),
+                                res.status(),
+                                res.response().error(),
+                            ),
+                            Err(e) => entry.finish(
+                                None as Option<&HttpRequest>,
+                                e.as_response_error().error_response().status(),
+                                Some(e),
+                            ),
+                        }
+                        audit_log.record(&entry);
+                    }
+                    response
+                }
+            })
+
+            // Count and time every request, including those that are denied.
+            .wrap_fn(move |req: ServiceRequest, srv| {
+                let start = Instant::now();
+                let method = req.method().clone();
+                let request_metrics = recorded_metrics.clone();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await;
+                    // Requests denied by the access check never reach the router, so we don't
+                    // know their route.
+                    let (route, status) = match &response {
+                        Ok(res) => (metrics::route_label(res.request()), res.status()),
+                        Err(e) => (
+                            "denied".to_string(),
+                            e.as_response_error().error_response().status(),
+                        ),
+                    };
+                    request_metrics.record(method.as_str(), route, status.as_u16(), start.elapsed());
+                    response
+                }
+            })
 
             // Retrieve the full API model; not all data is writable, so we only support GET.
             .route("/", web::get().to(get_model))
@@ -88,7 +196,10 @@ where
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
-                    .route("", web::patch().to(patch_settings)),
+                    .route("", web::patch().to(patch_settings))
+                    .route("", web::delete().to(delete_settings))
+                    .route("/watch", web::get().to(watch_settings))
+                    .route("/validate", web::post().to(validate_settings)),
             )
             .service(
                 // Transaction support
@@ -96,6 +207,7 @@ where
                     .route("/list", web::get().to(get_transaction_list))
                     .route("", web::get().to(get_transaction))
                     .route("", web::delete().to(delete_transaction))
+                    .route("/diff", web::get().to(get_transaction_diff))
                     .route("/commit", web::post().to(commit_transaction))
                     .route("/apply", web::post().to(apply_changes))
                     .route(
@@ -103,7 +215,17 @@ where
                         web::post().to(commit_transaction_and_apply),
                     ),
             )
+            .service(
+                // History of commits to live data, and rollback to earlier generations
+                web::scope("/history")
+                    .route("", web::get().to(get_history))
+                    .route("/{id}", web::get().to(get_generation))
+                    .route("/{id}/rollback", web::post().to(rollback)),
+            )
             .service(web::scope("/os").route("", web::get().to(get_os_info)))
+            .service(web::scope("/schema").route("", web::get().to(get_schema)))
+            .service(web::scope("/openapi").route("", web::get().to(get_openapi)))
+            .service(web::scope("/metrics").route("", web::get().to(get_metrics)))
             .service(
                 web::scope("/metadata")
                     .route("/affected-services", web::get().to(get_affected_services))
@@ -124,12 +246,26 @@ where
                     .route("/deactivate-update", web::post().to(deactivate_update)),
             )
             .service(web::scope("/updates").route("/status", web::get().to(get_update_status)))
-    })
-    .workers(threads)
-    .bind_uds(socket_path.as_ref())
-    .context(error::BindSocket {
-        path: socket_path.as_ref(),
-    })?;
+
+            // Mark requests that don't match any route, so metrics don't record their paths.
+            .default_service(web::route().to(unmatched))
+    };
+
+    // actix-web's HttpServer doesn't let us see the connection, so we build the HTTP service
+    // ourselves, the same way, so that we can store the credentials of each client with its
+    // requests.
+    let http_server = Server::build()
+        .workers(threads)
+        .bind_uds("apiserver", socket_path.as_ref(), move || {
+            pipeline_factory(|io: UnixStream| future::ok((io, Protocol::Http1, None))).and_then(
+                HttpService::build()
+                    .on_connect(PeerCredentials::from_stream)
+                    .finish(map_config(app(), |_| AppConfig::default())),
+            )
+        })
+        .context(error::BindSocket {
+            path: socket_path.as_ref(),
+        })?;
 
     // If the socket needs to be chowned to a group to grant further access, that can be passed
     // as a paramter.
@@ -192,16 +328,126 @@ async fn get_settings(
 
 /// Apply the requested settings to the pending data store
 async fn patch_settings(
+    req: HttpRequest,
     settings: web::Json<Settings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
 ) -> Result<HttpResponse> {
     let transaction = transaction_name(&query);
+    let keys = controller::settings_keys(&settings)?;
+    let key_names = keys.iter().map(|k| k.name());
+    audit::note(&req, Some(transaction), key_names.clone());
+    access.check_keys(peer_credentials(&req).as_ref(), key_names)?;
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
     controller::set_settings(&mut *datastore, &settings, transaction)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
+/// Removes the settings given in the 'keys' query parameter in the given transaction, or the
+/// "default" transaction if unspecified.  Returns the keys that will be removed when the
+/// transaction is committed.
+async fn delete_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
+) -> Result<ChangedKeysResponse> {
+    let transaction = transaction_name(&query);
+    let keys_str = query.get("keys").context(error::MissingInput { input: "keys" })?;
+    let names = comma_separated("keys", keys_str)?;
+    audit::note(&req, Some(transaction), &names);
+    access.check_keys(peer_credentials(&req).as_ref(), &names)?;
+
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    let deleted = controller::delete_settings(&mut *datastore, &names, transaction)?;
+    Ok(ChangedKeysResponse(deleted))
+}
+
+/// Checks whether the given settings would be accepted, and what they would change, without
+/// changing the data store.  The input is taken as generic JSON so that we can report every
+/// validation failure rather than the first one.
+async fn validate_settings(
+    input: web::Json<Value>,
+    data: web::Data<SharedDataStore>,
+) -> Result<SettingsValidationResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let validation = controller::validate_settings(&*datastore, &input)?;
+    Ok(SettingsValidationResponse(validation))
+}
+
+/// Waits for commits that change settings, returning the keys changed by each commit.  The
+/// 'since' query parameter gives the generation after which to look for commits; if it's not
+/// specified, we wait for the next commit.  If 'prefix' is specified, only keys starting with
+/// the prefix are considered.  'timeout' is the number of seconds to wait before returning with
+/// no changes.
+async fn watch_settings(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<SettingsChangesResponse> {
+    // Note: the prefix should not include "settings."
+    let prefix = match query.get("prefix") {
+        Some(prefix_str) if prefix_str.is_empty() => {
+            return error::EmptyInput { input: "prefix" }.fail()
+        }
+        Some(prefix_str) => format!("settings.{}", prefix_str),
+        None => "settings.".to_string(),
+    };
+    let timeout = match query.get("timeout") {
+        Some(timeout_str) => {
+            let secs = timeout_str
+                .parse()
+                .context(error::InvalidNumber { input: "timeout" })?;
+            Duration::from_secs(secs).min(MAX_WATCH_TIMEOUT)
+        }
+        None => DEFAULT_WATCH_TIMEOUT,
+    };
+    let since = match query.get("since") {
+        Some(since_str) => since_str
+            .parse()
+            .context(error::InvalidNumber { input: "since" })?,
+        None => {
+            let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+            controller::latest_generation(&*datastore)?
+        }
+    };
+
+    let deadline = Instant::now() + timeout;
+    // The latest generation we've listed changes for.  Finding the latest generation is cheap,
+    // so we only read the history again when there's a new commit.
+    let mut checked = None;
+    loop {
+        // Don't hold the lock while we wait, or commits could never happen.
+        let latest = {
+            let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+            controller::latest_generation(&*datastore)?
+        };
+        if checked != Some(latest) {
+            let changes = {
+                let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+                controller::get_settings_changes(&*datastore, since, &prefix)?
+            };
+
+            // If the client is ahead of our history, for example because the data store was
+            // migrated, or we no longer have all the commits it's asking about, return right
+            // away so it can start over.
+            if !changes.commits.is_empty() || since > changes.latest || changes.truncated {
+                return Ok(SettingsChangesResponse(changes));
+            }
+            checked = Some(changes.latest);
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(SettingsChangesResponse(controller::SettingsChanges {
+                latest: checked.unwrap_or(latest),
+                truncated: false,
+                commits: Vec::new(),
+            }));
+        }
+        delay_for(WATCH_POLL_INTERVAL).await;
+    }
+}
+
 async fn get_transaction_list(data: web::Data<SharedDataStore>) -> Result<TransactionListResponse> {
     let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
     let data = controller::list_transactions(&*datastore)?;
@@ -219,13 +465,35 @@ async fn get_transaction(
     Ok(SettingsResponse(data))
 }
 
+/// Get what committing the given transaction would change in live data, or the "default"
+/// transaction if unspecified.
+async fn get_transaction_diff(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<TransactionDiffResponse> {
+    let transaction = transaction_name(&query);
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let diff = controller::get_transaction_diff(&*datastore, transaction)?;
+    Ok(TransactionDiffResponse(diff))
+}
+
 /// Delete the given transaction, or the "default" transaction if unspecified.
 async fn delete_transaction(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+
+    // Deleting discards every pending change in the transaction, so check all of them, as we do
+    // for commits.
+    let pending_keys = controller::get_transaction_keys(&*datastore, transaction)?;
+    let key_names = pending_keys.iter().map(|k| k.name());
+    audit::note(&req, Some(transaction), key_names.clone());
+    access.check_keys(peer_credentials(&req).as_ref(), key_names)?;
+
     let deleted = controller::delete_transaction(&mut *datastore, transaction)?;
     Ok(ChangedKeysResponse(deleted))
 }
@@ -233,26 +501,40 @@ async fn delete_transaction(
 /// Save settings changes from the given transaction, or the "default" transaction if unspecified,
 /// to the live data store.  Returns the list of changed keys.
 async fn commit_transaction(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
+    request_metrics: web::Data<RequestMetrics>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
+    // The transaction may include changes from other clients, so check all of them.
+    let pending_keys = controller::get_transaction_keys(&*datastore, transaction)?;
+    let key_names = pending_keys.iter().map(|k| k.name());
+    audit::note(&req, Some(transaction), key_names.clone());
+    access.check_keys(peer_credentials(&req).as_ref(), key_names)?;
+
     let changes = controller::commit_transaction(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
+    request_metrics.record_commit();
 
     Ok(ChangedKeysResponse(changes))
 }
 
 /// Starts settings appliers for any changes that have been committed to the data store.  This
 /// updates config files, runs restart commands, etc.
-async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+async fn apply_changes(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
     if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
+        audit::note(&req, None, &keys);
         controller::apply_changes(Some(&keys))?;
     } else {
         controller::apply_changes(None as Option<&HashSet<&str>>)?;
@@ -265,17 +547,27 @@ async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<Htt
 /// perform both a commit and an apply.  Commits the given transaction, or the "default"
 /// transaction if unspecified.
 async fn commit_transaction_and_apply(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
+    request_metrics: web::Data<RequestMetrics>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
 
+    // The transaction may include changes from other clients, so check all of them.
+    let pending_keys = controller::get_transaction_keys(&*datastore, transaction)?;
+    let key_names = pending_keys.iter().map(|k| k.name());
+    audit::note(&req, Some(transaction), key_names.clone());
+    access.check_keys(peer_credentials(&req).as_ref(), key_names)?;
+
     let changes = controller::commit_transaction(&mut *datastore, transaction)?;
 
     if changes.is_empty() {
         return error::CommitWithNoPending.fail();
     }
+    request_metrics.record_commit();
 
     let key_names = changes.iter().map(|k| k.name()).collect();
     controller::apply_changes(Some(&key_names))?;
@@ -283,10 +575,94 @@ async fn commit_transaction_and_apply(
     Ok(ChangedKeysResponse(changes))
 }
 
+/// Returns the recorded history of commits to live data, oldest first.
+async fn get_history(data: web::Data<SharedDataStore>) -> Result<HistoryResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let generations = controller::list_generations(&*datastore)?;
+    Ok(HistoryResponse(generations))
+}
+
+/// Returns a single generation from the history of commits to live data.
+async fn get_generation(
+    id: web::Path<u64>,
+    data: web::Data<SharedDataStore>,
+) -> Result<GenerationResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let generation = controller::get_generation(&*datastore, *id)?;
+    Ok(GenerationResponse(generation))
+}
+
+/// Reverts live settings to their state as of the given generation, and applies the changes.
+/// Returns the list of changed keys.
+async fn rollback(
+    req: HttpRequest,
+    id: web::Path<u64>,
+    data: web::Data<SharedDataStore>,
+    access: web::Data<AccessControl>,
+) -> Result<ChangedKeysResponse> {
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+
+    // Rolling back may change any key changed since the generation.
+    let keys = controller::get_keys_changed_since(&*datastore, *id)?;
+    audit::note(&req, None, &keys);
+    access.check_keys(peer_credentials(&req).as_ref(), &keys)?;
+
+    let changes = controller::rollback_to_generation(&mut *datastore, *id)?;
+
+    // Live data may already match the requested generation, in which case there's nothing to do.
+    if !changes.is_empty() {
+        let key_names = changes.iter().map(|k| k.name()).collect();
+        controller::apply_changes(Some(&key_names))?;
+    }
+
+    Ok(ChangedKeysResponse(changes))
+}
+
 async fn get_os_info() -> Result<BottlerocketReleaseResponse> {
     Ok(BottlerocketReleaseResponse(controller::get_os_info()?))
 }
 
+/// Returns the schema of the settings of the current variant.
+async fn get_schema() -> Result<SchemaResponse> {
+    Ok(SchemaResponse(Settings::json_schema()))
+}
+
+/// Responds to requests that don't match any route.
+async fn unmatched(req: HttpRequest) -> HttpResponse {
+    req.extensions_mut().insert(metrics::Unmatched);
+    HttpResponse::NotFound().finish()
+}
+
+/// Returns metrics about requests, the data store, and updates, in the Prometheus text format.
+async fn get_metrics(
+    data: web::Data<SharedDataStore>,
+    request_metrics: web::Data<RequestMetrics>,
+) -> Result<HttpResponse> {
+    let datastore_metrics = {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::get_datastore_metrics(&*datastore)?
+    };
+    // The update status isn't always available, for example while an update command is running;
+    // we still want to report everything else.
+    let update_status = load_update_status().ok();
+
+    let mut text = MetricsText::default();
+    request_metrics.render(&mut text);
+    datastore_metrics.render(&mut text);
+    metrics::render_update_status(update_status.as_ref(), &mut text);
+
+    Ok(HttpResponse::Ok()
+        .content_type(metrics::CONTENT_TYPE)
+        .body(text.to_string()))
+}
+
+/// Returns the OpenAPI description of the API for the current variant.
+async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/yaml")
+        .body(OPENAPI_DOCUMENT)
+}
+
 /// Get the affected services for a list of data keys
 async fn get_affected_services(
     query: web::Query<HashMap<String, String>>,
@@ -363,18 +739,7 @@ async fn get_configuration_files(
 
 /// Get the update status from 'thar-be-updates'
 async fn get_update_status() -> Result<UpdateStatusResponse> {
-    let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpen)?;
-    lockfile.try_lock_shared().context(error::UpdateShareLock)?;
-    let result = thar_be_updates::status::get_update_status(&lockfile);
-    match result {
-        Ok(update_status) => Ok(UpdateStatusResponse(update_status)),
-        Err(e) => match e {
-            thar_be_updates::error::Error::NoStatusFile { .. } => {
-                error::UninitializedUpdateStatus.fail()
-            }
-            _ => error::UpdateError.fail(),
-        },
-    }
+    Ok(UpdateStatusResponse(load_update_status()?))
 }
 
 /// Refreshes the list of updates and checks if an update is available matching the configured version lock
@@ -382,14 +747,17 @@ async fn refresh_updates() -> Result<HttpResponse> {
     controller::dispatch_update_command(&["refresh"])
 }
 
-/// Prepares update by downloading the images to the staging partition set
-async fn prepare_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["prepare"])
+/// Prepares update by downloading the images to the staging partition set.  Outside of the update
+/// maintenance windows, this fails unless the request has "ignore-maintenance-windows=true".
+async fn prepare_update(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&update_command_args("prepare", &query))
 }
 
-/// "Activates" an already staged update by bumping the priority bits on the staging partition set
-async fn activate_update() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["activate"])
+/// "Activates" an already staged update by bumping the priority bits on the staging partition set.
+/// Outside of the update maintenance windows, this fails unless the request has
+/// "ignore-maintenance-windows=true".
+async fn activate_update(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    controller::dispatch_update_command(&update_command_args("activate", &query))
 }
 
 /// "Deactivates" an already activated update by rolling back actions done by 'activate-update'
@@ -397,8 +765,16 @@ async fn deactivate_update() -> Result<HttpResponse> {
     controller::dispatch_update_command(&["deactivate"])
 }
 
-/// Reboots the machine
-async fn reboot() -> Result<HttpResponse> {
+/// Reboots the machine, as long as we're inside one of the update maintenance windows, or the
+/// request says to ignore them with "ignore-maintenance-windows=true".
+async fn reboot(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    if !ignore_maintenance_windows(&query) {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::check_maintenance_windows(&*datastore, Utc::now())?;
+    }
     debug!("Rebooting now");
     let output = Command::new("/sbin/shutdown")
         .arg("-r")
@@ -422,6 +798,46 @@ async fn reboot() -> Result<HttpResponse> {
 
 // Helpers for handler methods called by the router
 
+/// Returns whether the request says to ignore the update maintenance windows.
+fn ignore_maintenance_windows(query: &HashMap<String, String>) -> bool {
+    query.get("ignore-maintenance-windows").map(String::as_str) == Some("true")
+}
+
+/// Returns the thar-be-updates arguments for the given command, passing along the request's
+/// choice to ignore the update maintenance windows.
+fn update_command_args<'a>(command: &'a str, query: &HashMap<String, String>) -> Vec<&'a str> {
+    let mut args = vec![command];
+    if ignore_maintenance_windows(query) {
+        args.push("--ignore-maintenance-windows");
+    }
+    args
+}
+
+/// Returns the credentials of the client that made the request, if we know them.
+fn peer_credentials<M: HttpMessage>(req: &M) -> Option<PeerCredentials> {
+    // The on_connect callback stores an Option, since credentials aren't always available.
+    req.extensions()
+        .get::<Option<PeerCredentials>>()
+        .copied()
+        .flatten()
+}
+
+/// Loads the update status written by thar-be-updates.
+fn load_update_status() -> Result<UpdateStatus> {
+    let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpen)?;
+    lockfile.try_lock_shared().context(error::UpdateShareLock)?;
+    let result = thar_be_updates::status::get_update_status(&lockfile);
+    match result {
+        Ok(update_status) => Ok(update_status),
+        Err(e) => match e {
+            thar_be_updates::error::Error::NoStatusFile { .. } => {
+                error::UninitializedUpdateStatus.fail()
+            }
+            _ => error::UpdateError.fail(),
+        },
+    }
+}
+
 fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet<&'a str>> {
     if input.is_empty() {
         return error::EmptyInput { input: key_name }.fail();
@@ -446,10 +862,16 @@ impl ResponseError for error::Error {
             // 400 Bad Request
             MissingInput { .. } => HttpResponse::BadRequest(),
             EmptyInput { .. } => HttpResponse::BadRequest(),
+            InvalidNumber { .. } => HttpResponse::BadRequest(),
             NewKey { .. } => HttpResponse::BadRequest(),
+            DeleteNonSetting { .. } => HttpResponse::BadRequest(),
+
+            // 403 Forbidden
+            Forbidden { .. } => HttpResponse::Forbidden(),
 
             // 404 Not Found
             MissingData { .. } => HttpResponse::NotFound(),
+            MissingGeneration { .. } => HttpResponse::NotFound(),
             ListKeys { .. } => HttpResponse::NotFound(),
             UpdateDoesNotExist { .. } => HttpResponse::NotFound(),
             NoStagedImage { .. } => HttpResponse::NotFound(),
@@ -464,11 +886,19 @@ impl ResponseError for error::Error {
 
             // 409 Conflict
             DisallowCommand { .. } => HttpResponse::Conflict(),
+            OutsideMaintenanceWindow { .. } => HttpResponse::Conflict(),
+            RebootOutsideMaintenanceWindow { .. } => HttpResponse::Conflict(),
 
             // 500 Internal Server Error
             DataStoreLock => HttpResponse::InternalServerError(),
             ResponseSerialization { .. } => HttpResponse::InternalServerError(),
             BindSocket { .. } => HttpResponse::InternalServerError(),
+            AccessPolicyRead { .. } => HttpResponse::InternalServerError(),
+            AccessPolicyParse { .. } => HttpResponse::InternalServerError(),
+            AuditLogOpen { .. } => HttpResponse::InternalServerError(),
+            AuditLogWrite { .. } => HttpResponse::InternalServerError(),
+            AuditEntrySerialization { .. } => HttpResponse::InternalServerError(),
+            AuditLogLock => HttpResponse::InternalServerError(),
             ServerStart { .. } => HttpResponse::InternalServerError(),
             ListedKeyNotPresent { .. } => HttpResponse::InternalServerError(),
             DataStore { .. } => HttpResponse::InternalServerError(),
@@ -491,6 +921,7 @@ impl ResponseError for error::Error {
             UpdateStatusParse { .. } => HttpResponse::InternalServerError(),
             UpdateInfoParse { .. } => HttpResponse::InternalServerError(),
             UpdateLockOpen { .. } => HttpResponse::InternalServerError(),
+            MaintenanceWindow { .. } => HttpResponse::InternalServerError(),
         }
         // Include the error message in the response, and for all error types.  The Bottlerocket
         // API is only exposed locally, and only on the host filesystem and to authorized
@@ -561,3 +992,21 @@ impl_responder_for!(ChangedKeysResponse, self, self.0);
 
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
+
+struct TransactionDiffResponse(controller::TransactionDiff);
+impl_responder_for!(TransactionDiffResponse, self, self.0);
+
+struct HistoryResponse(Vec<Generation>);
+impl_responder_for!(HistoryResponse, self, self.0);
+
+struct GenerationResponse(Generation);
+impl_responder_for!(GenerationResponse, self, self.0);
+
+struct SettingsChangesResponse(controller::SettingsChanges);
+impl_responder_for!(SettingsChangesResponse, self, self.0);
+
+struct SettingsValidationResponse(controller::SettingsValidation);
+impl_responder_for!(SettingsValidationResponse, self, self.0);
+
+struct SchemaResponse(model::schema::Value);
+impl_responder_for!(SchemaResponse, self, self.0);
@@ -0,0 +1,637 @@
+/*!
+# Introduction
+
+healthdog decides whether a boot was successful.
+
+After an update, the host boots into the new partition set, which isn't yet marked as successfully booted.
+healthdog runs once the host reaches `multi-user.target` and checks that the new boot is healthy:
+
+* the API server responds,
+* every systemd unit listed in `settings.updates.boot-health.required-units` is active, and
+* if `settings.updates.boot-health.check-command` is set, it exits successfully when run in the host container named by `settings.updates.boot-health.check-container`.
+
+The checks are retried until they all pass, or until `settings.updates.boot-health.timeout-seconds` have passed since healthdog started.
+healthdog reads the timeout straight from the data store, so it also limits how long to wait for the API server.
+A check that's still running at that point is killed and counts as failed, so a hung check can't hold up the boot.
+If they pass, healthdog marks the boot successful, like `signpost mark-successful-boot`.
+Otherwise, it records why in `/var/lib/thar-be-updates/boot-health-failure.json`, rolls back to the previous partition set, and reboots.
+thar-be-updates reports the recorded failure in the update status after the rollback.
+
+Mistakes in the boot health settings, like a `check-command` without a `check-container`, or a `timeout-seconds` of 0, don't say anything about the new boot, so healthdog logs them and marks the boot successful rather than rolling back.
+
+If the active partition set was already marked successful, there's nothing to decide, and healthdog exits without checking.
+If there's no previous partition set to roll back to, healthdog marks the boot successful anyway, so that the host can still boot.
+*/
+
+#![deny(rust_2018_idioms)]
+
+#[macro_use]
+extern crate log;
+
+use apiserver::datastore::{self, Committed, DataStore, FilesystemDataStore, Key, KeyType};
+use bottlerocket_release::BottlerocketRelease;
+use signpost::State;
+use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
+use snafu::{ensure, ResultExt};
+use std::env;
+use std::io::{self, Read};
+use std::process::{self, Command, Output, Stdio};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use thar_be_updates::status::BootHealthFailure;
+
+// FIXME Get from configuration in the future
+const DEFAULT_API_SOCKET: &str = "/run/api.sock";
+const API_SETTINGS_URI: &str = "/settings";
+const DEFAULT_DATASTORE_PATH: &str = "/var/lib/bottlerocket/datastore/current";
+const TIMEOUT_KEY: &str = "settings.updates.boot-health.timeout-seconds";
+
+const SYSTEMCTL_BIN: &str = "/bin/systemctl";
+const CTR_BIN: &str = "/usr/bin/ctr";
+const HOST_CONTAINERD_SOCKET: &str = "/run/host-containerd/containerd.sock";
+const HOST_CONTAINERD_NAMESPACE: &str = "default";
+
+/// How long to wait for checks to pass if we can't read the timeout from the data store.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long to wait between attempts at the checks.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// How often to see whether a check's process has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+mod error {
+    use http::StatusCode;
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error sending {} to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Error {} when sending {} to {}: {}", code, method, uri, response_body))]
+        APIResponse {
+            method: String,
+            uri: String,
+            code: StatusCode,
+            response_body: String,
+        },
+
+        #[snafu(display("Error deserializing response as JSON from {}: {}", uri, source))]
+        ResponseJson {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read OS disk partition table: {}", source))]
+        PartitionTableRead { source: signpost::Error },
+
+        #[snafu(display("Failed to write OS disk partition table: {}", source))]
+        PartitionTableWrite { source: signpost::Error },
+
+        #[snafu(display("Unable to get OS version: {}", source))]
+        ReleaseVersion { source: bottlerocket_release::Error },
+
+        #[snafu(display("Failed to record boot health failure: {}", source))]
+        RecordFailure {
+            source: thar_be_updates::error::Error,
+        },
+
+        #[snafu(display("Failed to clear boot health failure: {}", source))]
+        ClearFailure {
+            source: thar_be_updates::error::Error,
+        },
+
+        #[snafu(display("Failed to reboot: {}", source))]
+        Reboot { source: std::io::Error },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: simplelog::TermLogError },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Retrieves the boot health settings from the API.
+fn get_boot_health_settings(socket_path: &str) -> Result<model::BootHealthSettings> {
+    let uri = API_SETTINGS_URI;
+    let method = "GET";
+    let (code, response_body) = apiclient::raw_request(socket_path, uri, method, None)
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { uri })?;
+    Ok(settings
+        .updates
+        .and_then(|updates| updates.boot_health)
+        .unwrap_or_default())
+}
+
+/// Runs the command and returns its output, like `Command::output`, unless it's still running at
+/// the deadline, in which case we kill it and return None.
+fn output_before(command: &mut Command, deadline: Instant) -> io::Result<Option<Output>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read the output as it comes, so the child doesn't block on a full pipe.
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(Output {
+                status,
+                stdout: finish_reading(stdout),
+                stderr: finish_reading(stderr),
+            }));
+        }
+        if Instant::now() >= deadline {
+            // The child may have exited since we checked, so failing to kill it is fine.  Anything
+            // it started could still hold its output open, so we don't wait for the readers.
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        // Output is only used to describe problems, so we keep whatever we could read.
+        let _ = reader.read_to_end(&mut output);
+        output
+    })
+}
+
+fn finish_reading(reader: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default()
+}
+
+/// Returns a description of the problem if the given systemd unit isn't active, or we couldn't
+/// tell by the deadline.
+fn check_unit(unit: &str, deadline: Instant) -> Option<String> {
+    match output_before(
+        Command::new(SYSTEMCTL_BIN).args(&["is-active", unit]),
+        deadline,
+    ) {
+        Ok(Some(output)) if output.status.success() => None,
+        Ok(Some(output)) => Some(format!(
+            "Unit {} is not active: {}",
+            unit,
+            String::from_utf8_lossy(&output.stdout).trim()
+        )),
+        Ok(None) => Some(format!("Timed out checking unit {}", unit)),
+        Err(e) => Some(format!("Failed to check unit {}: {}", unit, e)),
+    }
+}
+
+/// Returns a description of the problem if the check command fails in the given host container,
+/// or doesn't finish by the deadline.
+fn check_command(container: &str, command: &str, deadline: Instant) -> Option<String> {
+    let exec_id = format!("healthdog-{}", process::id());
+    match output_before(
+        Command::new(CTR_BIN)
+            .args(&["--address", HOST_CONTAINERD_SOCKET])
+            .args(&["--namespace", HOST_CONTAINERD_NAMESPACE])
+            .args(&["task", "exec", "--exec-id", &exec_id, container])
+            .args(&["sh", "-c", command]),
+        deadline,
+    ) {
+        Ok(Some(output)) if output.status.success() => None,
+        Ok(Some(output)) => Some(format!(
+            "Check command '{}' in host container {} failed ({}): {}",
+            command,
+            container,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Ok(None) => Some(format!(
+            "Check command '{}' in host container {} timed out",
+            command, container
+        )),
+        Err(e) => Some(format!(
+            "Failed to run check command in host container {}: {}",
+            container, e
+        )),
+    }
+}
+
+/// Reads the timeout for the checks straight from the data store, since we need it before the API
+/// server is ready.  Returns None if it's not set or we can't read it.
+fn read_timeout(datastore_path: &str) -> Option<u32> {
+    let datastore = FilesystemDataStore::new(datastore_path);
+    let key = Key::new(KeyType::Data, TIMEOUT_KEY).ok()?;
+    match datastore.get_key(&key, &Committed::Live) {
+        Ok(Some(raw)) => match datastore::deserialize_scalar::<u32, datastore::ScalarError>(&raw) {
+            Ok(timeout) => Some(timeout),
+            Err(e) => {
+                warn!("Invalid {} in data store: {}", TIMEOUT_KEY, e);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Failed to read {} from data store: {}", TIMEOUT_KEY, e);
+            None
+        }
+    }
+}
+
+/// Returns how long the checks may take, or a description of the problem if the setting is 0,
+/// which would fail the checks before they start.
+fn check_timeout(timeout_seconds: Option<u32>) -> std::result::Result<Duration, String> {
+    match timeout_seconds {
+        None => Ok(DEFAULT_TIMEOUT),
+        Some(0) => Err("timeout-seconds is 0, leaving no time for the checks".to_string()),
+        Some(secs) => Ok(Duration::from_secs(secs.into())),
+    }
+}
+
+/// The checks described by the boot health settings.
+#[derive(Debug, PartialEq)]
+struct HealthChecks {
+    required_units: Vec<String>,
+    // The host container and the command to run in it
+    command: Option<(String, String)>,
+}
+
+impl HealthChecks {
+    /// Returns the checks described by the settings, or a description of what's wrong with them.
+    fn from_settings(settings: &model::BootHealthSettings) -> std::result::Result<Self, String> {
+        let required_units = settings
+            .required_units
+            .iter()
+            .flatten()
+            .map(|unit| unit.to_string())
+            .collect();
+        let command = match (&settings.check_container, &settings.check_command) {
+            (Some(container), Some(command)) => Some((container.to_string(), command.to_string())),
+            (None, Some(_)) => {
+                return Err("check-command is set without a check-container to run it in".into())
+            }
+            _ => None,
+        };
+        Ok(Self {
+            required_units,
+            command,
+        })
+    }
+
+    /// Runs each check once, and returns the problems found.
+    fn run(&self, deadline: Instant) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .required_units
+            .iter()
+            .filter_map(|unit| check_unit(unit, deadline))
+            .collect();
+        if let Some((container, command)) = &self.command {
+            problems.extend(check_command(container, command, deadline));
+        }
+        problems
+    }
+}
+
+/// Calls `attempt` until it finds no problems or the deadline passes, and returns the problems
+/// found by the last attempt.
+fn retry_until<F>(deadline: Instant, retry_interval: Duration, mut attempt: F) -> Vec<String>
+where
+    F: FnMut() -> Vec<String>,
+{
+    loop {
+        let problems = attempt();
+        if problems.is_empty() || Instant::now() >= deadline {
+            return problems;
+        }
+        for problem in &problems {
+            debug!("Not healthy yet: {}", problem);
+        }
+        thread::sleep(retry_interval);
+    }
+}
+
+/// Health is what we found out about the boot.
+#[derive(Debug, PartialEq)]
+enum Health {
+    /// Every check passed.
+    Healthy,
+    /// These checks failed.
+    Unhealthy(Vec<String>),
+    /// The settings for the checks are wrong, so we couldn't check.
+    Misconfigured(String),
+}
+
+/// Decision is what to do about the boot.
+#[derive(Debug, PartialEq)]
+enum Decision {
+    MarkSuccessful,
+    /// Roll back to the previous partition set because of these problems.
+    RollBack(Vec<String>),
+}
+
+impl From<Health> for Decision {
+    /// A mistake in the settings says nothing about the new boot, so it's no reason to give up
+    /// on the update; we only roll back if checks failed.
+    fn from(health: Health) -> Self {
+        match health {
+            Health::Healthy => {
+                info!("Boot is healthy");
+                Decision::MarkSuccessful
+            }
+            Health::Misconfigured(problem) => {
+                error!("Can't check boot health: {}", problem);
+                Decision::MarkSuccessful
+            }
+            Health::Unhealthy(problems) => Decision::RollBack(problems),
+        }
+    }
+}
+
+/// Runs the health checks until they all pass or we time out.
+fn check_health(socket_path: &str, datastore_path: &str) -> Health {
+    let start = Instant::now();
+    let timeout = match check_timeout(read_timeout(datastore_path)) {
+        Ok(timeout) => timeout,
+        Err(problem) => return Health::Misconfigured(problem),
+    };
+    let deadline = start + timeout;
+
+    // Talking to the API server is our first check, and tells us what else to check.
+    let settings = loop {
+        match get_boot_health_settings(socket_path) {
+            Ok(settings) => break settings,
+            Err(e) if Instant::now() < deadline => {
+                debug!("API server not ready: {}", e);
+                thread::sleep(RETRY_INTERVAL);
+            }
+            Err(e) => return Health::Unhealthy(vec![format!("API server not responding: {}", e)]),
+        }
+    };
+    let checks = match HealthChecks::from_settings(&settings) {
+        Ok(checks) => checks,
+        Err(problem) => return Health::Misconfigured(problem),
+    };
+
+    let problems = retry_until(deadline, RETRY_INTERVAL, || checks.run(deadline));
+    if problems.is_empty() {
+        Health::Healthy
+    } else {
+        Health::Unhealthy(problems)
+    }
+}
+
+/// Store the args we receive on the command line
+struct Args {
+    log_level: LevelFilter,
+    socket_path: String,
+    datastore_path: String,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --datastore-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]",
+        program_name
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+    let mut datastore_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            "--datastore-path" => {
+                datastore_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --datastore-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        datastore_path: datastore_path.unwrap_or_else(|| DEFAULT_DATASTORE_PATH.to_string()),
+    }
+}
+
+fn run() -> Result<()> {
+    // Parse and store the args passed to the program
+    let args = parse_args(env::args());
+
+    // TerminalMode::Mixed will send errors to stderr and anything less to stdout.
+    TermLogger::init(args.log_level, LogConfig::default(), TerminalMode::Mixed)
+        .context(error::Logger)?;
+
+    let mut state = State::load().context(error::PartitionTableRead)?;
+    if state.active_successful() {
+        info!("Active partition set was already marked successful");
+        return Ok(());
+    }
+
+    let health = check_health(&args.socket_path, &args.datastore_path);
+    let problems = match Decision::from(health) {
+        Decision::MarkSuccessful => {
+            info!("Marking boot successful");
+            state.mark_successful_boot();
+            state.write().context(error::PartitionTableWrite)?;
+            // Any earlier failure is no longer the most recent news.
+            BootHealthFailure::clear().context(error::ClearFailure)?;
+            return Ok(());
+        }
+        Decision::RollBack(problems) => problems,
+    };
+
+    for problem in &problems {
+        error!("{}", problem);
+    }
+    let os_info = BottlerocketRelease::new().context(error::ReleaseVersion)?;
+    BootHealthFailure::new(os_info.version_id, problems)
+        .write()
+        .context(error::RecordFailure)?;
+
+    if let Err(e) = state.rollback_to_inactive() {
+        // Leaving the boot unmarked could leave us with no bootable partition set at all.
+        warn!("Can't roll back, marking boot successful anyway: {}", e);
+        state.mark_successful_boot();
+        state.write().context(error::PartitionTableWrite)?;
+        return Ok(());
+    }
+    state.write().context(error::PartitionTableWrite)?;
+
+    warn!("Boot failed health checks, rebooting into the previous partition set");
+    Command::new("shutdown")
+        .arg("-r")
+        .status()
+        .context(error::Reboot)?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn output_before_deadline() {
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let output = output_before(Command::new("echo").arg("hi"), deadline)
+            .unwrap()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hi\n");
+    }
+
+    #[test]
+    fn killed_at_deadline() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(200);
+        let output = output_before(Command::new("sleep").arg("60"), deadline).unwrap();
+        assert!(output.is_none());
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn timeout() {
+        assert_eq!(check_timeout(None), Ok(DEFAULT_TIMEOUT));
+        assert_eq!(check_timeout(Some(60)), Ok(Duration::from_secs(60)));
+        assert!(check_timeout(Some(0)).is_err());
+    }
+
+    fn settings(json: &str) -> model::BootHealthSettings {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn checks_from_settings() {
+        let checks = HealthChecks::from_settings(&settings(
+            r#"{"required-units": ["kubelet.service"], "check-container": "admin", "check-command": "true"}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            checks,
+            HealthChecks {
+                required_units: vec!["kubelet.service".to_string()],
+                command: Some(("admin".to_string(), "true".to_string())),
+            }
+        );
+
+        let checks = HealthChecks::from_settings(&settings("{}")).unwrap();
+        assert!(checks.required_units.is_empty());
+        assert!(checks.command.is_none());
+    }
+
+    #[test]
+    fn command_without_container() {
+        assert!(HealthChecks::from_settings(&settings(r#"{"check-command": "true"}"#)).is_err());
+    }
+
+    #[test]
+    fn retry_until_healthy() {
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut attempts = 0;
+        let problems = retry_until(deadline, Duration::from_millis(1), || {
+            attempts += 1;
+            if attempts < 3 {
+                vec!["not yet".to_string()]
+            } else {
+                Vec::new()
+            }
+        });
+        assert!(problems.is_empty());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn retry_until_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let problems = retry_until(deadline, Duration::from_millis(10), || {
+            vec!["broken".to_string()]
+        });
+        assert_eq!(problems, vec!["broken".to_string()]);
+    }
+
+    #[test]
+    fn decision() {
+        assert_eq!(Decision::from(Health::Healthy), Decision::MarkSuccessful);
+        assert_eq!(
+            Decision::from(Health::Misconfigured("bad settings".to_string())),
+            Decision::MarkSuccessful
+        );
+        assert_eq!(
+            Decision::from(Health::Unhealthy(vec!["broken".to_string()])),
+            Decision::RollBack(vec!["broken".to_string()])
+        );
+    }
+
+    #[test]
+    fn misconfigured_timeout_does_not_roll_back() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        let key = Key::new(KeyType::Data, TIMEOUT_KEY).unwrap();
+        datastore.set_key(&key, "0", &Committed::Live).unwrap();
+        assert_eq!(read_timeout(dir.path().to_str().unwrap()), Some(0));
+
+        // We don't wait for the API server if we know the settings are wrong.
+        let health = check_health("/nonexistent/api.sock", dir.path().to_str().unwrap());
+        assert!(matches!(health, Health::Misconfigured(_)));
+        assert_eq!(Decision::from(health), Decision::MarkSuccessful);
+    }
+}
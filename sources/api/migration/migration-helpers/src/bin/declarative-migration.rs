@@ -0,0 +1,27 @@
+//! declarative-migration runs the migration described in the TOML appended to its own binary.
+//! See the `declarative` module of migration-helpers for the format.
+
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::declarative::{embedded_description, DeclarativeMigration};
+use migration_helpers::{migrate, Result};
+use std::process;
+
+/// The running binary.  The migrator runs migrations from sealed memory files that have no path
+/// of their own, so we can't use `env::current_exe`.
+const SELF_EXE: &str = "/proc/self/exe";
+
+fn run() -> Result<()> {
+    let description = embedded_description(SELF_EXE)?;
+    migrate(DeclarativeMigration::from_toml(&description)?)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{error, Metadata, Migration, MigrationData, Result};
 use apiserver::datastore;
 use serde::Serialize;
-use snafu::{OptionExt, ResultExt};
+use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::HashMap;
 
 /// We use this migration when we add settings and want to make sure they're removed before we go
@@ -357,3 +357,267 @@ impl Migration for ReplaceTemplateMigration {
         Ok(input)
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// We use this migration when we move a setting, or a whole subtree of settings like a map, to a
+/// new location.  Values and metadata (like `affected-services` and `setting-generator`) keep
+/// their place relative to the moved setting, and because migrations also run on pending
+/// transactions, pending changes move too.  References to the moved settings in metadata values,
+/// like a template that renders `settings.updates.seed`, are updated to the new location.
+///
+/// The migration fails rather than overwrite anything already at the destination.
+pub struct MoveSettingsMigration {
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+impl MoveSettingsMigration {
+    /// Moves the data and metadata under `from` to `to`, failing if anything is already under
+    /// `to`.
+    fn move_settings(
+        &self,
+        input: &mut MigrationData,
+        from: &'static str,
+        to: &'static str,
+        direction: &str,
+    ) -> Result<()> {
+        ensure!(
+            !in_subtree(from, to) && !in_subtree(to, from),
+            error::MoveOverlap { from, to }
+        );
+        // Check for collisions before moving anything.
+        let collision = input
+            .data
+            .keys()
+            .chain(input.metadata.keys())
+            .find(|key| in_subtree(key, to));
+        if let Some(key) = collision {
+            return error::MoveCollision { from, to, key }.fail();
+        }
+
+        let moved_data = move_subtree(&mut input.data, from, to);
+        let moved_metadata = move_subtree(&mut input.metadata, from, to);
+        if moved_data.is_empty() && moved_metadata.is_empty() {
+            println!("Found no '{}' to move on {}", from, direction);
+        }
+        for (old, new) in moved_data {
+            println!("Moved '{}' to '{}' on {}", old, new, direction);
+        }
+        for (old, new) in moved_metadata {
+            println!("Moved metadata of '{}' to '{}' on {}", old, new, direction);
+        }
+
+        // Metadata like setting generators and templates can name settings, so those references
+        // have to follow the move, wherever they are.
+        for (data_key, metadata) in input.metadata.iter_mut() {
+            for (metadata_key, value) in metadata.iter_mut() {
+                if replace_value_references(value, from, to) {
+                    println!(
+                        "Updated references to '{}' in metadata '{}' of '{}' on {}",
+                        from, metadata_key, data_key, direction
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces references to `from` in the strings of a metadata value with references to `to`, and
+/// returns whether anything changed.
+fn replace_value_references(value: &mut serde_json::Value, from: &str, to: &str) -> bool {
+    match value {
+        serde_json::Value::String(text) => {
+            let replaced = replace_references(text, from, to);
+            let changed = replaced != *text;
+            *text = replaced;
+            changed
+        }
+        serde_json::Value::Array(values) => values.iter_mut().fold(false, |changed, value| {
+            replace_value_references(value, from, to) || changed
+        }),
+        serde_json::Value::Object(map) => map.values_mut().fold(false, |changed, value| {
+            replace_value_references(value, from, to) || changed
+        }),
+        _ => false,
+    }
+}
+
+/// Replaces references to the key `from`, or keys under it, in `text` with the same references
+/// under `to`.  Only whole key names match, so "settings.motd" isn't found in "settings.motdx" or
+/// "os.settings.motd".
+fn replace_references(text: &str, from: &str, to: &str) -> String {
+    let is_key_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let mut replaced = String::new();
+    let mut copied = 0;
+    for (start, _) in text.match_indices(from) {
+        let end = start + from.len();
+        let starts_key = text[..start]
+            .chars()
+            .next_back()
+            .map_or(true, |c| !is_key_char(c) && c != '.');
+        let ends_key = text[end..].chars().next().map_or(true, |c| !is_key_char(c));
+        if starts_key && ends_key {
+            replaced.push_str(&text[copied..start]);
+            replaced.push_str(to);
+            copied = end;
+        }
+    }
+    replaced.push_str(&text[copied..]);
+    replaced
+}
+
+/// Returns whether `key` is `root` or under it.
+fn in_subtree(key: &str, root: &str) -> bool {
+    key == root || (key.starts_with(root) && key[root.len()..].starts_with('.'))
+}
+
+/// Moves every entry of the map whose key is in the subtree at `from` to the same place under
+/// `to`, and returns the old and new keys of the moved entries.
+fn move_subtree<V>(map: &mut HashMap<String, V>, from: &str, to: &str) -> Vec<(String, String)> {
+    let old_keys: Vec<String> = map
+        .keys()
+        .filter(|key| in_subtree(key, from))
+        .cloned()
+        .collect();
+    let mut moved = Vec::new();
+    for old_key in old_keys {
+        if let Some(value) = map.remove(&old_key) {
+            let new_key = format!("{}{}", to, &old_key[from.len()..]);
+            map.insert(new_key.clone(), value);
+            moved.push((old_key, new_key));
+        }
+    }
+    moved.sort();
+    moved
+}
+
+impl Migration for MoveSettingsMigration {
+    fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        self.move_settings(&mut input, self.from, self.to, "upgrade")?;
+        Ok(input)
+    }
+
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        self.move_settings(&mut input, self.to, self.from, "downgrade")?;
+        Ok(input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn input() -> MigrationData {
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "settings.kubernetes.labels".to_string(),
+            vec![("affected-services".to_string(), json!(["kubernetes"]))]
+                .into_iter()
+                .collect(),
+        );
+        metadata.insert(
+            "settings.motd".to_string(),
+            vec![(
+                "templates".to_string(),
+                json!("{{settings.kubernetes.labels.team}} {{settings.kubernetes.labelsmith}}"),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        MigrationData {
+            data: vec![
+                ("settings.kubernetes.labels.team", json!("storage")),
+                ("settings.kubernetes.labels.tier", json!("backend")),
+                ("settings.kubernetes.labelsmith", json!("unrelated")),
+                ("settings.motd", json!("hi")),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+            metadata,
+        }
+    }
+
+    #[test]
+    fn move_settings() {
+        let mut migration = MoveSettingsMigration {
+            from: "settings.kubernetes.labels",
+            to: "settings.kubernetes.node-labels",
+        };
+        let moved = migration.forward(input()).unwrap();
+        let mut keys: Vec<_> = moved.data.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "settings.kubernetes.labelsmith",
+                "settings.kubernetes.node-labels.team",
+                "settings.kubernetes.node-labels.tier",
+                "settings.motd",
+            ]
+        );
+        assert_eq!(
+            moved.data["settings.kubernetes.node-labels.team"],
+            json!("storage")
+        );
+        assert!(moved
+            .metadata
+            .contains_key("settings.kubernetes.node-labels"));
+        assert_eq!(
+            moved.metadata["settings.motd"]["templates"],
+            json!("{{settings.kubernetes.node-labels.team}} {{settings.kubernetes.labelsmith}}")
+        );
+
+        let restored = migration.backward(moved).unwrap();
+        assert_eq!(restored.data, input().data);
+        assert_eq!(restored.metadata, input().metadata);
+    }
+
+    #[test]
+    fn move_collision() {
+        // Something is already at the destination, in data or metadata.
+        let mut migration = MoveSettingsMigration {
+            from: "settings.kubernetes.labels",
+            to: "settings.motd",
+        };
+        assert!(migration.forward(input()).is_err());
+        let mut input_metadata = input();
+        input_metadata.data.remove("settings.motd");
+        assert!(input_metadata.metadata.contains_key("settings.motd"));
+        assert!(migration.forward(input_metadata).is_err());
+
+        // Moving a subtree into itself isn't a move.
+        let mut migration = MoveSettingsMigration {
+            from: "settings.kubernetes",
+            to: "settings.kubernetes.old",
+        };
+        assert!(migration.forward(input()).is_err());
+    }
+
+    #[test]
+    fn references() {
+        let from = "settings.updates.seed";
+        let to = "settings.updates.wave-seed";
+        for (text, expected) in &[
+            (
+                "schnauzer settings.updates.seed",
+                "schnauzer settings.updates.wave-seed",
+            ),
+            (
+                "{{settings.updates.seed.x}}",
+                "{{settings.updates.wave-seed.x}}",
+            ),
+            ("{{settings.updates.seeds}}", "{{settings.updates.seeds}}"),
+            ("os.settings.updates.seed", "os.settings.updates.seed"),
+            (
+                "settings.updates.seed,settings.updates.seed",
+                "settings.updates.wave-seed,settings.updates.wave-seed",
+            ),
+        ] {
+            assert_eq!(replace_references(text, from, to), *expected);
+        }
+    }
+}
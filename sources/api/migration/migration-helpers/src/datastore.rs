@@ -37,15 +37,6 @@ pub(crate) fn get_input_data<D: DataStore>(
         data.insert(key_name.clone(), value);
     }
 
-    // We also want to make "os.*" values, like variant and arch, available to migrations.
-    let release = BottlerocketRelease::new().context(error::BottlerocketRelease)?;
-    let os_pairs = to_pairs_with_prefix("os", &release).context(error::SerializeRelease)?;
-    for (data_key, value_str) in os_pairs.into_iter() {
-        let value =
-            deserialize_scalar(&value_str).context(error::Deserialize { input: value_str })?;
-        data.insert(data_key.name().clone(), value);
-    }
-
     // Metadata isn't committed, it goes live immediately, so we only populate the metadata
     // output for Committed::Live.
     let mut metadata = HashMap::new();
@@ -71,6 +62,21 @@ pub(crate) fn get_input_data<D: DataStore>(
     Ok(MigrationData { data, metadata })
 }
 
+/// Adds the given release data, like variant and arch, to the migration data as "os.*" values so
+/// it's available to migrations.
+pub(crate) fn add_release_data(
+    input: &mut MigrationData,
+    release: &BottlerocketRelease,
+) -> Result<()> {
+    let os_pairs = to_pairs_with_prefix("os", release).context(error::SerializeRelease)?;
+    for (data_key, value_str) in os_pairs.into_iter() {
+        let value =
+            deserialize_scalar(&value_str).context(error::Deserialize { input: value_str })?;
+        input.data.insert(data_key.name().clone(), value);
+    }
+    Ok(())
+}
+
 // Similar to get_input_data, we use datastore methods here; please read the comment on
 // get_input_data.  This method is also private to the crate, so we can reconsider as needed.
 /// Updates the given data store with the given (migrated) data.
@@ -119,3 +125,26 @@ pub(crate) fn set_output_data<D: DataStore>(
 
     Ok(())
 }
+
+/// Copies the given transaction's pending removals from the source data store to the target.
+/// Removals aren't data, so migrations never see them; a removal of a key the migration renamed
+/// has no effect when committed, since the old key no longer exists in live data.
+pub(crate) fn copy_pending_deletions<D: DataStore>(
+    source: &D,
+    target: &mut D,
+    transaction: &str,
+) -> Result<()> {
+    let deletions = source
+        .list_pending_deletions("", transaction)
+        .with_context(|| error::GetData {
+            committed: Committed::Pending {
+                tx: transaction.to_string(),
+            },
+        })?;
+    for key in deletions {
+        target
+            .set_pending_deletion(&key, transaction)
+            .context(error::DataStoreWrite)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//! The declarative module runs migrations described in TOML rather than written in Rust.
+//!
+//! Most migrations only add, remove, rename, or replace settings, so rather than starting a new
+//! crate, a migration can be described as an ordered list of operations:
+//!
+//! ```toml
+//! [[operation]]
+//! type = "add"
+//! settings = ["settings.updates.version-lock", "settings.updates.ignore-waves"]
+//!
+//! [[operation]]
+//! type = "remove"
+//! settings = ["settings.kubernetes.old-setting"]
+//!
+//! [[operation]]
+//! type = "rename"
+//! from = "settings.updates.seed"
+//! to = "settings.updates.wave-seed"
+//!
+//! [[operation]]
+//! type = "move-subtree"
+//! from = "settings.kubernetes.node-labels"
+//! to = "settings.kubernetes.labels"
+//!
+//! [[operation]]
+//! type = "replace-string"
+//! setting = "settings.host-containers.admin.source"
+//! old-value = "328549459982.dkr.ecr.us-west-2.amazonaws.com/bottlerocket-admin:v0.5.0"
+//! new-value = "328549459982.dkr.ecr.us-west-2.amazonaws.com/bottlerocket-admin:v0.5.2"
+//!
+//! [[operation]]
+//! type = "replace-template"
+//! setting = "settings.updates.metadata-base-url"
+//! old-template = "https://updates.bottlerocket.aws/2020-02-02/{{ os.variant_id }}/{{ os.arch }}/"
+//! new-template = "https://updates.bottlerocket.aws/2020-07-07/{{ os.variant_id }}/{{ os.arch }}/"
+//! ```
+//!
+//! Each operation behaves like the common migration of the same name.  Forward migrations run the
+//! operations in order, and backward migrations undo them in reverse order.
+//!
+//! The `declarative-migration` binary runs a description that's appended to the binary itself, so
+//! the result is a single self-contained migration like any other.  The description is followed by
+//! a trailer: its length in bytes, as 16 lowercase hex digits, and then `DESCRIPTION_MAGIC`.
+//! rpm2migrations builds these when packaging migrations.
+
+use crate::common_migrations::{
+    AddSettingsMigration, MoveSettingsMigration, RemoveSettingsMigration, ReplaceStringMigration,
+    ReplaceTemplateMigration,
+};
+use crate::{error, Migration, MigrationData, Result};
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Marks the end of a description appended to the `declarative-migration` binary.
+pub const DESCRIPTION_MAGIC: &str = "bottlerocket-migration-description\n";
+
+/// The length of the hex-encoded description length that precedes `DESCRIPTION_MAGIC`.
+const DESCRIPTION_LENGTH_SIZE: usize = 16;
+
+/// Description is the TOML representation of a declarative migration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Description {
+    #[serde(rename = "operation", default)]
+    pub operations: Vec<Operation>,
+}
+
+/// Operation is a single step of a declarative migration.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Operation {
+    /// Settings added in the new version; see `AddSettingsMigration`.
+    Add { settings: Vec<String> },
+    /// Settings removed in the new version; see `RemoveSettingsMigration`.
+    Remove { settings: Vec<String> },
+    /// A single setting that has a new name; see `MoveSettingsMigration`.
+    Rename { from: String, to: String },
+    /// A setting and everything under it, which have a new location; see `MoveSettingsMigration`.
+    MoveSubtree { from: String, to: String },
+    /// See `ReplaceStringMigration`.
+    #[serde(rename_all = "kebab-case")]
+    ReplaceString {
+        setting: String,
+        old_value: String,
+        new_value: String,
+    },
+    /// See `ReplaceTemplateMigration`.
+    #[serde(rename_all = "kebab-case")]
+    ReplaceTemplate {
+        setting: String,
+        old_template: String,
+        new_template: String,
+    },
+}
+
+/// DeclarativeMigration runs the operations of a `Description`.
+pub struct DeclarativeMigration {
+    steps: Vec<Box<dyn Migration>>,
+}
+
+impl DeclarativeMigration {
+    /// Parses a description from a TOML string.
+    pub fn from_toml(description: &str) -> Result<Self> {
+        let description: Description =
+            toml::from_str(description).context(error::DescriptionParse)?;
+        Ok(Self::new(description))
+    }
+
+    pub fn new(description: Description) -> Self {
+        let steps = description
+            .operations
+            .into_iter()
+            .map(Operation::into_migration)
+            .collect();
+        Self { steps }
+    }
+}
+
+impl Migration for DeclarativeMigration {
+    fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        for step in self.steps.iter_mut() {
+            input = step.forward(input)?;
+        }
+        Ok(input)
+    }
+
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        for step in self.steps.iter_mut().rev() {
+            input = step.backward(input)?;
+        }
+        Ok(input)
+    }
+}
+
+impl Operation {
+    /// Returns the common migration that performs this operation.
+    fn into_migration(self) -> Box<dyn Migration> {
+        match self {
+            Operation::Add { settings } => Box::new(AddSettingsMigration(leak_all(settings))),
+            Operation::Remove { settings } => Box::new(RemoveSettingsMigration(leak_all(settings))),
+            Operation::Rename { from, to } | Operation::MoveSubtree { from, to } => {
+                Box::new(MoveSettingsMigration {
+                    from: leak(from),
+                    to: leak(to),
+                })
+            }
+            Operation::ReplaceString {
+                setting,
+                old_value,
+                new_value,
+            } => Box::new(ReplaceStringMigration {
+                setting: leak(setting),
+                old_val: leak(old_value),
+                new_val: leak(new_value),
+            }),
+            Operation::ReplaceTemplate {
+                setting,
+                old_template,
+                new_template,
+            } => Box::new(ReplaceTemplateMigration {
+                setting: leak(setting),
+                old_template: leak(old_template),
+                new_template: leak(new_template),
+            }),
+        }
+    }
+}
+
+// The common migrations are written for descriptions compiled into the binary, so they take
+// static strings.  A description lasts for the whole migration process, so we leak its strings
+// rather than copy the common migrations.
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+fn leak_all(strings: Vec<String>) -> &'static [&'static str] {
+    let strings: Vec<_> = strings.into_iter().map(leak).collect();
+    Box::leak(strings.into_boxed_slice())
+}
+
+/// Reads the description appended to the file at the given path, which is usually the running
+/// `declarative-migration` binary.
+pub fn embedded_description<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let mut file = File::open(path).context(error::DescriptionRead { path })?;
+
+    let trailer_size = (DESCRIPTION_LENGTH_SIZE + DESCRIPTION_MAGIC.len()) as u64;
+    let file_size = file
+        .metadata()
+        .context(error::DescriptionRead { path })?
+        .len();
+    ensure!(
+        file_size >= trailer_size,
+        error::DescriptionMissing { path }
+    );
+    let mut trailer = vec![0; trailer_size as usize];
+    file.seek(SeekFrom::Start(file_size - trailer_size))
+        .and_then(|_| file.read_exact(&mut trailer))
+        .context(error::DescriptionRead { path })?;
+    let (length, magic) = trailer.split_at(DESCRIPTION_LENGTH_SIZE);
+    ensure!(
+        magic == DESCRIPTION_MAGIC.as_bytes(),
+        error::DescriptionMissing { path }
+    );
+
+    let length = std::str::from_utf8(length)
+        .ok()
+        .and_then(|length| u64::from_str_radix(length, 16).ok())
+        .filter(|length| *length <= file_size - trailer_size);
+    let length = match length {
+        Some(length) => length,
+        None => return error::DescriptionMissing { path }.fail(),
+    };
+    let mut description = String::new();
+    file.seek(SeekFrom::Start(file_size - trailer_size - length))
+        .and_then(|_| file.take(length).read_to_string(&mut description))
+        .context(error::DescriptionRead { path })?;
+    Ok(description)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_harness::assert_round_trip;
+    use std::io::Write;
+
+    fn data_path(name: &str) -> String {
+        format!(
+            "{}/tests/data/declarative/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    #[test]
+    fn parse() {
+        let description = r#"
+            [[operation]]
+            type = "add"
+            settings = ["settings.a"]
+
+            [[operation]]
+            type = "replace-string"
+            setting = "settings.b"
+            old-value = "x"
+            new-value = "y"
+        "#;
+        let description: Description = toml::from_str(description).unwrap();
+        assert_eq!(
+            description.operations,
+            vec![
+                Operation::Add {
+                    settings: vec!["settings.a".to_string()]
+                },
+                Operation::ReplaceString {
+                    setting: "settings.b".to_string(),
+                    old_value: "x".to_string(),
+                    new_value: "y".to_string(),
+                },
+            ]
+        );
+
+        assert!(DeclarativeMigration::from_toml("[[operation]]\ntype = \"paint\"").is_err());
+        assert!(DeclarativeMigration::from_toml(
+            "[[operation]]\ntype = \"add\"\nsettings = []\nsetting = \"settings.a\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let description = std::fs::read_to_string(data_path("migration.toml")).unwrap();
+        assert_round_trip(
+            &mut DeclarativeMigration::from_toml(&description).unwrap(),
+            data_path("before.toml"),
+            data_path("after.toml"),
+        );
+    }
+
+    #[test]
+    fn embedded() {
+        let description = "[[operation]]\ntype = \"add\"\nsettings = [\"settings.a\"]\n";
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "\x7fELF binary{}{:016x}{}",
+            description,
+            description.len(),
+            DESCRIPTION_MAGIC
+        )
+        .unwrap();
+        assert_eq!(embedded_description(file.path()).unwrap(), description);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "\x7fELF binary").unwrap();
+        assert!(embedded_description(file.path()).is_err());
+
+        // A length longer than the file can't be right.
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{:016x}{}", 100, DESCRIPTION_MAGIC).unwrap();
+        assert!(embedded_description(file.path()).is_err());
+    }
+}
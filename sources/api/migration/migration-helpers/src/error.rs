@@ -1,6 +1,8 @@
 //! Contains the Error and Result types used by the migration helper functions and migrations.
 
 use snafu::Snafu;
+use std::io;
+use std::path::PathBuf;
 
 use apiserver::datastore;
 
@@ -46,6 +48,18 @@ pub enum Error {
     #[snafu(display("Migrated data failed validation: {}", msg))]
     Validation { msg: String },
 
+    #[snafu(display("Unable to load defaults: {}", source))]
+    LoadDefaults { source: storewolf::error::Error },
+
+    #[snafu(display("No defaults found for '{}'", path))]
+    MissingDefaults { path: String },
+
+    #[snafu(display("Unable to convert defaults for '{}': {}", path, source))]
+    ConvertDefaults {
+        path: String,
+        source: serde_json::Error,
+    },
+
     // Generic error variant for migration authors
     #[snafu(display("Migration returned error: {}", msg))]
     Migration { msg: String },
@@ -73,6 +87,53 @@ pub enum Error {
         source: handlebars::TemplateRenderError,
     },
 
+    #[snafu(display("Unable to read fixture '{}': {}", path.display(), source))]
+    FixtureRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid fixture '{}': {}", path.display(), source))]
+    Fixture {
+        path: PathBuf,
+        #[snafu(source(from(Error, Box::new)))]
+        source: Box<Error>,
+    },
+
+    #[snafu(display("Fixture is not valid TOML: {}", source))]
+    FixtureParse { source: toml::de::Error },
+
+    #[snafu(display("Unable to convert fixture data: {}", source))]
+    FixtureConvert { source: serde_json::Error },
+
+    #[snafu(display("Fixture has unexpected format: {}", msg))]
+    FixtureFormat { msg: String },
+
+    #[snafu(display("Fixture has invalid os table: {}", source))]
+    FixtureRelease { source: serde_json::Error },
+
+    #[snafu(display("Unable to create temporary directory for fixture: {}", source))]
+    FixtureTempDir { source: io::Error },
+
+    #[snafu(display("Unable to move '{}' to '{}' because '{}' already exists", from, to, key))]
+    MoveCollision {
+        from: &'static str,
+        to: &'static str,
+        key: String,
+    },
+
+    #[snafu(display("Unable to move '{}' to '{}', which overlap", from, to))]
+    MoveOverlap {
+        from: &'static str,
+        to: &'static str,
+    },
+
+    #[snafu(display("Unable to read migration description from '{}': {}", path.display(), source))]
+    DescriptionRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("No migration description found at the end of '{}'", path.display()))]
+    DescriptionMissing { path: PathBuf },
+
+    #[snafu(display("Invalid migration description: {}", source))]
+    DescriptionParse { source: toml::de::Error },
+
     #[snafu(display("'{}' is set to non-string value", setting))]
     NonStringSettingDataType { setting: &'static str },
 
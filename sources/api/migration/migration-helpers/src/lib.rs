@@ -14,18 +14,23 @@
 mod args;
 pub mod common_migrations;
 mod datastore;
+pub mod declarative;
 pub mod error;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod test_harness;
 
-use snafu::ResultExt;
+use bottlerocket_release::BottlerocketRelease;
+use snafu::{OptionExt, ResultExt};
 use std::collections::HashMap;
 use std::env;
 use std::fmt;
+use std::path::Path;
 
-use apiserver::datastore::{Committed, Value};
+use apiserver::datastore::{Committed, Key, KeyType, Value};
 pub use apiserver::datastore::{DataStore, FilesystemDataStore};
 
 use args::{parse_args, Args};
-use datastore::{get_input_data, set_output_data};
+use datastore::{add_release_data, copy_pending_deletions, get_input_data, set_output_data};
 pub use error::Result;
 
 /// The data store implementation currently in use.  Used by the simpler `migrate` interface; can
@@ -71,24 +76,57 @@ pub struct MigrationData {
 /// Returns the default settings for a given path so you can easily replace a given section of the
 /// datastore with new defaults.  For example, you could request "settings" to get all new default
 /// settings, or "settings.serviceX.subsection" to scope it down.
-pub fn defaults_for<S: AsRef<str>>(_path: S) -> Result<Value> {
-    unimplemented!()
-}
-
-/// Ensures we can use the migrated data in the new data store.  Can use this result to stop the
-/// migration process before saving any data.
-fn validate_migrated_data(_migrated: &MigrationData) -> Result<()> {
-    // No validations yet.
-    // You can check the migrated data and throw error::Validation if anything seems wrong.
-    Ok(())
+///
+/// The defaults are the same ones storewolf uses to populate the datastore, as of the release the
+/// migration was built for.
+pub fn defaults_for<S: AsRef<str>>(path: S) -> Result<Value> {
+    let path = path.as_ref();
+    let key = Key::new(KeyType::Data, path).context(error::InvalidKey {
+        key_type: KeyType::Data,
+        key: path,
+    })?;
+    let defaults = storewolf::defaults().context(error::LoadDefaults)?;
+
+    let mut value = &defaults;
+    for segment in key.segments() {
+        value = value
+            .get(segment)
+            .context(error::MissingDefaults { path })?;
+    }
+    serde_json::to_value(value).context(error::ConvertDefaults { path })
 }
 
 /// If you need a little more control over a migration than with migrate, or you're using this
 /// module as a library, you can call run_migration directly with the arguments that would
 /// normally be parsed from the migration binary's command line.
 pub fn run_migration(mut migration: impl Migration, args: &Args) -> Result<()> {
-    let source = DataStoreImplementation::new(&args.source_datastore);
-    let mut target = DataStoreImplementation::new(&args.target_datastore);
+    let release = BottlerocketRelease::new().context(error::BottlerocketRelease)?;
+    migrate_datastore(
+        &mut migration,
+        args.migration_type,
+        &args.source_datastore,
+        &args.target_datastore,
+        &release,
+    )
+}
+
+/// Runs the migration in the given direction on the data in the source data store, writing the
+/// migrated data to the target data store.  The given release data is made available to the
+/// migration as "os.*" keys.
+fn migrate_datastore<M, P1, P2>(
+    migration: &mut M,
+    migration_type: MigrationType,
+    source_datastore: P1,
+    target_datastore: P2,
+    release: &BottlerocketRelease,
+) -> Result<()>
+where
+    M: Migration + ?Sized,
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let source = DataStoreImplementation::new(source_datastore.as_ref());
+    let mut target = DataStoreImplementation::new(target_datastore.as_ref());
 
     // Run for live data and for each pending transaction
     let mut committeds = vec![Committed::Live];
@@ -96,17 +134,20 @@ pub fn run_migration(mut migration: impl Migration, args: &Args) -> Result<()> {
     committeds.extend(transactions.into_iter().map(|tx| Committed::Pending { tx }));
 
     for committed in committeds {
-        let input = get_input_data(&source, &committed)?;
+        let mut input = get_input_data(&source, &committed)?;
+        add_release_data(&mut input, release)?;
 
-        let mut migrated = input.clone();
-        migrated = match args.migration_type {
+        let mut migrated = input;
+        migrated = match migration_type {
             MigrationType::Forward => migration.forward(migrated),
             MigrationType::Backward => migration.backward(migrated),
         }?;
 
-        validate_migrated_data(&migrated)?;
-
         set_output_data(&mut target, &migrated, &committed)?;
+
+        if let Committed::Pending { tx } = &committed {
+            copy_pending_deletions(&source, &mut target, tx)?;
+        }
     }
     Ok(())
 }
@@ -135,3 +176,22 @@ pub fn migrate(migration: impl Migration) -> Result<()> {
     let args = parse_args(env::args())?;
     run_migration(migration, &args)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            defaults_for("settings.motd").unwrap(),
+            json!("Welcome to Bottlerocket!")
+        );
+        assert_eq!(
+            defaults_for("settings.updates").unwrap()["version-lock"],
+            json!("latest")
+        );
+        assert!(defaults_for("settings.no-such-setting").is_err());
+    }
+}
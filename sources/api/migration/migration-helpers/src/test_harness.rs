@@ -0,0 +1,404 @@
+//! The test_harness module lets migrations test themselves against fixture data stores.  It's
+//! only built with the `test-harness` feature, which migrations enable in their dev-dependencies:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! migration-helpers = { path = "../../../migration-helpers", features = ["test-harness"] }
+//! ```
+//!
+//! A fixture is a TOML file describing the contents of a data store: live data, pending
+//! transactions, and metadata.  Data keys are nested tables, as in defaults.toml, and each
+//! scalar or array is the value of the key that leads to it:
+//!
+//! ```toml
+//! [live.settings]
+//! motd = "hello"
+//!
+//! [live.settings.updates]
+//! seed = 123
+//!
+//! [pending.bottlerocket-launch.settings]
+//! motd = "goodbye"
+//!
+//! # The last segment of each metadata key is the metadata name.
+//! [metadata.settings.motd]
+//! affected-services = ["motd"]
+//!
+//! # Optional; overrides the release data that migrations see as "os.*" values.
+//! [os]
+//! arch = "aarch64"
+//! ```
+//!
+//! A test builds a data store from one fixture, runs the migration on it as the migrator would,
+//! and checks the resulting data store against another fixture:
+//!
+//! ```no_run
+//! # use migration_helpers::common_migrations::AddSettingsMigration;
+//! use migration_helpers::test_harness::assert_round_trip;
+//!
+//! assert_round_trip(
+//!     &mut AddSettingsMigration(&["settings.updates.seed"]),
+//!     concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/before.toml"),
+//!     concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/after.toml"),
+//! );
+//! ```
+
+use crate::datastore::{get_input_data, set_output_data};
+use crate::{error, migrate_datastore, Metadata, Migration, MigrationData, MigrationType, Result};
+use apiserver::datastore::{Committed, DataStore, FilesystemDataStore, Key, KeyType, Value};
+use bottlerocket_release::BottlerocketRelease;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Fixture describes the contents of a data store.  Maps are ordered so that differences are easy
+/// to spot in failed assertions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fixture {
+    /// Mapping of live data key names to their values.
+    pub live: BTreeMap<String, Value>,
+    /// Mapping of pending transaction names to their data.
+    pub pending: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Mapping of data key names to their metadata.
+    pub metadata: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Release data that overrides the test release data given to migrations.
+    pub os: BTreeMap<String, Value>,
+}
+
+impl Fixture {
+    /// Loads a fixture from the given TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fixture_str = fs::read_to_string(path).context(error::FixtureRead { path })?;
+        Self::from_toml(&fixture_str).context(error::Fixture { path })
+    }
+
+    /// Parses a fixture from a TOML string.
+    pub fn from_toml(fixture_str: &str) -> Result<Self> {
+        let fixture: toml::Value = toml::from_str(fixture_str).context(error::FixtureParse)?;
+        // Convert to the Value type used by migrations, so leaf values compare as they would in
+        // migration data.
+        let fixture = serde_json::to_value(fixture).context(error::FixtureConvert)?;
+        let mut table = match fixture {
+            Value::Object(table) => table,
+            _ => unreachable!("TOML documents are tables"),
+        };
+
+        let mut result = Self::default();
+        if let Some(live) = table.remove("live") {
+            result.live = data_pairs(flatten(live))?;
+        }
+        if let Some(pending) = table.remove("pending") {
+            for (tx, data) in expect_table(pending, "pending")? {
+                result.pending.insert(tx, data_pairs(flatten(data))?);
+            }
+        }
+        if let Some(metadata) = table.remove("metadata") {
+            for (mut segments, value) in flatten(metadata) {
+                let metadata_key = segments.pop().context(error::FixtureFormat {
+                    msg: "metadata must be a table",
+                })?;
+                let data_key = key_name(KeyType::Data, &segments)?;
+                let metadata_key = key_name(KeyType::Meta, &[metadata_key])?;
+                result
+                    .metadata
+                    .entry(data_key)
+                    .or_default()
+                    .insert(metadata_key, value);
+            }
+        }
+        if let Some(os) = table.remove("os") {
+            result.os = expect_table(os, "os")?.into_iter().collect();
+        }
+        if let Some(unknown) = table.keys().next() {
+            return error::FixtureFormat {
+                msg: format!(
+                    "unknown table '{}'; expected live, pending, metadata, or os",
+                    unknown
+                ),
+            }
+            .fail();
+        }
+        Ok(result)
+    }
+
+    /// Writes the fixture's data and metadata to a new data store at the given path.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut datastore = FilesystemDataStore::new(path.as_ref());
+        let live = MigrationData {
+            data: self.live.clone().into_iter().collect(),
+            metadata: self
+                .metadata
+                .iter()
+                .map(|(key, metadata)| (key.clone(), metadata.clone().into_iter().collect()))
+                .collect(),
+        };
+        set_output_data(&mut datastore, &live, &Committed::Live)?;
+        for (tx, data) in &self.pending {
+            let pending = MigrationData {
+                data: data.clone().into_iter().collect(),
+                metadata: HashMap::new(),
+            };
+            set_output_data(
+                &mut datastore,
+                &pending,
+                &Committed::Pending { tx: tx.clone() },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads the data and metadata of the data store at the given path.  "os.*" values come from
+    /// the release given to the migration rather than from the data store, so they're left out.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let datastore = FilesystemDataStore::new(path.as_ref());
+        let without_os = |data: HashMap<String, Value>| -> BTreeMap<String, Value> {
+            data.into_iter()
+                .filter(|(key, _)| !key.starts_with("os."))
+                .collect()
+        };
+
+        let live = get_input_data(&datastore, &Committed::Live)?;
+        let mut result = Self {
+            live: without_os(live.data),
+            metadata: live
+                .metadata
+                .into_iter()
+                .map(|(key, metadata): (String, Metadata)| (key, metadata.into_iter().collect()))
+                .collect(),
+            ..Self::default()
+        };
+        for tx in datastore
+            .list_transactions()
+            .context(error::ListTransactions)?
+        {
+            let pending = get_input_data(&datastore, &Committed::Pending { tx: tx.clone() })?;
+            result.pending.insert(tx, without_os(pending.data));
+        }
+        Ok(result)
+    }
+
+    /// Returns the release data given to migrations: a test release for the current variant, with
+    /// any values from the fixture's `os` table.
+    fn release(&self) -> Result<BottlerocketRelease> {
+        let mut release = serde_json::json!({
+            "pretty_name": "Bottlerocket OS 0.0.0",
+            "variant_id": option_env!("VARIANT").unwrap_or("aws-k8s-1.17"),
+            "version_id": "0.0.0",
+            "build_id": "test",
+            "arch": std::env::consts::ARCH,
+        });
+        for (key, value) in &self.os {
+            release[key] = value.clone();
+        }
+        serde_json::from_value(release).context(error::FixtureRelease)
+    }
+}
+
+/// Runs the migration in the given direction on a data store built from the input fixture, as
+/// the migrator would, and returns the contents of the resulting data store.
+pub fn run_fixture<M>(
+    migration: &mut M,
+    migration_type: MigrationType,
+    input: &Fixture,
+) -> Result<Fixture>
+where
+    M: Migration + ?Sized,
+{
+    let dir = TempDir::new().context(error::FixtureTempDir)?;
+    let source = dir.path().join("source");
+    let target = dir.path().join("target");
+    input.write(&source)?;
+    migrate_datastore(
+        migration,
+        migration_type,
+        &source,
+        &target,
+        &input.release()?,
+    )?;
+    Fixture::read(&target)
+}
+
+/// Runs the migration in the given direction on the data store described by the fixture at
+/// `input`, and panics unless the result matches the fixture at `expected`.
+pub fn assert_migration<M, P1, P2>(
+    migration: &mut M,
+    migration_type: MigrationType,
+    input: P1,
+    expected: P2,
+) where
+    M: Migration + ?Sized,
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let input = Fixture::load(input).unwrap_or_else(|e| panic!("{}", e));
+    let mut expected = Fixture::load(expected).unwrap_or_else(|e| panic!("{}", e));
+    let output = run_fixture(migration, migration_type, &input).unwrap_or_else(|e| panic!("{}", e));
+    // Release data isn't part of the data store.
+    expected.os.clear();
+    assert_eq!(
+        output, expected,
+        "{} migration gave unexpected data",
+        migration_type
+    );
+}
+
+/// Runs the migration forward on the data store described by the fixture at `before`, checking
+/// that the result matches the fixture at `after`, then runs it backward on that result, checking
+/// that it matches `before` again.  Panics if either doesn't match.
+pub fn assert_round_trip<M, P1, P2>(migration: &mut M, before: P1, after: P2)
+where
+    M: Migration + ?Sized,
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let before = Fixture::load(before).unwrap_or_else(|e| panic!("{}", e));
+    let after = Fixture::load(after).unwrap_or_else(|e| panic!("{}", e));
+
+    let mut forward =
+        run_fixture(migration, MigrationType::Forward, &before).unwrap_or_else(|e| panic!("{}", e));
+    let mut expected = Fixture {
+        os: BTreeMap::new(),
+        ..after
+    };
+    assert_eq!(forward, expected, "forward migration gave unexpected data");
+
+    // Run backward with the same release data as forward.
+    forward.os = before.os.clone();
+    let backward = run_fixture(migration, MigrationType::Backward, &forward)
+        .unwrap_or_else(|e| panic!("{}", e));
+    expected = Fixture {
+        os: BTreeMap::new(),
+        ..before
+    };
+    assert_eq!(
+        backward, expected,
+        "backward migration gave unexpected data"
+    );
+}
+
+/// Returns the entries of a table in a fixture.
+fn expect_table(value: Value, name: &str) -> Result<serde_json::Map<String, Value>> {
+    match value {
+        Value::Object(table) => Ok(table),
+        _ => error::FixtureFormat {
+            msg: format!("{} must be a table", name),
+        }
+        .fail(),
+    }
+}
+
+/// Flattens nested tables into pairs of key segments and the scalar or array at the end of them.
+fn flatten(value: Value) -> Vec<(Vec<String>, Value)> {
+    let mut pairs = Vec::new();
+    let mut to_process = vec![(Vec::new(), value)];
+    while let Some((segments, value)) = to_process.pop() {
+        match value {
+            Value::Object(table) => {
+                for (segment, value) in table {
+                    let mut segments = segments.clone();
+                    segments.push(segment);
+                    to_process.push((segments, value));
+                }
+            }
+            value => pairs.push((segments, value)),
+        }
+    }
+    pairs
+}
+
+/// Turns flattened pairs into a mapping of data key names to values.
+fn data_pairs(pairs: Vec<(Vec<String>, Value)>) -> Result<BTreeMap<String, Value>> {
+    pairs
+        .into_iter()
+        .map(|(segments, value)| Ok((key_name(KeyType::Data, &segments)?, value)))
+        .collect()
+}
+
+/// Returns the name of the key made of the given segments, quoting any that need it.
+fn key_name(key_type: KeyType, segments: &[String]) -> Result<String> {
+    ensure!(
+        !segments.is_empty(),
+        error::FixtureFormat {
+            msg: "values must be inside a table"
+        }
+    );
+    let key = Key::from_segments(key_type, segments).context(error::InvalidKey {
+        key_type,
+        key: segments.join("."),
+    })?;
+    Ok(key.name().clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common_migrations::AddSettingsMigration;
+    use serde_json::json;
+
+    const BEFORE: &str = r#"
+[live.settings]
+motd = "hello"
+
+[pending.bottlerocket-launch.settings.host-containers.admin]
+enabled = true
+
+[metadata.settings.motd]
+affected-services = ["motd"]
+"#;
+
+    #[test]
+    fn parse() {
+        let fixture = Fixture::from_toml(BEFORE).unwrap();
+        assert_eq!(fixture.live["settings.motd"], json!("hello"));
+        assert_eq!(
+            fixture.pending["bottlerocket-launch"]["settings.host-containers.admin.enabled"],
+            json!(true)
+        );
+        assert_eq!(
+            fixture.metadata["settings.motd"]["affected-services"],
+            json!(["motd"])
+        );
+
+        assert!(Fixture::from_toml("[settings]\nmotd = \"hi\"").is_err());
+        assert!(Fixture::from_toml("[live]\nmotd = \"hi\"").is_ok());
+        assert!(Fixture::from_toml("[metadata]\nmotd = \"hi\"").is_err());
+    }
+
+    #[test]
+    fn write_and_read() {
+        let fixture = Fixture::from_toml(BEFORE).unwrap();
+        let dir = TempDir::new().unwrap();
+        fixture.write(dir.path()).unwrap();
+        assert_eq!(Fixture::read(dir.path()).unwrap(), fixture);
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::new().unwrap();
+        let before = dir.path().join("before.toml");
+        let after = dir.path().join("after.toml");
+        fs::write(&before, BEFORE).unwrap();
+        fs::write(&after, BEFORE).unwrap();
+        // Adding a setting that isn't there changes nothing in either direction.
+        assert_round_trip(
+            &mut AddSettingsMigration(&["settings.updates.seed"]),
+            &before,
+            &after,
+        );
+        // Removing it on the way back only affects data that has it.
+        let mut with_seed = Fixture::from_toml(BEFORE).unwrap();
+        with_seed
+            .live
+            .insert("settings.updates.seed".to_string(), json!(42));
+        let output = run_fixture(
+            &mut AddSettingsMigration(&["settings.updates.seed"]),
+            MigrationType::Backward,
+            &with_seed,
+        )
+        .unwrap();
+        assert_eq!(output, Fixture::from_toml(BEFORE).unwrap());
+    }
+}
@@ -5,11 +5,13 @@ use migration_helpers::{migrate, Result};
 use std::process;
 
 /// We added two new settings, `updates.version-lock` and `updates.ignore-waves`
+const MIGRATION: AddSettingsMigration<'static> = AddSettingsMigration(&[
+    "settings.updates.version-lock",
+    "settings.updates.ignore-waves",
+]);
+
 fn run() -> Result<()> {
-    migrate(AddSettingsMigration(&[
-        "settings.updates.version-lock",
-        "settings.updates.ignore-waves",
-    ]))
+    migrate(MIGRATION)
 }
 
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
@@ -21,3 +23,25 @@ fn main() {
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use migration_helpers::test_harness::assert_migration;
+    use migration_helpers::MigrationType;
+
+    const OLD_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/v0.4.0.toml");
+    const NEW_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/v0.4.1.toml");
+
+    #[test]
+    fn upgrade() {
+        let mut migration = MIGRATION;
+        assert_migration(&mut migration, MigrationType::Forward, OLD_DATA, OLD_DATA);
+    }
+
+    #[test]
+    fn downgrade() {
+        let mut migration = MIGRATION;
+        assert_migration(&mut migration, MigrationType::Backward, NEW_DATA, OLD_DATA);
+    }
+}
@@ -11,12 +11,14 @@ const AFTER_PIVOT_REPO_URL: &str =
 
 /// Starting with v0.4.1 we use a new set of repos that does not contain
 /// unsigned migrations
+const MIGRATION: ReplaceTemplateMigration = ReplaceTemplateMigration {
+    setting: "settings.updates.metadata-base-url",
+    old_template: BEFORE_PIVOT_REPO_URL,
+    new_template: AFTER_PIVOT_REPO_URL,
+};
+
 fn run() -> Result<()> {
-    migrate(ReplaceTemplateMigration {
-        setting: "settings.updates.metadata-base-url",
-        old_template: BEFORE_PIVOT_REPO_URL,
-        new_template: AFTER_PIVOT_REPO_URL,
-    })
+    migrate(MIGRATION)
 }
 
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
@@ -28,3 +30,35 @@ fn main() {
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use migration_helpers::test_harness::assert_round_trip;
+
+    #[test]
+    fn default_repo() {
+        let mut migration = MIGRATION;
+        assert_round_trip(
+            &mut migration,
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/before.toml"),
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/after.toml"),
+        );
+    }
+
+    #[test]
+    fn customized_repo() {
+        let mut migration = MIGRATION;
+        assert_round_trip(
+            &mut migration,
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/data/customized-before.toml"
+            ),
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/data/customized-after.toml"
+            ),
+        );
+    }
+}
@@ -19,6 +19,7 @@ fn usage() -> ! {
             --root-path PATH
             --metadata-directory PATH
             (--migrate-to-version x.y | --migrate-to-version-from-os-release)
+            [ --dry-run ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]",
         program_name
@@ -35,6 +36,7 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 /// Stores user-supplied arguments.
 pub(crate) struct Args {
     pub(crate) datastore_path: PathBuf,
+    pub(crate) dry_run: bool,
     pub(crate) log_level: LevelFilter,
     pub(crate) migration_directory: PathBuf,
     pub(crate) migrate_to_version: Version,
@@ -47,6 +49,7 @@ impl Args {
     pub(crate) fn from_env(args: env::Args) -> Self {
         // Required parameters.
         let mut datastore_path = None;
+        let mut dry_run = false;
         let mut log_level = None;
         let mut migration_directory = None;
         let mut migrate_to_version = None;
@@ -81,6 +84,8 @@ impl Args {
                     datastore_path = Some(canonical);
                 }
 
+                "--dry-run" => dry_run = true,
+
                 "--log-level" => {
                     let log_level_str = iter
                         .next()
@@ -138,6 +143,7 @@ impl Args {
         Self {
             datastore_path: datastore_path
                 .unwrap_or_else(|| usage_msg("--datastore-path must be specified")),
+            dry_run,
             log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
             migration_directory: migration_directory
                 .unwrap_or_else(|| usage_msg("--migration-directory must be specified")),
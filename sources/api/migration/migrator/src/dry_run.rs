@@ -0,0 +1,288 @@
+//! This module rehearses a migration without changing the data store.  It lists the migrations
+//! that would run, runs them against a throwaway copy of the data store, reports the changes
+//! they made, key by key, and checks the result against the model, just like a real run.
+
+use crate::direction::Direction;
+use crate::error::{self, Result};
+use crate::run_migrations;
+use crate::validate;
+use apiserver::datastore::{Committed, DataStore, FilesystemDataStore};
+use semver::Version;
+use snafu::{OptionExt, ResultExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Prints the migrations that would take the data store at `datastore_path` from
+/// `current_version` to `new_version`, runs them on a copy of the data store, and prints the
+/// resulting changes.  Fails if the real run would reject the migrated data store.
+pub(crate) fn dry_run<S>(
+    repository: &tough::Repository<'_, tough::FilesystemTransport>,
+    direction: Direction,
+    migrations: &[S],
+    datastore_path: &Path,
+    current_version: &Version,
+    new_version: &Version,
+) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    println!(
+        "Dry run of migrating data store at {} from {} to {}",
+        datastore_path.display(),
+        current_version,
+        new_version
+    );
+    if migrations.is_empty() {
+        println!("No migrations to run; the data store would be linked to the new version as-is");
+        return Ok(());
+    }
+    println!("Migrations to run, in order:");
+    for (i, migration) in migrations.iter().enumerate() {
+        println!("  {}. {} {}", i + 1, migration.as_ref(), direction);
+    }
+
+    // Each migration writes a new data store next to its source, so working from a copy in a
+    // temporary directory keeps all of them out of the real data store directory.
+    let copy_dir = TempDir::new().context(error::CreateDryRunTempDir)?;
+    let copy_name = datastore_path
+        .file_name()
+        .context(error::DataStoreLinkToRoot {
+            path: datastore_path,
+        })?;
+    let copy_path = copy_dir.path().join(copy_name);
+    copy_datastore(datastore_path, &copy_path)?;
+    let migrated_path = run_migrations(repository, direction, migrations, &copy_path, new_version)?;
+    report(datastore_path, &migrated_path)
+}
+
+/// Prints the changes from the data store at `datastore_path` to the migrated one at
+/// `migrated_path`, then validates the migrated data store as a real run would.
+fn report(datastore_path: &Path, migrated_path: &Path) -> Result<()> {
+    let changes = diff(
+        &datastore_contents(datastore_path)?,
+        &datastore_contents(migrated_path)?,
+    );
+    if changes.is_empty() {
+        println!("Migrations would not change the data store");
+    } else {
+        println!("Changes to the data store:");
+        for change in changes {
+            println!("  {}", change);
+        }
+    }
+
+    if let Err(e) = validate::validate_datastore(migrated_path) {
+        println!("Migrated data store would be rejected: {}", e);
+        return Err(e);
+    }
+    println!("Migrated data store is valid");
+    Ok(())
+}
+
+/// Recursively copies the data store directory at `from` to a new directory at `to`.
+fn copy_datastore(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir(to).context(error::CopyDataStore { path: to })?;
+    for entry in fs::read_dir(from).context(error::CopyDataStore { path: from })? {
+        let entry = entry.context(error::CopyDataStore { path: from })?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .context(error::CopyDataStore { path: &source })?;
+        if file_type.is_dir() {
+            copy_datastore(&source, &target)?;
+        } else {
+            fs::copy(&source, &target).context(error::CopyDataStore { path: &source })?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the data and metadata in the data store at the given path, mapping a description of
+/// each key to its serialized value.  Pending and metadata keys are described as suffixes of
+/// their data key, so related keys sort together.  Pending removals map to an empty value.
+fn datastore_contents(path: &Path) -> Result<BTreeMap<String, String>> {
+    let datastore = FilesystemDataStore::new(path);
+    let mut contents = BTreeMap::new();
+
+    let live = datastore
+        .get_prefix("", &Committed::Live)
+        .context(error::ReadDataStore { path })?;
+    for (key, value) in live {
+        contents.insert(key.name().to_string(), value);
+    }
+
+    let transactions = datastore
+        .list_transactions()
+        .context(error::ReadDataStore { path })?;
+    for tx in transactions {
+        let pending = datastore
+            .get_prefix("", &Committed::Pending { tx: tx.clone() })
+            .context(error::ReadDataStore { path })?;
+        for (key, value) in pending {
+            contents.insert(format!("{} (pending in {})", key.name(), tx), value);
+        }
+        let deletions = datastore
+            .list_pending_deletions("", &tx)
+            .context(error::ReadDataStore { path })?;
+        for key in deletions {
+            contents.insert(
+                format!("{} (pending removal in {})", key.name(), tx),
+                String::new(),
+            );
+        }
+    }
+
+    let metadata = datastore
+        .get_metadata_prefix("", &None as &Option<&str>)
+        .context(error::ReadDataStore { path })?;
+    for (data_key, metadata) in metadata {
+        for (metadata_key, value) in metadata {
+            contents.insert(
+                format!("{} (metadata {})", data_key.name(), metadata_key.name()),
+                value,
+            );
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Change is a difference in one key between two data stores.
+#[derive(Debug, PartialEq)]
+enum Change {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { key, value } => write!(f, "+ {} = {}", key, value),
+            Change::Removed { key, value } => write!(f, "- {} = {}", key, value),
+            Change::Changed { key, old, new } => write!(f, "~ {}: {} -> {}", key, old, new),
+        }
+    }
+}
+
+/// Returns the changes from `before` to `after`, in key order.
+fn diff(before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Vec<Change> {
+    let keys: BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| match (before.get(key), after.get(key)) {
+            (Some(old), Some(new)) if old == new => None,
+            (Some(old), Some(new)) => Some(Change::Changed {
+                key: key.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            (Some(value), None) => Some(Change::Removed {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (None, Some(value)) => Some(Change::Added {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use apiserver::datastore::{Key, KeyType};
+
+    fn set(datastore: &mut FilesystemDataStore, key: &str, value: &str, committed: &Committed) {
+        let key = Key::new(KeyType::Data, key).unwrap();
+        datastore.set_key(&key, value, committed).unwrap();
+    }
+
+    #[test]
+    fn datastore_diff() {
+        let dir = TempDir::new().unwrap();
+        let before_path = dir.path().join("before");
+        let mut before = FilesystemDataStore::new(&before_path);
+        let pending = Committed::Pending {
+            tx: "bottlerocket-launch".to_string(),
+        };
+        set(&mut before, "settings.motd", "\"hi\"", &Committed::Live);
+        set(&mut before, "settings.old", "1", &Committed::Live);
+        set(&mut before, "settings.same", "true", &Committed::Live);
+        set(&mut before, "settings.old", "2", &pending);
+        before
+            .set_metadata(
+                &Key::new(KeyType::Meta, "affected-services").unwrap(),
+                &Key::new(KeyType::Data, "settings.old").unwrap(),
+                "[\"old\"]",
+            )
+            .unwrap();
+
+        let after_path = dir.path().join("after");
+        copy_datastore(&before_path, &after_path).unwrap();
+        assert!(diff(
+            &datastore_contents(&before_path).unwrap(),
+            &datastore_contents(&after_path).unwrap()
+        )
+        .is_empty());
+
+        let mut after = FilesystemDataStore::new(&after_path);
+        set(&mut after, "settings.motd", "\"hello\"", &Committed::Live);
+        after
+            .unset_key(&Key::new(KeyType::Data, "settings.old").unwrap(), &pending)
+            .unwrap();
+        set(&mut after, "settings.new", "2", &pending);
+
+        let changes = diff(
+            &datastore_contents(&before_path).unwrap(),
+            &datastore_contents(&after_path).unwrap(),
+        );
+        let changes: Vec<_> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "~ settings.motd: \"hi\" -> \"hello\"",
+                "+ settings.new (pending in bottlerocket-launch) = 2",
+                "- settings.old (pending in bottlerocket-launch) = 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn report_validates() {
+        let dir = TempDir::new().unwrap();
+        let before_path = dir.path().join("before");
+        let mut before = FilesystemDataStore::new(&before_path);
+        set(&mut before, "settings.motd", "\"hi\"", &Committed::Live);
+
+        let after_path = dir.path().join("after");
+        copy_datastore(&before_path, &after_path).unwrap();
+        let mut after = FilesystemDataStore::new(&after_path);
+        set(&mut after, "settings.motd", "\"hello\"", &Committed::Live);
+        report(&before_path, &after_path).unwrap();
+
+        // A setting the model doesn't have is rejected by the real run, so the dry run fails too
+        set(
+            &mut after,
+            "settings.no-such-setting",
+            "1",
+            &Committed::Live,
+        );
+        assert!(report(&before_path, &after_path).is_err());
+    }
+}
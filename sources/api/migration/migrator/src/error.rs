@@ -16,6 +16,30 @@ pub(crate) enum Error {
     #[snafu(display("Unable to create tempdir for tough datastore: '{}'", source))]
     CreateToughTempDir { source: std::io::Error },
 
+    #[snafu(display("Unable to create tempdir for dry run: '{}'", source))]
+    CreateDryRunTempDir { source: std::io::Error },
+
+    #[snafu(display("Unable to copy data store at '{}' for dry run: {}", path.display(), source))]
+    CopyDataStore { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to read data store at '{}': {}", path.display(), source))]
+    ReadDataStore {
+        path: PathBuf,
+        source: apiserver::datastore::Error,
+    },
+
+    #[snafu(display(
+        "Migrated data store at '{}' doesn't match the model, in {}: {}",
+        path.display(),
+        given,
+        source
+    ))]
+    ValidateModel {
+        path: PathBuf,
+        given: String,
+        source: apiserver::datastore::deserialization::Error,
+    },
+
     #[snafu(display("Data store path '{}' contains invalid UTF-8", path.display()))]
     DataStorePathNotUTF8 { path: PathBuf },
 
@@ -11,10 +11,17 @@
 //! * find migrations between the two versions
 //! * if there are migrations:
 //!   * run the migrations; the transformed data becomes the new data store
+//!   * check that the new data store matches the model the migrator was built with
 //! * if there are *no* migrations:
 //!   * just symlink to the old data store
 //! * do symlink flips so the new version takes the place of the original
 //!
+//! With `--dry-run`, it instead lists the migrations it would run, runs them against a throwaway
+//! copy of the data store, and prints the resulting changes to each key, without touching the
+//! given data store.  It validates the migrated copy against the model like a real run does, and
+//! fails if the real run would.  This lets you rehearse a version change, including a downgrade,
+//! on a snapshot of a real data store.
+//!
 //! To understand motivation and more about the overall process, look at the migration system
 //! documentation, one level up.
 
@@ -44,9 +51,11 @@ use update_metadata::load_manifest;
 
 mod args;
 mod direction;
+mod dry_run;
 mod error;
 #[cfg(test)]
 mod test;
+mod validate;
 
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
@@ -161,6 +170,17 @@ pub(crate) fn run(args: &Args) -> Result<()> {
         update_metadata::find_migrations(&current_version, &args.migrate_to_version, &manifest)
             .context(error::FindMigrations)?;
 
+    if args.dry_run {
+        return dry_run::dry_run(
+            &repo,
+            direction,
+            &migrations,
+            &args.datastore_path,
+            &current_version,
+            &args.migrate_to_version,
+        );
+    }
+
     if migrations.is_empty() {
         // Not all new OS versions need to change the data store format.  If there's been no
         // change, we can just link to the last version rather than making a copy.
@@ -175,6 +195,7 @@ pub(crate) fn run(args: &Args) -> Result<()> {
             &args.datastore_path,
             &args.migrate_to_version,
         )?;
+        validate::validate_datastore(&copy_path)?;
         flip_to_new_version(&args.migrate_to_version, &copy_path)?;
     }
     Ok(())
@@ -1,7 +1,8 @@
 //! Provides an end-to-end test of `migrator` via the `run` function. This module is conditionally
 //! compiled for cfg(test) only.
 use crate::args::Args;
-use crate::run;
+use crate::{get_current_version, run};
+use apiserver::datastore::{Committed, DataStore, FilesystemDataStore, Key, KeyType};
 use chrono::{DateTime, Utc};
 use semver::Version;
 use std::fs;
@@ -38,9 +39,9 @@ const FIRST_MIGRATION: &str = "b-first-migration";
 const SECOND_MIGRATION: &str = "a-second-migration";
 
 /// Creates a script that will serve as a migration during testing. The script writes its migrations
-/// name to a file named `result.txt` in the parent directory of the datastore. `pentacle` does not
-/// retain the name of the executing binary or script, so we take the `migration_name` as input,
-/// and 'hardcode' it into the script.
+/// name to a file named `result.txt` in the parent directory of the datastore, and copies the
+/// source datastore to the target datastore. `pentacle` does not retain the name of the executing
+/// binary or script, so we take the `migration_name` as input, and 'hardcode' it into the script.
 fn create_test_migration<S: AsRef<str>>(migration_name: S) -> String {
     format!(
         r#"#!/usr/bin/env bash
@@ -49,6 +50,7 @@ migration_name="{}"
 datastore_parent_dir="$(dirname "${{3}}")"
 outfile="${{datastore_parent_dir}}/result.txt"
 echo "${{migration_name}}:" "${{@}}" >> "${{outfile}}"
+cp -r "${{3}}" "${{5}}"
 "#,
         migration_name.as_ref()
     )
@@ -63,10 +65,18 @@ struct TestDatastore {
 
 impl TestDatastore {
     /// Creates a `TempDir`, sets up the datastore links needed to represent the `from_version`
-    /// and returns a `TestDatastore` populated with this information.
+    /// and returns a `TestDatastore` populated with this information.  The datastore holds a live
+    /// setting, since the migrator checks the migrated live data against the model.
     fn new(from_version: Version) -> Self {
         let tmp = TempDir::new().unwrap();
         let datastore = storewolf::create_new_datastore(tmp.path(), Some(from_version)).unwrap();
+        FilesystemDataStore::new(&datastore)
+            .set_key(
+                &Key::new(KeyType::Data, "settings.motd").unwrap(),
+                "\"hi\"",
+                &Committed::Live,
+            )
+            .unwrap();
         TestDatastore { tmp, datastore }
     }
 }
@@ -200,6 +210,7 @@ fn migrate_forward() {
     let test_repo = create_test_repo();
     let args = Args {
         datastore_path: test_datastore.datastore.clone(),
+        dry_run: false,
         log_level: log::LevelFilter::Info,
         migration_directory: test_repo.targets_path.clone(),
         migrate_to_version: to_version,
@@ -232,6 +243,7 @@ fn migrate_backward() {
     let test_repo = create_test_repo();
     let args = Args {
         datastore_path: test_datastore.datastore.clone(),
+        dry_run: false,
         log_level: log::LevelFilter::Info,
         migration_directory: test_repo.targets_path.clone(),
         migrate_to_version: to_version,
@@ -252,3 +264,34 @@ fn migrate_backward() {
     let got: String = second_line.chars().take(want.len()).collect();
     assert_eq!(got, want);
 }
+
+/// This test ensures that a dry run runs migrations without changing the given datastore.
+/// See `migrate_forward` for a description of how these tests work.
+#[test]
+fn migrate_dry_run() {
+    let from_version = Version::parse("0.99.1").unwrap();
+    let to_version = Version::parse("0.99.0").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    let test_repo = create_test_repo();
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        dry_run: true,
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path,
+    };
+    let entries_before = fs::read_dir(test_datastore.tmp.path()).unwrap().count();
+    run(&args).unwrap();
+    // The migrations ran on a copy elsewhere, and the datastore wasn't flipped to the new version.
+    assert_eq!(
+        fs::read_dir(test_datastore.tmp.path()).unwrap().count(),
+        entries_before
+    );
+    assert!(!test_datastore.tmp.path().join("result.txt").exists());
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        from_version
+    );
+}
@@ -0,0 +1,162 @@
+//! This module checks a migrated data store against the model the migrator was built with, before
+//! the data store becomes live.
+//!
+//! Each migration is built with the model of the release that ships it, but a multi-version jump
+//! runs migrations from several releases, so a migration can't know which keys later migrations
+//! will still change.  We check once, after the whole chain has run.  At boot, the migrator runs
+//! from the image of the version it's migrating to, so its model is the one the API server will
+//! use with the new data store.
+
+use crate::error::{self, Result};
+use apiserver::datastore::deserialization::{from_map, from_map_with_prefix};
+use apiserver::datastore::{Committed, DataStore, FilesystemDataStore, Key};
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Ensures the live data and pending transactions in the data store at `datastore_path` can be
+/// deserialized into the model, just as the API server will deserialize them.
+pub(crate) fn validate_datastore(datastore_path: &Path) -> Result<()> {
+    let datastore = FilesystemDataStore::new(datastore_path);
+
+    let mut committeds = vec![Committed::Live];
+    let transactions = datastore
+        .list_transactions()
+        .context(error::ReadDataStore {
+            path: datastore_path,
+        })?;
+    committeds.extend(transactions.into_iter().map(|tx| Committed::Pending { tx }));
+
+    for committed in &committeds {
+        let settings = get_prefix(&datastore, datastore_path, "settings.", committed)?;
+        let _: model::Settings = from_map(&settings).context(error::ValidateModel {
+            path: datastore_path,
+            given: format!("{:?} settings", committed),
+        })?;
+    }
+
+    // Services and configuration files are only complete in live data; pending transactions can
+    // hold parts of them.
+    let services = get_prefix(&datastore, datastore_path, "services.", &Committed::Live)?;
+    let _: model::Services = from_map_with_prefix(Some("services".to_string()), &services)
+        .context(error::ValidateModel {
+            path: datastore_path,
+            given: "services",
+        })?;
+    let configuration_files = get_prefix(
+        &datastore,
+        datastore_path,
+        "configuration-files.",
+        &Committed::Live,
+    )?;
+    let _: model::ConfigurationFiles = from_map_with_prefix(
+        Some("configuration-files".to_string()),
+        &configuration_files,
+    )
+    .context(error::ValidateModel {
+        path: datastore_path,
+        given: "configuration files",
+    })?;
+
+    Ok(())
+}
+
+/// Returns the keys in the data store with the given prefix, and their serialized values.
+fn get_prefix(
+    datastore: &FilesystemDataStore,
+    datastore_path: &Path,
+    prefix: &str,
+    committed: &Committed,
+) -> Result<HashMap<Key, String>> {
+    datastore
+        .get_prefix(prefix, committed)
+        .context(error::ReadDataStore {
+            path: datastore_path,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use apiserver::datastore::KeyType;
+    use tempfile::TempDir;
+
+    fn set(datastore: &mut FilesystemDataStore, name: &str, value: &str, committed: &Committed) {
+        let key = Key::new(KeyType::Data, name).unwrap();
+        datastore.set_key(&key, value, committed).unwrap();
+    }
+
+    #[test]
+    fn validation() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("datastore");
+        let mut datastore = FilesystemDataStore::new(&path);
+        let live = Committed::Live;
+        let pending = Committed::Pending {
+            tx: "test".to_string(),
+        };
+        set(&mut datastore, "settings.motd", "\"hi\"", &live);
+        set(
+            &mut datastore,
+            "services.motd.configuration-files",
+            "[\"motd\"]",
+            &live,
+        );
+        set(
+            &mut datastore,
+            "services.motd.restart-commands",
+            "[]",
+            &live,
+        );
+        set(
+            &mut datastore,
+            "configuration-files.motd.path",
+            "\"/etc/motd\"",
+            &live,
+        );
+        set(
+            &mut datastore,
+            "configuration-files.motd.template-path",
+            "\"/t\"",
+            &live,
+        );
+        validate_datastore(&path).unwrap();
+
+        // Pending transactions can remove keys and hold part of a service
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        datastore.set_pending_deletion(&motd, "test").unwrap();
+        set(
+            &mut datastore,
+            "services.motd.restart-commands",
+            "[]",
+            &pending,
+        );
+        validate_datastore(&path).unwrap();
+
+        // A setting the model doesn't have, like one a later migration should have moved
+        set(
+            &mut datastore,
+            "settings.no-such-setting",
+            "\"hi\"",
+            &pending,
+        );
+        assert!(validate_datastore(&path).is_err());
+        datastore.delete_transaction("test").unwrap();
+        validate_datastore(&path).unwrap();
+
+        // A value of the wrong type
+        set(&mut datastore, "settings.updates.seed", "\"lots\"", &live);
+        assert!(validate_datastore(&path).is_err());
+        set(&mut datastore, "settings.updates.seed", "42", &live);
+        validate_datastore(&path).unwrap();
+
+        // Live services must be complete
+        set(
+            &mut datastore,
+            "services.other.restart-commands",
+            "[]",
+            &live,
+        );
+        assert!(validate_datastore(&path).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::status::{UpdateCommand, UpdateState};
+use chrono::{DateTime, Utc};
 use http::StatusCode;
 use num_derive::{FromPrimitive, ToPrimitive};
 use snafu::Snafu;
@@ -36,6 +37,30 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Failed to read boot health failure '{}': {}", path.display(), source))]
+    BootHealthRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse boot health failure '{}': {}", path.display(), source))]
+    BootHealthParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to write boot health failure '{}': {}", path.display(), source))]
+    BootHealthSerialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to remove boot health failure '{}': {}", path.display(), source))]
+    BootHealthRemove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to deserialize update info: {}", source))]
     UpdateInfo { source: serde_json::Error },
 
@@ -101,6 +126,9 @@ pub enum Error {
     #[snafu(display("Failed to start updog: {}", source))]
     Updog { source: std::io::Error },
 
+    #[snafu(display("Failed to list skipped updates with updog"))]
+    SkippedUpdates,
+
     #[snafu(display("Failed to prepare the update with updog"))]
     PrepareUpdate,
 
@@ -137,6 +165,17 @@ pub enum Error {
     #[snafu(display("Chosen update does not exist"))]
     UpdateDoesNotExist,
 
+    #[snafu(display(
+        "Outside of update maintenance windows; the next one opens at {}",
+        next
+    ))]
+    OutsideMaintenanceWindow { next: DateTime<Utc> },
+
+    #[snafu(display("Failed to check update maintenance windows: {}", source))]
+    MaintenanceWindow {
+        source: model::modeled_types::error::Error,
+    },
+
     #[snafu(display("Update version to query is not specified"))]
     UnspecifiedVersion,
 
@@ -158,4 +197,5 @@ pub enum TbuErrorStatus {
     DisallowCommand = 65,
     UpdateDoesNotExist = 66,
     NoStagedImage = 67,
+    OutsideMaintenanceWindow = 68,
 }
@@ -14,6 +14,9 @@ This allows the caller to synchronously call thar-be-updates without having to w
 
 thar-be-updates uses a lockfile to control read/write access to the disks and the update status file.
 
+Outside of the windows in `settings.updates.maintenance-windows`, the `prepare` and `activate` commands fail with an exit status indicating so.
+Pass `--ignore-maintenance-windows` to run them anyway; it's passed on to updog, which checks the same windows.
+
 */
 
 use fs2::FileExt;
@@ -32,8 +35,8 @@ use tempfile::NamedTempFile;
 use thar_be_updates::error;
 use thar_be_updates::error::{Error, Result, TbuErrorStatus};
 use thar_be_updates::status::{
-    get_update_status, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
-    UPDATE_STATUS_FILE,
+    check_maintenance_windows, get_update_status, ImageInspection, SkippedUpdate, UpdateCommand,
+    UpdateState, UpdateStatus, UPDATE_LOCKFILE, UPDATE_STATUS_FILE,
 };
 
 // FIXME Get this from configuration in the future
@@ -46,6 +49,7 @@ struct Args {
     subcommand: UpdateCommand,
     log_level: LevelFilter,
     socket_path: String,
+    ignore_maintenance_windows: bool,
 }
 
 /// Prints an usage message
@@ -61,6 +65,9 @@ fn usage() -> ! {
                 activate    Marks the inactive partition for boot
                 deactivate  Reverts update activation by marking current active partition for boot
 
+            Prepare and activate options:
+                    [ --ignore-maintenance-windows ]  Run outside of update maintenance windows
+
             Global options:
                     [ --socket-path PATH ]    Bottlerocket API socket path (default {})
                     [ --log-level trace|debug|info|warn|error ]  (default info)",
@@ -80,6 +87,7 @@ fn parse_args(args: std::env::Args) -> Args {
     let mut subcommand = None;
     let mut log_level = None;
     let mut socket_path = None;
+    let mut ignore_maintenance_windows = false;
 
     let mut iter = args.skip(1).peekable();
     while let Some(arg) = iter.next() {
@@ -99,6 +107,9 @@ fn parse_args(args: std::env::Args) -> Args {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
                 )
             }
+
+            "--ignore-maintenance-windows" => ignore_maintenance_windows = true,
+
             // Assume any arguments not prefixed with '-' is a subcommand
             s if !s.starts_with('-') => {
                 if subcommand.is_some() {
@@ -115,6 +126,7 @@ fn parse_args(args: std::env::Args) -> Args {
         subcommand: subcommand.unwrap_or_else(|| usage()),
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_API_SOCKET.to_string()),
+        ignore_maintenance_windows,
     }
 }
 
@@ -137,6 +149,8 @@ fn initialize_update_status() -> Result<()> {
     let mut new_status = UpdateStatus::new();
     // Initialize active partition set information
     new_status.update_active_partition_info()?;
+    // Report the last update that was rolled back after failing its boot health checks, if any
+    new_status.update_boot_health_failure()?;
     write_update_status(&new_status)
 }
 
@@ -196,12 +210,40 @@ fn refresh(status: &mut UpdateStatus, socket_path: &str) -> Result<bool> {
         }
         let update_info: Vec<update_metadata::Update> =
             serde_json::from_slice(&output.stdout).context(error::UpdateInfo)?;
+        // Knowing why a version is missing is only informational, so failing to find out doesn't
+        // fail the refresh.
+        let skipped = skipped_updates().unwrap_or_else(|e| {
+            warn!("Failed to list updates skipped by updog: {}", e);
+            Vec::new()
+        });
+        status.set_skipped_updates(skipped);
         status.update_available_updates(socket_path, update_info)
     })
 }
 
+/// Runs updog to list the updates that the version policy in the update settings rules out.
+fn skipped_updates() -> Result<Vec<SkippedUpdate>> {
+    debug!("Spawning 'updog whats --skipped'");
+    let output = Command::new("updog")
+        .args(&["whats", "--skipped", "--json"])
+        .output()
+        .context(error::Updog)?;
+    ensure!(output.status.success(), error::SkippedUpdates);
+    serde_json::from_slice(&output.stdout).context(error::UpdateInfo)
+}
+
+/// Returns the arguments for an updog subcommand that's limited to maintenance windows.  updog
+/// checks the windows itself, so it has to be told when we were asked to ignore them.
+fn updog_args(subcommand: &str, ignore_maintenance_windows: bool) -> Vec<&str> {
+    let mut args = vec![subcommand];
+    if ignore_maintenance_windows {
+        args.push("--ignore-maintenance-windows");
+    }
+    args
+}
+
 /// Prepares the update by downloading and writing the update to the staging partition
-fn prepare(status: &mut UpdateStatus) -> Result<()> {
+fn prepare(status: &mut UpdateStatus, ignore_maintenance_windows: bool) -> Result<()> {
     fork_and_return!({
         debug!("Spawning 'updog update-image'");
         let chosen_update = status
@@ -209,7 +251,7 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
             .context(error::UpdateDoesNotExist)?
             .clone();
         let output = Command::new("updog")
-            .arg("update-image")
+            .args(updog_args("update-image", ignore_maintenance_windows))
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Prepare, &output);
@@ -217,17 +259,51 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
             warn!("Failed to prepare the update with updog");
             return error::PrepareUpdate.fail();
         }
-        status.set_staging_partition_image_info(chosen_update);
+        status.set_staging_partition_image_info(chosen_update, inspect_staged_image());
         Ok(())
     })
 }
 
+/// Spawns updog process to inspect the image just written to the staging partition set.
+/// The update is staged either way, so problems running updog are only logged.
+fn inspect_staged_image() -> Option<ImageInspection> {
+    debug!("Spawning 'updog inspect'");
+    let output = match Command::new("updog").args(&["inspect", "--json"]).output() {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to run updog to inspect the staged update: {}", e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        warn!(
+            "Failed to inspect the staged update with updog: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    let inspection: ImageInspection = match serde_json::from_slice(&output.stdout) {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            warn!(
+                "Failed to parse updog's inspection of the staged update: {}",
+                e
+            );
+            return None;
+        }
+    };
+    for problem in inspection.problems() {
+        warn!("Staged update not verified: {}", problem);
+    }
+    Some(inspection)
+}
+
 /// "Activates" the staged update by letting updog set up the appropriate boot flags
-fn activate(status: &mut UpdateStatus) -> Result<()> {
+fn activate(status: &mut UpdateStatus, ignore_maintenance_windows: bool) -> Result<()> {
     fork_and_return!({
         debug!("Spawning 'updog update-apply'");
         let output = Command::new("updog")
-            .arg("update-apply")
+            .args(updog_args("update-apply", ignore_maintenance_windows))
             .output()
             .context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Activate, &output);
@@ -256,11 +332,13 @@ fn deactivate(status: &mut UpdateStatus) -> Result<()> {
     })
 }
 
-/// Given the update command, this drives the update state machine.
+/// Given the update command, this drives the update state machine.  Preparing and activating
+/// updates are limited to the update maintenance windows unless `ignore_maintenance_windows` is set.
 fn drive_state_machine(
     update_status: &mut UpdateStatus,
     operation: &UpdateCommand,
     socket_path: &str,
+    ignore_maintenance_windows: bool,
 ) -> Result<()> {
     let new_state = match (operation, update_status.update_state()) {
         (UpdateCommand::Refresh, UpdateState::Idle)
@@ -287,7 +365,10 @@ fn drive_state_machine(
                 update_status.chosen_update().is_some(),
                 error::UpdateDoesNotExist
             );
-            prepare(update_status)?;
+            if !ignore_maintenance_windows {
+                check_maintenance_windows(socket_path)?;
+            }
+            prepare(update_status, ignore_maintenance_windows)?;
             // If we succeed in preparing the update, we transition to `Staged`
             UpdateState::Staged
         }
@@ -298,7 +379,10 @@ fn drive_state_machine(
                 update_status.staging_partition().is_some(),
                 error::StagingPartition
             );
-            activate(update_status)?;
+            if !ignore_maintenance_windows {
+                check_maintenance_windows(socket_path)?;
+            }
+            activate(update_status, ignore_maintenance_windows)?;
             // If we succeed in activating the update, we transition to `Ready`
             UpdateState::Ready
         }
@@ -347,7 +431,12 @@ fn run() -> Result<()> {
         initialize_update_status()?;
     }
     let mut update_status = get_update_status(&lockfile)?;
-    drive_state_machine(&mut update_status, &args.subcommand, &args.socket_path)?;
+    drive_state_machine(
+        &mut update_status,
+        &args.subcommand,
+        &args.socket_path,
+        args.ignore_maintenance_windows,
+    )?;
     write_update_status(&update_status)?;
     Ok(())
 }
@@ -358,6 +447,7 @@ fn match_error_to_exit_status(err: Error) -> i32 {
         Error::DisallowCommand { .. } => TbuErrorStatus::DisallowCommand,
         Error::UpdateDoesNotExist { .. } => TbuErrorStatus::UpdateDoesNotExist,
         Error::StagingPartition { .. } => TbuErrorStatus::NoStagedImage,
+        Error::OutsideMaintenanceWindow { .. } => TbuErrorStatus::OutsideMaintenanceWindow,
         _ => TbuErrorStatus::OtherError,
     }
     .to_i32()
@@ -370,3 +460,26 @@ fn main() {
         std::process::exit(match_error_to_exit_status(e));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::updog_args;
+
+    #[test]
+    fn updog_respects_maintenance_windows() {
+        assert_eq!(updog_args("update-image", false), vec!["update-image"]);
+        assert_eq!(updog_args("update-apply", false), vec!["update-apply"]);
+    }
+
+    #[test]
+    fn updog_ignores_maintenance_windows() {
+        assert_eq!(
+            updog_args("update-image", true),
+            vec!["update-image", "--ignore-maintenance-windows"]
+        );
+        assert_eq!(
+            updog_args("update-apply", true),
+            vec!["update-apply", "--ignore-maintenance-windows"]
+        );
+    }
+}
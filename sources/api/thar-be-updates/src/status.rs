@@ -2,17 +2,21 @@ use crate::error;
 use crate::error::Result;
 use bottlerocket_release::BottlerocketRelease;
 use chrono::{DateTime, Utc};
-use model::modeled_types::FriendlyVersion;
+use model::modeled_types::{next_maintenance_window, FriendlyVersion, MaintenanceWindow};
 use serde::{Deserialize, Serialize};
 use signpost::State;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::convert::TryInto;
-use std::fs::File;
+use std::fs::{self, File};
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::Output;
+use tempfile::NamedTempFile;
 
 pub const UPDATE_LOCKFILE: &str = "/run/lock/thar-be-updates.lock";
 pub const UPDATE_STATUS_FILE: &str = "/run/cache/thar-be-updates/status.json";
+// This has to survive the reboot that rolls back a failed update
+pub const BOOT_HEALTH_FAILURE_FILE: &str = "/var/lib/thar-be-updates/boot-health-failure.json";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum UpdateState {
@@ -36,18 +40,72 @@ impl UpdateImage {
     }
 }
 
+/// SkippedUpdate represents an update that isn't available because the version policy in the
+/// update settings, like `skip-versions` or `minimum-version`, rules it out
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SkippedUpdate {
+    version: semver::Version,
+    /// Why updog skipped the version
+    reason: String,
+}
+
+impl SkippedUpdate {
+    pub fn version(&self) -> &semver::Version {
+        &self.version
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
 /// StagedImage represents a Bottlerocket image that is written to a partition set
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StagedImage {
     image: UpdateImage,
     /// Indicates whether this image is marked for next boot
     next_to_boot: bool,
+    /// What updog found when it inspected the image after writing it
+    #[serde(default)]
+    inspection: Option<ImageInspection>,
 }
 
 impl StagedImage {
+    pub fn image(&self) -> &UpdateImage {
+        &self.image
+    }
+
     pub(crate) fn set_next_to_boot(&mut self, next_to_boot: bool) {
         self.next_to_boot = next_to_boot
     }
+
+    pub fn inspection(&self) -> Option<&ImageInspection> {
+        self.inspection.as_ref()
+    }
+}
+
+/// ImageInspection describes an image staged in the inactive partition set, as reported by
+/// `updog inspect`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImageInspection {
+    /// The os-release of the staged image
+    os_release: Option<BottlerocketRelease>,
+    /// The dm-verity root hash the staged image boots with
+    verity_root_hash: Option<String>,
+    /// Whether the staged partitions were verified against the update manifest
+    verified: bool,
+    /// Why the staged image couldn't be verified
+    problems: Vec<String>,
+}
+
+impl ImageInspection {
+    pub fn verified(&self) -> bool {
+        self.verified
+    }
+
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -68,7 +126,7 @@ pub enum UpdateCommand {
 
 /// CommandResult represents the result of an issued command
 #[derive(Debug, Clone, Deserialize, Serialize)]
-struct CommandResult {
+pub struct CommandResult {
     cmd_type: UpdateCommand,
     cmd_status: CommandStatus,
     timestamp: DateTime<Utc>,
@@ -76,14 +134,89 @@ struct CommandResult {
     stderr: Option<String>,
 }
 
+impl CommandResult {
+    pub fn cmd_type(&self) -> &UpdateCommand {
+        &self.cmd_type
+    }
+
+    pub fn cmd_status(&self) -> &CommandStatus {
+        &self.cmd_status
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}
+
+/// BootHealthFailure records why a boot into an updated partition set failed its health checks
+/// and was rolled back
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BootHealthFailure {
+    /// The version that failed to boot
+    version: semver::Version,
+    timestamp: DateTime<Utc>,
+    reasons: Vec<String>,
+}
+
+impl BootHealthFailure {
+    pub fn new(version: semver::Version, reasons: Vec<String>) -> Self {
+        Self {
+            version,
+            timestamp: Utc::now(),
+            reasons,
+        }
+    }
+
+    pub fn reasons(&self) -> &[String] {
+        &self.reasons
+    }
+
+    /// Loads the most recent boot health failure, if there is one
+    pub fn load() -> Result<Option<Self>> {
+        let path = Path::new(BOOT_HEALTH_FAILURE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(path).context(error::BootHealthRead { path })?;
+        serde_json::from_reader(file).context(error::BootHealthParse { path })
+    }
+
+    /// Atomically writes out the boot health failure so it can be reported after rolling back
+    pub fn write(&self) -> Result<()> {
+        let path = Path::new(BOOT_HEALTH_FAILURE_FILE);
+        let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        let tempfile = NamedTempFile::new_in(dir).context(error::CreateTempfile)?;
+        serde_json::to_writer_pretty(&tempfile, self)
+            .context(error::BootHealthSerialize { path })?;
+        tempfile
+            .into_temp_path()
+            .persist(path)
+            .context(error::CreateStatusFile { path })?;
+        Ok(())
+    }
+
+    /// Removes the record of a boot health failure, once a later boot passes its health checks
+    pub fn clear() -> Result<()> {
+        let path = Path::new(BOOT_HEALTH_FAILURE_FILE);
+        if path.exists() {
+            fs::remove_file(path).context(error::BootHealthRemove { path })?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateStatus {
     update_state: UpdateState,
     available_updates: Vec<semver::Version>,
+    #[serde(default)]
+    skipped_updates: Vec<SkippedUpdate>,
     chosen_update: Option<UpdateImage>,
     active_partition: Option<StagedImage>,
     staging_partition: Option<StagedImage>,
     most_recent_command: Option<CommandResult>,
+    #[serde(default)]
+    boot_health_failure: Option<BootHealthFailure>,
 }
 
 impl Default for UpdateStatus {
@@ -113,10 +246,12 @@ impl UpdateStatus {
         Self {
             update_state: UpdateState::Idle,
             available_updates: vec![],
+            skipped_updates: vec![],
             chosen_update: None,
             active_partition: None,
             staging_partition: None,
             most_recent_command: None,
+            boot_health_failure: None,
         }
     }
 
@@ -142,6 +277,38 @@ impl UpdateStatus {
         }
     }
 
+    pub fn active_partition(&self) -> Option<&StagedImage> {
+        self.active_partition.as_ref()
+    }
+
+    pub fn available_updates(&self) -> &[semver::Version] {
+        &self.available_updates
+    }
+
+    pub fn skipped_updates(&self) -> &[SkippedUpdate] {
+        &self.skipped_updates
+    }
+
+    /// Sets the updates that the version policy rules out, so users can see why they aren't
+    /// available
+    pub fn set_skipped_updates(&mut self, skipped: Vec<SkippedUpdate>) {
+        self.skipped_updates = skipped;
+    }
+
+    pub fn most_recent_command(&self) -> Option<&CommandResult> {
+        self.most_recent_command.as_ref()
+    }
+
+    pub fn boot_health_failure(&self) -> Option<&BootHealthFailure> {
+        self.boot_health_failure.as_ref()
+    }
+
+    /// Loads the record of the last boot that failed its health checks and was rolled back
+    pub fn update_boot_health_failure(&mut self) -> Result<()> {
+        self.boot_health_failure = BootHealthFailure::load()?;
+        Ok(())
+    }
+
     /// Updates the active partition set information
     pub fn update_active_partition_info(&mut self) -> Result<()> {
         // Get current OS release info to determine active partition image information
@@ -160,15 +327,21 @@ impl UpdateStatus {
         self.active_partition = Some(StagedImage {
             image: active_image,
             next_to_boot: active_set == next_set,
+            inspection: None,
         });
         Ok(())
     }
 
-    /// Sets the staging partition image information
-    pub fn set_staging_partition_image_info(&mut self, image: UpdateImage) {
+    /// Sets the staging partition image information, along with what updog found inspecting it
+    pub fn set_staging_partition_image_info(
+        &mut self,
+        image: UpdateImage,
+        inspection: Option<ImageInspection>,
+    ) {
         self.staging_partition = Some(StagedImage {
             image,
             next_to_boot: false,
+            inspection,
         });
     }
 
@@ -251,21 +424,7 @@ impl UpdateStatus {
         self.available_updates = updates.iter().map(|u| u.version.to_owned()).collect();
         // Check if the 'version-lock'ed update is available as the 'chosen' update
         // Retrieve the 'version-lock' setting
-        let uri = "/settings";
-        let method = "GET";
-        let (code, response_body) = apiclient::raw_request(&socket_path, uri, method, None)
-            .context(error::APIRequest { method, uri })?;
-        ensure!(
-            code.is_success(),
-            error::APIResponse {
-                method,
-                uri,
-                code,
-                response_body,
-            }
-        );
-        let settings: serde_json::Value =
-            serde_json::from_str(&response_body).context(error::ResponseJson { uri })?;
+        let settings = get_settings(socket_path)?;
         let locked_version: FriendlyVersion = serde_json::from_value(
             settings["updates"]["version-lock"].to_owned(),
         )
@@ -307,3 +466,40 @@ impl UpdateStatus {
         Ok(false)
     }
 }
+
+/// Retrieves the current settings from the API
+pub fn get_settings(socket_path: &str) -> Result<serde_json::Value> {
+    let uri = "/settings";
+    let method = "GET";
+    let (code, response_body) = apiclient::raw_request(&socket_path, uri, method, None)
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+    serde_json::from_str(&response_body).context(error::ResponseJson { uri })
+}
+
+/// Makes sure we're inside one of the maintenance windows set in the updates settings, if any
+pub fn check_maintenance_windows(socket_path: &str) -> Result<()> {
+    let settings = get_settings(socket_path)?;
+    let windows = settings["updates"]["maintenance-windows"].to_owned();
+    // Settings without any windows allow updates at any time
+    let windows: Vec<MaintenanceWindow> = if windows.is_null() {
+        Vec::new()
+    } else {
+        serde_json::from_value(windows).context(error::GetSetting {
+            setting: "/settings/updates/maintenance-windows",
+        })?
+    };
+    let now = Utc::now();
+    if let Some(next) = next_maintenance_window(&windows, now).context(error::MaintenanceWindow)? {
+        ensure!(next <= now, error::OutsideMaintenanceWindow { next });
+    }
+    Ok(())
+}
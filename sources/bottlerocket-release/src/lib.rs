@@ -54,7 +54,9 @@ impl BottlerocketRelease {
         Self::from_file(DEFAULT_RELEASE_FILE)
     }
 
-    fn from_file<P>(path: P) -> Result<Self>
+    /// Reads release data from the given os-release file, such as one in another root
+    /// filesystem.
+    pub fn from_file<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
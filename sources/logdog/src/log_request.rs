@@ -61,15 +61,22 @@ pub(crate) fn log_requests() -> Vec<&'static str> {
 /// ```text
 /// file some-conf /etc/some/conf
 /// ```
+///
+/// An `optional-file` request copies a file the same way, but is skipped if the file doesn't
+/// exist, which is useful for files that are only created later, like rotated logs:
+///
+/// ```text
+/// optional-file some-log.1 /var/log/some-log.1
+/// ```
 #[derive(Debug, Clone)]
 struct LogRequest<'a> {
-    /// The log request mode. For example `exec`, `http`, or `file`.
+    /// The log request mode. For example `exec`, `http`, `file`, or `optional-file`.
     mode: &'a str,
     /// The filename that the logs will be written to.
     filename: &'a str,
     /// Any additional instructions or commands needed to fulfill the log request. For example, with
     /// `exec` this will be a program invocation like `echo hello world`. For an `http` request this
-    /// will be a URL. For a `file` request this will be the source file path.
+    /// will be a URL. For a `file` or `optional-file` request this will be the source file path.
     instructions: &'a str,
 }
 
@@ -104,6 +111,7 @@ where
         "exec" => handle_exec_request(&req, tempdir)?,
         "http" | "https" => handle_http_request(&req, tempdir)?,
         "file" => handle_file_request(&req, tempdir)?,
+        "optional-file" => handle_optional_file_request(&req, tempdir)?,
         unmatched => {
             return Err(error::Error::UnhandledRequest {
                 mode: unmatched.into(),
@@ -205,6 +213,18 @@ where
     Ok(())
 }
 
+/// Copies a file like `handle_file_request`, unless the file at `request.instructions` doesn't
+/// exist, in which case there's nothing to collect and the request is skipped.
+fn handle_optional_file_request<P>(request: &LogRequest<'_>, tempdir: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    if !request.instructions.is_empty() && !Path::new(request.instructions).exists() {
+        return Ok(());
+    }
+    handle_file_request(request, tempdir)
+}
+
 #[cfg(test)]
 mod test {
     use crate::log_request::handle_log_request;
@@ -225,6 +245,25 @@ mod test {
         assert_eq!(got, want);
     }
 
+    #[test]
+    fn optional_file_request() {
+        let source_dir = TempDir::new().unwrap();
+        let source_filepath = source_dir.path().join("foo-bar.source");
+        let request = format!("optional-file foo-bar {}", source_filepath.display());
+        let outdir = TempDir::new().unwrap();
+        let outfile = outdir.path().join("foo-bar");
+
+        // A missing file isn't an error, and isn't collected.
+        handle_log_request(&request, outdir.path()).unwrap();
+        assert!(!outfile.exists());
+
+        let want = "123";
+        write(&source_filepath, want).unwrap();
+        handle_log_request(&request, outdir.path()).unwrap();
+        let got = std::fs::read_to_string(&outfile).unwrap();
+        assert_eq!(got, want);
+    }
+
     #[test]
     fn exec_request() {
         let want = "hello world! \"quoted\"\n";
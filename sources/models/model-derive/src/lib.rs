@@ -35,16 +35,23 @@ This can be disabled by specifying the argument `add_option = false`.
 
 Fields are all wrapped in `Option<...>`.
 Similar to the `serde` attribute added to fields, this is because we don't want users to have to specify fields they aren't changing, and can be disabled the same way, by specifying `add_option = false`.
+
+## Schema
+
+Structs implement `JsonSchema` from the model's `schema` module, describing the struct as a JSON object with a property for each (kebab-case) field.
+Fields wrapped in `Option<...>` may be omitted; with `add_option = false`, all fields are required.
+Each field's type must implement `JsonSchema` too.
 */
 
 extern crate proc_macro;
 
 use darling::FromMeta;
 use proc_macro::TokenStream;
-use quote::ToTokens;
+use quote::quote;
 use syn::visit_mut::{self, VisitMut};
 use syn::{
-    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, ItemStruct, Visibility,
+    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, Fields, ItemStruct,
+    Visibility,
 };
 
 /// Define a `#[model]` attribute that can be placed on structs to be used in an API model.
@@ -63,7 +70,13 @@ pub fn model(args: TokenStream, input: TokenStream) -> TokenStream {
     let mut ast: ItemStruct =
         syn::parse(input).expect("Unable to parse item `model` was placed on - is it a struct?");
     helper.visit_item_struct_mut(&mut ast);
-    ast.into_token_stream().into()
+    let schema_impl = helper.schema_impl(&ast);
+
+    let output = quote! {
+        #ast
+        #schema_impl
+    };
+    output.into()
 }
 
 /// Store any args given by the user inside `#[model(...)]`.
@@ -95,6 +108,56 @@ impl From<ParsedArgs> for ModelHelper {
     }
 }
 
+impl ModelHelper {
+    /// Generates an implementation of JsonSchema for the (already modified) struct, describing
+    /// it as an object with a property for each field, named as serde will name it.
+    fn schema_impl(&self, node: &ItemStruct) -> proc_macro2::TokenStream {
+        let name = &node.ident;
+        let title = name.to_string();
+
+        let fields = match &node.fields {
+            Fields::Named(fields) => &fields.named,
+            // Model structs always have named fields; we can't describe anything else as an
+            // object, so don't try.
+            _ => return quote! {},
+        };
+
+        let mut properties = Vec::new();
+        let mut required = Vec::new();
+        for field in fields {
+            let ident = match &field.ident {
+                Some(ident) => ident,
+                None => continue,
+            };
+            // Matches the serde attribute we add to structs, rename_all = "kebab-case".
+            let property = ident
+                .to_string()
+                .trim_start_matches("r#")
+                .replace('_', "-");
+            let ty = &field.ty;
+            properties.push(quote! {
+                (#property, <#ty as crate::schema::JsonSchema>::json_schema())
+            });
+            // Fields we wrap in Option can be left out; otherwise they're required.
+            if !self.add_option {
+                required.push(property);
+            }
+        }
+
+        quote! {
+            impl crate::schema::JsonSchema for #name {
+                fn json_schema() -> crate::schema::Value {
+                    crate::schema::object_schema(
+                        #title,
+                        vec![#(#properties),*],
+                        &[#(#required),*],
+                    )
+                }
+            }
+        }
+    }
+}
+
 /// VisitMut helps us modify the node types we want without digging through the huge token trees
 /// need to represent them.
 impl VisitMut for ModelHelper {
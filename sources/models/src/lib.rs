@@ -17,6 +17,11 @@ Default values are specified in [defaults.toml](defaults.toml) and can be overri
 
 The `#[model]` attribute on Settings and its sub-structs reduces duplication and adds some required metadata; see [its docs](model-derive/) for details.
 
+The `#[model]` attribute also describes each struct with an OpenAPI 3.0 schema, through the `JsonSchema` trait in the [schema module](src/schema.rs).
+Modeled types describe their validation in their schemas, like the patterns and length limits they enforce.
+OpenAPI 3.0 schemas can't constrain the keys of maps, so maps describe their key type in an `x-key-schema` extension property instead.
+The API server returns the schema of the current variant's `Settings` from `/schema`, so you can validate settings, for example in user data, before using them.
+
 ## aws-k8s-1.15: Kubernetes 1.15
 
 * [Model](src/aws-k8s-1.15/mod.rs)
@@ -66,6 +71,9 @@ Note: all models share the same `Cargo.toml`.
 // "Modeled types" are types with special ser/de behavior used for validation.
 pub mod modeled_types;
 
+// OpenAPI schema descriptions of the model, implemented for model structs by the #[model] attribute.
+pub mod schema;
+
 // The "variant" module is just a directory where we symlink in the user's requested build
 // variant; each variant defines a top-level Settings structure and we re-export the current one.
 mod variant;
@@ -81,9 +89,9 @@ use std::collections::HashMap;
 use std::net::Ipv4Addr;
 
 use crate::modeled_types::{
-    DNSDomain, ECSAgentLogLevel, ECSAttributeKey, ECSAttributeValue, FriendlyVersion,
-    KubernetesClusterName, KubernetesLabelKey, KubernetesLabelValue, KubernetesTaintValue,
-    SingleLineString, Url, ValidBase64,
+    DNSDomain, ECSAgentLogLevel, ECSAttributeKey, ECSAttributeValue, ExactVersion, FriendlyVersion,
+    Identifier, KubernetesClusterName, KubernetesLabelKey, KubernetesLabelValue,
+    KubernetesTaintValue, MaintenanceWindow, SingleLineString, Url, ValidBase64,
 };
 
 // Kubernetes related settings. The dynamic settings are retrieved from
@@ -126,6 +134,28 @@ struct UpdatesSettings {
     // Version to update to when updating via the API.
     version_lock: FriendlyVersion,
     ignore_waves: bool,
+    // Windows in which updates may be applied automatically; no windows means any time.
+    maintenance_windows: Vec<MaintenanceWindow>,
+    // Versions never to update to, and the lowest version to update to.
+    skip_versions: Vec<ExactVersion>,
+    minimum_version: ExactVersion,
+    // Limits on downloading update images, so a wave doesn't saturate the network: the highest
+    // download rate, and the longest random delay before starting.
+    max_download_bytes_per_second: u32,
+    download_start_jitter_seconds: u32,
+    boot_health: BootHealthSettings,
+}
+
+// Checks a boot into a new partition set must pass before it's marked successful; if they don't
+// pass within the timeout, the host rolls back to the previous partition set.
+#[model(impl_default = true)]
+struct BootHealthSettings {
+    // systemd units that must be active
+    required_units: Vec<SingleLineString>,
+    timeout_seconds: u32,
+    // An optional command to run in the given host container, which must exit successfully
+    check_container: Identifier,
+    check_command: SingleLineString,
 }
 
 #[model]
@@ -3,7 +3,9 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use super::error;
+use crate::schema::{string_schema, JsonSchema, Value};
 use serde::de::Error as _;
+use serde_json::json;
 use snafu::{ensure, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
@@ -49,6 +51,17 @@ impl TryFrom<&str> for ECSAttributeKey {
 
 string_impls_for!(ECSAttributeKey, "ECSAttributeKey");
 
+impl JsonSchema for ECSAttributeKey {
+    fn json_schema() -> Value {
+        string_schema(
+            "ECSAttributeKey",
+            "An ECS attribute key",
+            Some(r"^[a-zA-Z0-9._/-]{1,128}$"),
+            Some(128),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_ecs_attribute_key {
     use super::ECSAttributeKey;
@@ -134,6 +147,17 @@ impl TryFrom<&str> for ECSAttributeValue {
 
 string_impls_for!(ECSAttributeValue, "ECSAttributeValue");
 
+impl JsonSchema for ECSAttributeValue {
+    fn json_schema() -> Value {
+        string_schema(
+            "ECSAttributeValue",
+            "An ECS attribute value, with no leading or trailing spaces",
+            Some(r"^[a-zA-Z0-9.@:_/\\-](([a-zA-Z0-9.@: _/\\-]{0,126})?[a-zA-Z0-9.@:_/\\-])?$"),
+            Some(128),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_ecs_attribute_value {
     use super::ECSAttributeValue;
@@ -196,6 +220,17 @@ enum ECSLogLevel {
 
 string_impls_for!(ECSAgentLogLevel, "ECSAgentLogLevel");
 
+impl JsonSchema for ECSAgentLogLevel {
+    fn json_schema() -> Value {
+        json!({
+            "title": "ECSAgentLogLevel",
+            "description": "A log level for the ECS agent",
+            "type": "string",
+            "enum": ["debug", "info", "warn", "error", "crit"],
+        })
+    }
+}
+
 impl TryFrom<&str> for ECSAgentLogLevel {
     type Error = error::Error;
 
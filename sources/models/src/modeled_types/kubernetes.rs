@@ -9,6 +9,7 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 use super::error;
+use crate::schema::{string_schema, JsonSchema, Value};
 
 /// KubernetesName represents a string that contains a valid Kubernetes resource name.  It stores
 /// the original string and makes it accessible through standard traits.
@@ -42,6 +43,17 @@ impl TryFrom<&str> for KubernetesName {
 
 string_impls_for!(KubernetesName, "KubernetesName");
 
+impl JsonSchema for KubernetesName {
+    fn json_schema() -> Value {
+        string_schema(
+            "KubernetesName",
+            "A Kubernetes resource name",
+            Some(r"^[0-9a-z.-]{1,253}$"),
+            Some(253),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_kubernetes_name {
     use super::KubernetesName;
@@ -107,6 +119,17 @@ impl TryFrom<&str> for KubernetesLabelKey {
 
 string_impls_for!(KubernetesLabelKey, "KubernetesLabelKey");
 
+impl JsonSchema for KubernetesLabelKey {
+    fn json_schema() -> Value {
+        string_schema(
+            "KubernetesLabelKey",
+            "A Kubernetes label key, with an optional DNS prefix",
+            Some(r"^([A-Za-z0-9.-]{1,253}/)?[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?$"),
+            Some(317),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_kubernetes_label_key {
     use super::KubernetesLabelKey;
@@ -183,6 +206,17 @@ impl TryFrom<&str> for KubernetesLabelValue {
 
 string_impls_for!(KubernetesLabelValue, "KubernetesLabelValue");
 
+impl JsonSchema for KubernetesLabelValue {
+    fn json_schema() -> Value {
+        string_schema(
+            "KubernetesLabelValue",
+            "A Kubernetes label value",
+            Some(r"^$|^[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?$"),
+            Some(63),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_kubernetes_label_value {
     use super::KubernetesLabelValue;
@@ -254,6 +288,17 @@ impl TryFrom<&str> for KubernetesTaintValue {
 
 string_impls_for!(KubernetesTaintValue, "KubernetesTaintValue");
 
+impl JsonSchema for KubernetesTaintValue {
+    fn json_schema() -> Value {
+        string_schema(
+            "KubernetesTaintValue",
+            "A Kubernetes label value, then a colon and an effect",
+            Some(r"^[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?:[A-Za-z0-9]{1,253}$"),
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_kubernetes_taint_value {
     use super::KubernetesTaintValue;
@@ -318,6 +363,17 @@ impl TryFrom<&str> for KubernetesClusterName {
 
 string_impls_for!(KubernetesClusterName, "KubernetesClusterName");
 
+impl JsonSchema for KubernetesClusterName {
+    fn json_schema() -> Value {
+        string_schema(
+            "KubernetesClusterName",
+            "A Kubernetes cluster name; a non-empty label value",
+            Some(r"^[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?$"),
+            Some(63),
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_kubernetes_cluster_name {
     use super::KubernetesClusterName;
@@ -48,6 +48,9 @@ pub mod error {
         #[snafu(display("Invalid domain name '{}': {}", input, msg))]
         InvalidDomainName { input: String, msg: String },
 
+        #[snafu(display("Can't tell when maintenance window '{}' next opens", window))]
+        MaintenanceWindowOpening { window: String },
+
         #[snafu(display("Invalid input for field {}: {}", field, source))]
         InvalidPlainValue {
             field: String,
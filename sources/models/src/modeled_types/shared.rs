@@ -1,9 +1,16 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use super::error;
+use crate::schema::{string_schema, JsonSchema, Value};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
 use semver::Version;
 use serde::de::Error as _;
-use snafu::{ensure, ResultExt};
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
 use std::fmt;
@@ -35,6 +42,14 @@ impl TryFrom<&str> for ValidBase64 {
 
 string_impls_for!(ValidBase64, "ValidBase64");
 
+impl JsonSchema for ValidBase64 {
+    fn json_schema() -> Value {
+        let mut schema = string_schema("ValidBase64", "Base64-encoded data", None, None);
+        schema["format"] = "byte".into();
+        schema
+    }
+}
+
 #[cfg(test)]
 mod test_valid_base64 {
     use super::ValidBase64;
@@ -95,6 +110,17 @@ impl TryFrom<&str> for SingleLineString {
 
 string_impls_for!(SingleLineString, "SingleLineString");
 
+impl JsonSchema for SingleLineString {
+    fn json_schema() -> Value {
+        string_schema(
+            "SingleLineString",
+            "A string with no line terminators",
+            Some("^[^\n\r\u{000B}\u{000C}\u{0085}\u{2028}\u{2029}]*$"),
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_single_line_string {
     use super::SingleLineString;
@@ -156,6 +182,17 @@ impl TryFrom<&str> for Identifier {
 
 string_impls_for!(Identifier, "Identifier");
 
+impl JsonSchema for Identifier {
+    fn json_schema() -> Value {
+        string_schema(
+            "Identifier",
+            "ASCII alphanumerics plus hyphens",
+            Some(r"^[A-Za-z0-9-]*$"),
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_valid_identifier {
     use super::Identifier;
@@ -218,6 +255,12 @@ impl TryFrom<&str> for Url {
 
 string_impls_for!(Url, "Url");
 
+impl JsonSchema for Url {
+    fn json_schema() -> Value {
+        string_schema("Url", "A URL; the scheme may be omitted", None, None)
+    }
+}
+
 #[cfg(test)]
 mod test_url {
     use super::Url;
@@ -305,6 +348,19 @@ impl TryFrom<FriendlyVersion> for semver::Version {
 
 string_impls_for!(FriendlyVersion, "FriendlyVersion");
 
+impl JsonSchema for FriendlyVersion {
+    fn json_schema() -> Value {
+        string_schema(
+            "FriendlyVersion",
+            "A semantic version, optionally prefixed with 'v', or 'latest'",
+            Some(
+                r"^(latest|v?(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)(-[0-9A-Za-z.-]+)?(\+[0-9A-Za-z.-]+)?)$",
+            ),
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_version {
     use super::FriendlyVersion;
@@ -353,6 +409,95 @@ mod test_version {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// ExactVersion represents a specific version string that can optionally be prefixed with 'v'.
+/// Unlike FriendlyVersion, it can't be 'latest', so it's suitable for settings that name a
+/// particular release.  It stores the original string and makes it accessible through standard
+/// traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ExactVersion {
+    inner: String,
+    version: Version,
+}
+
+impl TryFrom<&str> for ExactVersion {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // If the string begins with a 'v', skip it before checking if it is valid semver.
+        let version = if input.starts_with('v') {
+            &input[1..]
+        } else {
+            input
+        };
+        let version = version
+            .parse::<Version>()
+            .ok()
+            .context(error::InvalidVersion { input })?;
+        Ok(ExactVersion {
+            inner: input.to_string(),
+            version,
+        })
+    }
+}
+
+impl ExactVersion {
+    /// Returns the version, without any 'v' prefix.
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+}
+
+impl From<ExactVersion> for Version {
+    fn from(input: ExactVersion) -> Version {
+        input.version
+    }
+}
+
+string_impls_for!(ExactVersion, "ExactVersion");
+
+impl JsonSchema for ExactVersion {
+    fn json_schema() -> Value {
+        string_schema(
+            "ExactVersion",
+            "A semantic version, optionally prefixed with 'v'",
+            Some(
+                r"^v?(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)(-[0-9A-Za-z.-]+)?(\+[0-9A-Za-z.-]+)?$",
+            ),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_exact_version {
+    use super::ExactVersion;
+    use semver::Version;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_version_strings() {
+        for (ok, version) in &[
+            ("1.0.0", "1.0.0"),
+            ("v1.0.0", "1.0.0"),
+            ("v1.0.1-alpha", "1.0.1-alpha"),
+            ("1.0.2-alpha+1.0", "1.0.2-alpha+1.0"),
+        ] {
+            let exact = ExactVersion::try_from(*ok).unwrap();
+            assert_eq!(exact.as_ref(), *ok);
+            assert_eq!(Version::from(exact), Version::parse(version).unwrap());
+        }
+    }
+
+    #[test]
+    fn bad_version_strings() {
+        for err in &["latest", "hi", "1.0", "1", "v", "v1", "v1.0", "vv1.1.0"] {
+            ExactVersion::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
 /// DNSDomain represents a string that is a valid DNS domain. It stores the
 /// original string and makes it accessible through standard traits. Its purpose
 /// is input validation, for example validating the kubelet's "clusterDomain"
@@ -396,6 +541,17 @@ impl TryFrom<&str> for DNSDomain {
 
 string_impls_for!(DNSDomain, "DNSDomain");
 
+impl JsonSchema for DNSDomain {
+    fn json_schema() -> Value {
+        string_schema(
+            "DNSDomain",
+            "A DNS domain name, not starting with '.'",
+            None,
+            None,
+        )
+    }
+}
+
 #[cfg(test)]
 mod test_dns_domain {
     use super::DNSDomain;
@@ -420,3 +576,351 @@ mod test_dns_domain {
         }
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// MaintenanceWindow represents a weekly window of time in which updates may be applied, like
+/// "Mon-Fri 02:00-04:00 America/Los_Angeles".  It lists the days the window opens, as names,
+/// ranges of names, or "*" for every day, then the start and end times, then an optional time
+/// zone: an IANA time zone name, which follows daylight saving time, or a fixed offset from UTC
+/// like "-07:00".  Without one, times are in UTC.  A window whose end isn't after its start
+/// closes the next day, so "Sat 22:00-02:00" runs into Sunday morning and "Sun 00:00-00:00" lasts
+/// all of Sunday.  It stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MaintenanceWindow {
+    inner: String,
+    // Bit n is set if the window opens n days after Monday.
+    days: u8,
+    start: NaiveTime,
+    end: NaiveTime,
+    zone: WindowZone,
+}
+
+/// WindowZone is the time zone of a maintenance window's times.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum WindowZone {
+    Offset(FixedOffset),
+    Named(Tz),
+}
+
+impl WindowZone {
+    /// Returns the local time in the zone at the given time.
+    fn local(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            WindowZone::Offset(offset) => at.with_timezone(offset).naive_local(),
+            WindowZone::Named(tz) => at.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// Returns the time at which the zone's clocks show the given local time.  Where clocks go
+    /// back and a local time happens twice, this is the first.  Where they go forward past it,
+    /// this is the moment they change.
+    fn utc(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let tz = match self {
+            WindowZone::Offset(offset) => {
+                return offset
+                    .from_local_datetime(&local)
+                    .earliest()
+                    .map(|time| time.with_timezone(&Utc))
+            }
+            WindowZone::Named(tz) => tz,
+        };
+        if let Some(time) = tz.from_local_datetime(&local).earliest() {
+            return Some(time.with_timezone(&Utc));
+        }
+        // Clocks skip an hour at most, so an hour later exists, and is within an hour after the
+        // change; step back to it.
+        let mut time = tz
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()?
+            .with_timezone(&Utc);
+        while self.local(time - Duration::minutes(1)) >= local {
+            time = time - Duration::minutes(1);
+        }
+        Some(time)
+    }
+}
+
+const DAY_NAMES: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+const MAINTENANCE_WINDOW_PATTERN: &str = concat!(
+    r"^(\*|(Mon|Tue|Wed|Thu|Fri|Sat|Sun)(-(Mon|Tue|Wed|Thu|Fri|Sat|Sun))?",
+    r"(,(Mon|Tue|Wed|Thu|Fri|Sat|Sun)(-(Mon|Tue|Wed|Thu|Fri|Sat|Sun))?)*)",
+    r" ([01][0-9]|2[0-3]):[0-5][0-9]-([01][0-9]|2[0-3]):[0-5][0-9]",
+    r"( ([+-](0[0-9]|1[0-4]):[0-5][0-9]|[A-Za-z][A-Za-z0-9_+-]*(/[A-Za-z0-9_+-]+)*))?$"
+);
+
+lazy_static! {
+    pub(crate) static ref MAINTENANCE_WINDOW: Regex =
+        Regex::new(MAINTENANCE_WINDOW_PATTERN).unwrap();
+}
+
+impl TryFrom<&str> for MaintenanceWindow {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // The pattern checks the format, so parsing the parts only fails on a bug or on an
+        // unknown time zone name.
+        let (days, start, end, zone) = Some(input)
+            .filter(|input| MAINTENANCE_WINDOW.is_match(input))
+            .and_then(parse_maintenance_window)
+            .context(error::Pattern {
+                thing: "Maintenance window",
+                pattern: MAINTENANCE_WINDOW.clone(),
+                input,
+            })?;
+        Ok(MaintenanceWindow {
+            inner: input.to_string(),
+            days,
+            start,
+            end,
+            zone,
+        })
+    }
+}
+
+/// Splits a maintenance window that matched MAINTENANCE_WINDOW into its days, start time, end
+/// time, and time zone.
+fn parse_maintenance_window(input: &str) -> Option<(u8, NaiveTime, NaiveTime, WindowZone)> {
+    let mut parts = input.split(' ');
+    let day_list = parts.next()?;
+    let mut times = parts.next()?.split('-');
+    let start = NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(times.next()?, "%H:%M").ok()?;
+    let zone = match parts.next() {
+        None => WindowZone::Offset(FixedOffset::east(0)),
+        Some(offset) if offset.starts_with('+') || offset.starts_with('-') => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let hours: i32 = offset.get(1..3)?.parse().ok()?;
+            let minutes: i32 = offset.get(4..6)?.parse().ok()?;
+            WindowZone::Offset(FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?)
+        }
+        Some(name) => WindowZone::Named(name.parse().ok()?),
+    };
+
+    let day_index = |name| DAY_NAMES.iter().position(|day| *day == name);
+    let mut days = 0;
+    if day_list == "*" {
+        days = 0b111_1111;
+    } else {
+        for item in day_list.split(',') {
+            let mut range = item.split('-');
+            let first = day_index(range.next()?)?;
+            let last = match range.next() {
+                Some(name) => day_index(name)?,
+                None => first,
+            };
+            // Ranges can wrap around the end of the week, like "Fri-Mon".
+            let mut day = first;
+            loop {
+                days |= 1 << day;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+    }
+    Some((days, start, end, zone))
+}
+
+impl MaintenanceWindow {
+    fn opens_on(&self, day: Weekday) -> bool {
+        self.days & (1 << day.num_days_from_monday()) != 0
+    }
+
+    /// Returns whether the window is open at the given time.
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let local = self.zone.local(at);
+        let day = local.weekday();
+        let time = local.time();
+        if self.start < self.end {
+            self.opens_on(day) && self.start <= time && time < self.end
+        } else {
+            // The window closes the day after it opens.
+            (self.opens_on(day) && self.start <= time)
+                || (self.opens_on(day.pred()) && time < self.end)
+        }
+    }
+
+    /// Returns the first time, at or after the given time, that the window is open, or None if
+    /// that can't be found in the window's time zone.
+    pub fn next_open(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.contains(after) {
+            return Some(after);
+        }
+        let local = self.zone.local(after);
+        // Every window opens at least once a week, so it opens within the next eight days.
+        (0..=7)
+            .map(|n| local.date() + Duration::days(n))
+            .filter(|date| self.opens_on(date.weekday()))
+            .filter_map(|date| self.zone.utc(date.and_time(self.start)))
+            .find(|open| *open > after)
+    }
+}
+
+/// Returns the first time, at or after the given time, that any of the given maintenance windows
+/// is open, or None if there are no windows, meaning updates aren't restricted.  Fails if we can't
+/// tell when one of the windows opens, so callers don't treat it as open.
+pub fn next_maintenance_window(
+    windows: &[MaintenanceWindow],
+    after: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, error::Error> {
+    let mut next = None;
+    for window in windows {
+        let open = window
+            .next_open(after)
+            .context(error::MaintenanceWindowOpening {
+                window: &window.inner,
+            })?;
+        next = Some(next.map_or(open, |next: DateTime<Utc>| next.min(open)));
+    }
+    Ok(next)
+}
+
+string_impls_for!(MaintenanceWindow, "MaintenanceWindow");
+
+impl JsonSchema for MaintenanceWindow {
+    fn json_schema() -> Value {
+        string_schema(
+            "MaintenanceWindow",
+            "A weekly window of time, like 'Mon-Fri 02:00-04:00 America/Los_Angeles'; the time zone \
+             can also be a fixed offset like '-07:00', and defaults to UTC",
+            Some(MAINTENANCE_WINDOW_PATTERN),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_maintenance_window {
+    use super::{next_maintenance_window, MaintenanceWindow};
+    use chrono::{DateTime, Utc};
+    use std::convert::TryFrom;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn window(s: &str) -> MaintenanceWindow {
+        MaintenanceWindow::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn good_windows() {
+        for ok in &[
+            "Mon 00:00-01:00",
+            "* 02:00-04:00",
+            "Mon-Fri 02:00-04:00 UTC",
+            "Sat,Sun 22:00-02:00 -07:00",
+            "Fri-Mon,Wed 23:30-23:30 +05:30",
+            "Mon-Fri 02:00-04:00 America/Los_Angeles",
+            "* 00:00-00:00 Etc/GMT+5",
+        ] {
+            MaintenanceWindow::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_windows() {
+        for err in &[
+            "",
+            "Mon",
+            "02:00-04:00",
+            "mon 02:00-04:00",
+            "Monday 02:00-04:00",
+            "Mon 2:00-4:00",
+            "Mon 24:00-01:00",
+            "Mon-Fri 02:00-04:00 PST",
+            "Mon-Fri 02:00-04:00 Mars/Olympus_Mons",
+            "Mon-Fri 02:00-04:00 America/",
+            "Mon-Fri 02:00-04:00 +15:00",
+            "Mon,,Tue 02:00-04:00",
+            "Mon  02:00-04:00",
+        ] {
+            MaintenanceWindow::try_from(*err).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn contains() {
+        // 2020-08-03 was a Monday.
+        let weekdays = window("Mon-Fri 02:00-04:00");
+        assert!(weekdays.contains(time("2020-08-03T02:00:00Z")));
+        assert!(weekdays.contains(time("2020-08-07T03:59:59Z")));
+        assert!(!weekdays.contains(time("2020-08-03T04:00:00Z")));
+        assert!(!weekdays.contains(time("2020-08-08T02:30:00Z")));
+
+        // Saturday 22:00 through Sunday 02:00, and Sunday 22:00 through Monday 02:00, at UTC-7.
+        let weekend = window("Sat,Sun 22:00-02:00 -07:00");
+        assert!(weekend.contains(time("2020-08-09T05:30:00Z")));
+        assert!(weekend.contains(time("2020-08-10T08:59:00Z")));
+        assert!(!weekend.contains(time("2020-08-10T09:00:00Z")));
+        assert!(!weekend.contains(time("2020-08-08T05:30:00Z")));
+
+        let all_day = window("Sun 00:00-00:00");
+        assert!(all_day.contains(time("2020-08-09T00:00:00Z")));
+        assert!(all_day.contains(time("2020-08-09T23:59:59Z")));
+        assert!(!all_day.contains(time("2020-08-10T00:00:00Z")));
+
+        // Named time zones follow daylight saving time: UTC-7 in August, UTC-8 in December.
+        let pacific = window("Mon-Fri 02:00-04:00 America/Los_Angeles");
+        assert!(pacific.contains(time("2020-08-03T09:00:00Z")));
+        assert!(!pacific.contains(time("2020-08-03T11:00:00Z")));
+        assert!(!pacific.contains(time("2020-12-07T09:00:00Z")));
+        assert!(pacific.contains(time("2020-12-07T11:00:00Z")));
+    }
+
+    #[test]
+    fn next_open() {
+        let weekdays = window("Mon-Fri 02:00-04:00");
+        let now = time("2020-08-03T03:00:00Z");
+        assert_eq!(weekdays.next_open(now), Some(now));
+        assert_eq!(
+            weekdays.next_open(time("2020-08-03T04:00:00Z")),
+            Some(time("2020-08-04T02:00:00Z"))
+        );
+        assert_eq!(
+            weekdays.next_open(time("2020-08-07T05:00:00Z")),
+            Some(time("2020-08-10T02:00:00Z"))
+        );
+
+        let weekend = window("Sat 22:00-02:00 +01:00");
+        assert_eq!(
+            weekend.next_open(time("2020-08-08T20:59:00Z")),
+            Some(time("2020-08-08T21:00:00Z"))
+        );
+        assert_eq!(
+            weekend.next_open(time("2020-08-09T01:00:00Z")),
+            Some(time("2020-08-15T21:00:00Z"))
+        );
+
+        let pacific = window("Mon-Fri 02:00-04:00 America/Los_Angeles");
+        assert_eq!(
+            pacific.next_open(time("2020-08-03T12:00:00Z")),
+            Some(time("2020-08-04T09:00:00Z"))
+        );
+        assert_eq!(
+            pacific.next_open(time("2020-12-07T12:00:00Z")),
+            Some(time("2020-12-08T10:00:00Z"))
+        );
+        // On 2020-03-08, Pacific clocks went from 02:00 straight to 03:00, at 10:00 UTC, so a
+        // window starting at 02:30 opened then.
+        let skipped = window("Sun 02:30-04:00 America/Los_Angeles");
+        assert_eq!(
+            skipped.next_open(time("2020-03-08T09:00:00Z")),
+            Some(time("2020-03-08T10:00:00Z"))
+        );
+        assert!(skipped.contains(time("2020-03-08T10:00:00Z")));
+
+        let windows = [weekdays, weekend];
+        assert_eq!(
+            next_maintenance_window(&windows, time("2020-08-08T12:00:00Z")).unwrap(),
+            Some(time("2020-08-08T21:00:00Z"))
+        );
+        assert_eq!(
+            next_maintenance_window(&[], time("2020-08-08T12:00:00Z")).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! The schema module lets types in the API model describe themselves with a schema, so users can
+//! validate input (like user data) against the exact model of a variant before sending it.
+//!
+//! The schemas are OpenAPI 3.0 Schema Objects, so they can be included in the OpenAPI description
+//! of the API; that means they only use the subset of JSON Schema that OpenAPI 3.0 allows.
+//!
+//! The `#[model]` attribute implements JsonSchema for model structs; modeled types implement it
+//! by hand to describe their validation, and this module covers the standard types we use.
+
+use bottlerocket_release::BottlerocketRelease;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+pub use serde_json::{Map, Value};
+
+/// The extension property that holds the schema of the keys of a map.
+pub const KEY_SCHEMA_EXTENSION: &str = "x-key-schema";
+
+/// JsonSchema is implemented by types that can describe their serialized form with a schema.
+pub trait JsonSchema {
+    fn json_schema() -> Value;
+}
+
+/// Builds the schema for a model structure from the schemas of its fields.  Fields not listed in
+/// `required` may be omitted.  Unknown fields are rejected, matching our deserialization.
+pub fn object_schema(title: &str, properties: Vec<(&str, Value)>, required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect();
+    let mut schema = json!({
+        "title": title,
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+/// Builds the schema for a string-like modeled type.  `pattern` is an ECMA 262 regular
+/// expression, as required by OpenAPI, so it's not always written the same way as the regex
+/// used for validation, but it must accept the same strings.
+pub fn string_schema(
+    title: &str,
+    description: &str,
+    pattern: Option<&str>,
+    max_length: Option<usize>,
+) -> Value {
+    let mut schema = json!({
+        "title": title,
+        "description": description,
+        "type": "string",
+    });
+    if let Some(pattern) = pattern {
+        schema["pattern"] = pattern.into();
+    }
+    if let Some(max_length) = max_length {
+        schema["maxLength"] = max_length.into();
+    }
+    schema
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+// Standard types used in the model
+
+impl JsonSchema for String {
+    fn json_schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for bool {
+    fn json_schema() -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+impl JsonSchema for u32 {
+    fn json_schema() -> Value {
+        json!({ "type": "integer", "minimum": 0, "maximum": u32::MAX })
+    }
+}
+
+impl JsonSchema for Ipv4Addr {
+    fn json_schema() -> Value {
+        json!({ "type": "string", "format": "ipv4" })
+    }
+}
+
+/// Metadata values can be anything representable in TOML.
+impl JsonSchema for toml::Value {
+    fn json_schema() -> Value {
+        json!({})
+    }
+}
+
+/// Optional fields are described by the schema of the inner type; whether a field may be omitted
+/// is described by the containing structure.
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema() -> Value {
+        json!({ "type": "array", "items": T::json_schema() })
+    }
+}
+
+/// Maps are serialized as objects, with a property for each entry.  OpenAPI 3.0 can't constrain
+/// property names, so the schema of the key type goes in an extension property for tools that
+/// want to check keys, and in the description for people.
+impl<K: JsonSchema, V: JsonSchema> JsonSchema for HashMap<K, V> {
+    fn json_schema() -> Value {
+        let mut schema = json!({
+            "type": "object",
+            "additionalProperties": V::json_schema(),
+        });
+        let key_schema = K::json_schema();
+        if let Some(pattern) = key_schema["pattern"].as_str() {
+            schema["description"] = format!("Keys must match '{}'", pattern).into();
+        }
+        if key_schema != String::json_schema() {
+            schema[KEY_SCHEMA_EXTENSION] = key_schema;
+        }
+        schema
+    }
+}
+
+impl JsonSchema for BottlerocketRelease {
+    fn json_schema() -> Value {
+        let string = String::json_schema();
+        object_schema(
+            "BottlerocketRelease",
+            vec![
+                ("pretty_name", string.clone()),
+                ("variant_id", string.clone()),
+                ("version_id", string.clone()),
+                ("build_id", string.clone()),
+                ("arch", string),
+            ],
+            &[
+                "pretty_name",
+                "variant_id",
+                "version_id",
+                "build_id",
+                "arch",
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modeled_types::{
+        ECSAttributeKey, ECSAttributeValue, ExactVersion, FriendlyVersion, Identifier,
+        KubernetesClusterName, KubernetesLabelKey, KubernetesLabelValue, KubernetesName,
+        KubernetesTaintValue, MaintenanceWindow, SingleLineString, ValidBase64,
+    };
+    use regex::Regex;
+    use std::convert::TryFrom;
+
+    /// Checks that the schema pattern of a modeled type accepts exactly the given inputs that the
+    /// type itself accepts.
+    fn check_pattern<T>(inputs: &[&str])
+    where
+        T: JsonSchema + for<'a> TryFrom<&'a str>,
+    {
+        let schema = T::json_schema();
+        let pattern = schema["pattern"].as_str().unwrap();
+        let max_length = schema["maxLength"].as_u64();
+        let regex = Regex::new(pattern).unwrap();
+        for input in inputs {
+            let schema_ok = regex.is_match(input)
+                && max_length.map_or(true, |max| input.chars().count() as u64 <= max);
+            assert_eq!(
+                schema_ok,
+                T::try_from(input).is_ok(),
+                "pattern '{}' disagrees with validation of '{}'",
+                pattern,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn patterns_match_validation() {
+        let long_label_key = format!("{}/{}", "a".repeat(253), "name");
+        let too_long_label_key = format!("{}/{}", "a".repeat(254), "name");
+        let long_63 = "a".repeat(63);
+        let long_64 = "a".repeat(64);
+        let common = [
+            "",
+            "a",
+            "hi",
+            "42",
+            "HOWDY",
+            ".bad",
+            "bad.",
+            "a-b_c.d",
+            "a/b",
+            "a b",
+            "a\\b",
+            "@",
+            "hi\nthere",
+            "x:NoSchedule",
+            "foo:",
+            ":bar",
+            "hello|World",
+            "タール",
+        ];
+
+        let mut inputs = common.to_vec();
+        inputs.extend(&[long_label_key.as_str(), too_long_label_key.as_str()]);
+        inputs.extend(&[long_63.as_str(), long_64.as_str()]);
+
+        check_pattern::<Identifier>(&inputs);
+        check_pattern::<SingleLineString>(&inputs);
+        check_pattern::<KubernetesName>(&inputs);
+        check_pattern::<KubernetesLabelKey>(&inputs);
+        check_pattern::<KubernetesLabelValue>(&inputs);
+        check_pattern::<KubernetesTaintValue>(&inputs);
+        check_pattern::<ECSAttributeKey>(&inputs);
+        check_pattern::<ECSAttributeValue>(&inputs);
+        check_pattern::<KubernetesClusterName>(&inputs);
+
+        let versions = [
+            "1.0.0",
+            "v1.0.0",
+            "1.0.1-alpha",
+            "1.0.2-alpha+1.0",
+            "1.0.3-beta.1.01",
+            "latest",
+            "hi",
+            "1.0",
+            "v",
+            "v1.0",
+            "vv1.1.0",
+            "01.0.0",
+            "1.0.0-",
+            "latest1",
+        ];
+        check_pattern::<FriendlyVersion>(&versions);
+        check_pattern::<ExactVersion>(&versions);
+
+        let windows = [
+            "Mon 00:00-01:00",
+            "* 02:00-04:00",
+            "Mon-Fri 02:00-04:00 UTC",
+            "Sat,Sun 22:00-02:00 -07:00",
+            "Fri-Mon,Wed 23:30-23:30 +05:30",
+            "mon 02:00-04:00",
+            "Mon 24:00-01:00",
+            "Mon 2:00-4:00",
+            "Mon 02:00-04:00 +15:00",
+            "Mon,,Tue 02:00-04:00",
+            "*,Mon 02:00-04:00",
+            "Mon 02:00-04:00 America/Los_Angeles",
+            "Mon 02:00-04:00 EST",
+            "Mon 02:00-04:00 America/",
+            "Mon 02:00-04:00 /UTC",
+            "Mon 02:00-04:00 US Pacific",
+        ];
+        check_pattern::<MaintenanceWindow>(&windows);
+
+        // The pattern only checks the format of time zone names, so it can't reject unknown ones.
+        let unknown_zone = "Mon 02:00-04:00 PST";
+        let pattern = MaintenanceWindow::json_schema()["pattern"].clone();
+        assert!(Regex::new(pattern.as_str().unwrap())
+            .unwrap()
+            .is_match(unknown_zone));
+        assert!(MaintenanceWindow::try_from(unknown_zone).is_err());
+    }
+
+    #[test]
+    fn model_struct_schema() {
+        let schema = crate::NtpSettings::json_schema();
+        assert_eq!(schema["title"], "NtpSettings");
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["properties"]["time-servers"]["type"], "array");
+        // Fields are optional when the model wraps them in Option
+        assert!(schema.get("required").is_none());
+
+        let schema = crate::Service::json_schema();
+        assert_eq!(
+            schema["required"],
+            json!(["configuration-files", "restart-commands"])
+        );
+    }
+
+    #[test]
+    fn map_schema() {
+        let schema = HashMap::<KubernetesLabelKey, KubernetesLabelValue>::json_schema();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(
+            schema["additionalProperties"],
+            KubernetesLabelValue::json_schema()
+        );
+        assert_eq!(
+            schema[KEY_SCHEMA_EXTENSION],
+            KubernetesLabelKey::json_schema()
+        );
+
+        // Any string is a valid key, so there's nothing to describe.
+        let schema = crate::Services::json_schema();
+        assert!(schema.get(KEY_SCHEMA_EXTENSION).is_none());
+    }
+
+    /// Finds any JSON Schema keywords in the given schema that aren't allowed in an OpenAPI 3.0
+    /// Schema Object.
+    fn unsupported_keywords(schema: &Value, found: &mut Vec<String>) {
+        const UNSUPPORTED: &[&str] = &[
+            "$schema",
+            "$id",
+            "$defs",
+            "definitions",
+            "const",
+            "contains",
+            "contentEncoding",
+            "contentMediaType",
+            "dependencies",
+            "examples",
+            "if",
+            "then",
+            "else",
+            "patternProperties",
+            "propertyNames",
+        ];
+        match schema {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    if UNSUPPORTED.contains(&key.as_str()) {
+                        found.push(key.clone());
+                    }
+                    // The names of properties aren't keywords, but their schemas are checked.
+                    if key == "properties" {
+                        for property in value.as_object().unwrap().values() {
+                            unsupported_keywords(property, found);
+                        }
+                    } else if !key.starts_with("x-") {
+                        unsupported_keywords(value, found);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for value in values {
+                    unsupported_keywords(value, found);
+                }
+            }
+            _ => {}
+        }
+        if schema["type"] == "null" {
+            found.push("type: null".to_string());
+        }
+    }
+
+    #[test]
+    fn openapi_keywords_only() {
+        let mut found = Vec::new();
+        unsupported_keywords(&crate::Settings::json_schema(), &mut found);
+        unsupported_keywords(&ValidBase64::json_schema(), &mut found);
+        assert!(found.is_empty(), "unsupported keywords: {:?}", found);
+    }
+}
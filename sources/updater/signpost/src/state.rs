@@ -183,6 +183,11 @@ impl State {
         }
     }
 
+    /// Returns whether the active partition has been marked as successfully booted.
+    pub fn active_successful(&self) -> bool {
+        self.gptprio(self.active()).successful()
+    }
+
     /// Sets the active partition as successfully booted, but **does not write to the disk**.
     pub fn mark_successful_boot(&mut self) {
         let mut flags = self.gptprio(self.active());
@@ -140,6 +140,14 @@ pub enum Error {
         target: Version,
     },
 
+    #[snafu(display("No update found for {} {} {}", variant, arch, version))]
+    UpdateNotFound {
+        variant: String,
+        arch: String,
+        version: Version,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
@@ -5,13 +5,14 @@ pub mod error;
 mod se;
 
 use crate::error::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use parse_datetime::parse_offset;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::ops::Bound::{Excluded, Included};
@@ -71,6 +72,23 @@ impl UpdateWaves {
         let wave_data = fs::read_to_string(path).context(error::FileRead { path })?;
         toml::from_str(&wave_data).context(error::InvalidToml { path })
     }
+
+    /// Returns the problems with the waves that can only be seen in the wave file, because they're
+    /// lost when the waves are set in the manifest.
+    #[must_use]
+    pub fn wave_problems(&self) -> Vec<WaveProblem> {
+        let mut problems = Vec::new();
+        // The manifest only records where each wave starts, so the last wave of an update always
+        // covers the rest of the fleet, whatever percentage the wave file asked for.
+        if let Some(last) = self.waves.last() {
+            if last.fleet_percentage < 100 {
+                problems.push(WaveProblem::FinalWaveIncomplete {
+                    fleet_percentage: last.fleet_percentage,
+                });
+            }
+        }
+        problems
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -84,6 +102,19 @@ pub struct Images {
     pub boot: String,
     pub root: String,
     pub hash: String,
+    /// Delta images that produce this update's images from those of an earlier version, keyed by
+    /// that earlier version.  Hosts running one of these versions can download a delta instead of
+    /// a full image.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub deltas: BTreeMap<Version, DeltaImages>,
+}
+
+/// `DeltaImages` are the target names of the deltas for each image of an update.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeltaImages {
+    pub boot: String,
+    pub root: String,
+    pub hash: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -95,6 +126,18 @@ pub struct Update {
     #[serde(deserialize_with = "de::deserialize_bound")]
     pub waves: BTreeMap<u32, DateTime<Utc>>,
     pub images: Images,
+    /// Set while the rollout of this update is halted; hosts won't update to it, whatever their
+    /// wave, until it's resumed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub halt: Option<Halt>,
+}
+
+/// `Halt` records that a release manager stopped the rollout of an update.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Halt {
+    pub since: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -165,6 +208,7 @@ impl Manifest {
             max_version: max_version.clone(),
             images,
             waves: BTreeMap::new(),
+            halt: None,
         };
         self.update_max_version(
             &update.max_version,
@@ -293,6 +337,90 @@ impl Manifest {
         Self::validate_updates(&self.updates)?;
         Ok(num_matching)
     }
+
+    /// Halts the rollout of the update matching variant, arch, and version, so that no more hosts
+    /// update to it, until it's resumed with `resume_update`.  Returns the number of matching
+    /// updates.
+    pub fn halt_update(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        since: DateTime<Utc>,
+        reason: Option<String>,
+    ) -> Result<usize> {
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        ensure!(
+            !matching.is_empty(),
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+        let num_matching = matching.len();
+        let halt = Halt { since, reason };
+        for update in matching {
+            update.halt = Some(halt.clone());
+        }
+        Ok(num_matching)
+    }
+
+    /// Resumes the halted rollout of the update matching variant, arch, and version.  Hosts
+    /// follow the update's waves again, so any whose time has come while it was halted can update
+    /// right away.  Returns the number of matching updates.
+    pub fn resume_update(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+    ) -> Result<usize> {
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        ensure!(
+            !matching.is_empty(),
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+        let num_matching = matching.len();
+        for update in matching {
+            update.halt = None;
+        }
+        Ok(num_matching)
+    }
+
+    /// Adds delta images to the update matching variant, arch, and version, for hosts updating
+    /// from `from_version`.  Any deltas already listed for that version are replaced.
+    pub fn add_delta(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        from_version: &Version,
+        deltas: &DeltaImages,
+    ) -> Result<()> {
+        let matching =
+            self.get_matching_updates(variant.clone(), arch.clone(), image_version.clone());
+        ensure!(
+            !matching.is_empty(),
+            error::UpdateNotFound {
+                variant,
+                arch,
+                version: image_version,
+            }
+        );
+        for update in matching {
+            update
+                .images
+                .deltas
+                .insert(from_version.clone(), deltas.clone());
+        }
+        Ok(())
+    }
 }
 
 impl Update {
@@ -385,6 +513,150 @@ impl Update {
         // There are no waves, so we consider the update available
         true
     }
+
+    /// Returns how many seeds the update is ready for at `time`.
+    fn ready_seeds(&self, time: DateTime<Utc>) -> u32 {
+        (0..MAX_SEED).fold(0, |ready, seed| {
+            if self.update_ready(seed, time) {
+                ready + 1
+            } else {
+                ready
+            }
+        })
+    }
+
+    /// Returns how many seeds the update is ready for at each `step` from `start` through `end`,
+    /// according to `update_ready`.  A `step` that isn't positive only checks `start`.
+    #[must_use]
+    pub fn simulate_waves(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        step: Duration,
+    ) -> Vec<WaveProgress> {
+        let mut progress = Vec::new();
+        let mut time = start;
+        while time <= end {
+            let ready_seeds = self.ready_seeds(time);
+            progress.push(WaveProgress { time, ready_seeds });
+            if step <= Duration::zero() {
+                break;
+            }
+            time = time + step;
+        }
+        progress
+    }
+
+    /// Returns the problems with the update's wave schedule, if the rollout is meant to be
+    /// complete by `end`.
+    #[must_use]
+    pub fn wave_problems(&self, end: DateTime<Utc>) -> Vec<WaveProblem> {
+        let mut problems = Vec::new();
+        if self.waves.is_empty() {
+            return problems;
+        }
+
+        if let Some((first_seed, _)) = self.waves.iter().next() {
+            if *first_seed > 0 {
+                problems.push(WaveProblem::Gap {
+                    end_seed: *first_seed,
+                });
+            }
+        }
+
+        let mut previous: Option<(u32, DateTime<Utc>)> = None;
+        for (seed, start_time) in &self.waves {
+            if let Some((previous_seed, previous_start_time)) = previous {
+                if *start_time <= previous_start_time {
+                    problems.push(WaveProblem::Overlap {
+                        seed: *seed,
+                        start_time: *start_time,
+                        previous_seed,
+                        previous_start_time,
+                    });
+                }
+            }
+            previous = Some((*seed, *start_time));
+        }
+
+        let ready_seeds = self.ready_seeds(end);
+        if ready_seeds < MAX_SEED {
+            problems.push(WaveProblem::Incomplete { end, ready_seeds });
+        }
+        problems
+    }
+}
+
+/// The number of seeds an update is ready for at a point in time, from `Update::simulate_waves`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveProgress {
+    pub time: DateTime<Utc>,
+    pub ready_seeds: u32,
+}
+
+impl WaveProgress {
+    /// Returns how many of a fleet of `fleet_size` hosts would be ready to update, assuming their
+    /// seeds are spread evenly up to `MAX_SEED`.
+    #[must_use]
+    pub fn ready_hosts(&self, fleet_size: u64) -> u64 {
+        fleet_size * u64::from(self.ready_seeds) / u64::from(MAX_SEED)
+    }
+}
+
+/// A problem with an update's wave schedule, from `Update::wave_problems` or
+/// `UpdateWaves::wave_problems`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaveProblem {
+    /// The first wave doesn't start at seed 0, so hosts with seeds up to and including `end_seed`
+    /// are in the initial wave and can update right away.
+    Gap { end_seed: u32 },
+    /// A wave doesn't start after the wave before it, so the two are rolled out at once, or out of
+    /// order.
+    Overlap {
+        seed: u32,
+        start_time: DateTime<Utc>,
+        previous_seed: u32,
+        previous_start_time: DateTime<Utc>,
+    },
+    /// The last wave hasn't reached the whole fleet by `end`.
+    Incomplete {
+        end: DateTime<Utc>,
+        ready_seeds: u32,
+    },
+    /// The last wave in a wave file doesn't reach 100% of the fleet.
+    FinalWaveIncomplete { fleet_percentage: u32 },
+}
+
+impl fmt::Display for WaveProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gap { end_seed } => write!(
+                f,
+                "Gap: seeds 0-{} are before the first wave and are ready immediately",
+                end_seed
+            ),
+            Self::Overlap {
+                seed,
+                start_time,
+                previous_seed,
+                previous_start_time,
+            } => write!(
+                f,
+                "Overlap: wave at seed {} starts at {}, not after the wave at seed {} starting at {}",
+                seed, start_time, previous_seed, previous_start_time
+            ),
+            Self::Incomplete { end, ready_seeds } => write!(
+                f,
+                "Incomplete: only {}/{} seeds are ready by {}",
+                ready_seeds, MAX_SEED, end
+            ),
+            Self::FinalWaveIncomplete { fleet_percentage } => write!(
+                f,
+                "Incomplete: the final wave is for {}% of the fleet, not 100%",
+                fleet_percentage
+            ),
+        }
+    }
 }
 
 pub fn find_migrations(from: &Version, to: &Version, manifest: &Manifest) -> Result<Vec<String>> {
@@ -479,7 +751,9 @@ mod tests {
                 boot: String::from("boot"),
                 root: String::from("root"),
                 hash: String::from("hash"),
+                deltas: BTreeMap::new(),
             },
+            halt: None,
         }
     }
 
@@ -644,7 +918,9 @@ mod tests {
                 boot: String::from("boot"),
                 root: String::from("root"),
                 hash: String::from("hash"),
+                deltas: BTreeMap::new(),
             },
+            halt: None,
         };
         let seed = 1024;
         // Construct a DateTime object for 1/1/2000 00:00:00
@@ -664,6 +940,95 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_simulate_waves() {
+        let time = test_time();
+        let mut update = test_update();
+        add_test_waves(&mut update);
+
+        let progress = update.simulate_waves(
+            time - Duration::seconds(1),
+            time + Duration::seconds(5),
+            Duration::seconds(1),
+        );
+        assert_eq!(progress.len(), 7);
+        // Seed 0 is in the "initial" wave, which is always ready, but no other seed is ready
+        // before the first wave starts; every seed is ready once the last wave has started.
+        assert_eq!(progress[0].ready_seeds, 1);
+        assert_eq!(progress[6].ready_seeds, MAX_SEED);
+        assert!(progress
+            .windows(2)
+            .all(|pair| pair[0].ready_seeds <= pair[1].ready_seeds));
+        assert_eq!(progress[6].ready_hosts(1000), 1000);
+        assert_eq!(
+            WaveProgress {
+                time,
+                ready_seeds: MAX_SEED / 4
+            }
+            .ready_hosts(1000),
+            250
+        );
+    }
+
+    #[test]
+    fn test_wave_problems() {
+        let time = test_time();
+        let mut update = test_update();
+        assert!(update.wave_problems(time).is_empty());
+
+        add_test_waves(&mut update);
+        assert!(update
+            .wave_problems(time + Duration::milliseconds(4096))
+            .is_empty());
+        assert_eq!(
+            update.wave_problems(time + Duration::milliseconds(4000)),
+            vec![WaveProblem::Incomplete {
+                end: time + Duration::milliseconds(4000),
+                ready_seeds: update.ready_seeds(time + Duration::milliseconds(4000)),
+            }]
+        );
+
+        update.waves.remove(&0);
+        update.waves.insert(512, time + Duration::milliseconds(100));
+        assert_eq!(
+            update.wave_problems(time + Duration::milliseconds(4096)),
+            vec![
+                WaveProblem::Gap { end_seed: 50 },
+                WaveProblem::Overlap {
+                    seed: 512,
+                    start_time: time + Duration::milliseconds(100),
+                    previous_seed: 100,
+                    previous_start_time: time + Duration::milliseconds(1024),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wave_file_problems() {
+        let wave = |start_after: &str, fleet_percentage| UpdateWave {
+            start_after: start_after.to_string(),
+            fleet_percentage,
+        };
+        let mut waves = UpdateWaves {
+            waves: vec![wave("1 hour", 10), wave("1 day", 100)],
+        };
+        assert!(waves.wave_problems().is_empty());
+
+        waves.waves[1].fleet_percentage = 90;
+        assert_eq!(
+            waves.wave_problems(),
+            vec![WaveProblem::FinalWaveIncomplete {
+                fleet_percentage: 90
+            }]
+        );
+
+        assert_eq!(
+            WaveProblem::Gap { end_seed: 50 }.to_string(),
+            "Gap: seeds 0-50 are before the first wave and are ready immediately"
+        );
+    }
+
     #[test]
     fn test_migrations_forward() {
         // A manifest with four migration tuples starting at 1.0 and ending at 1.3.
@@ -696,4 +1061,98 @@ mod tests {
         assert!(i.next().unwrap() == "migration_1.1.0_b");
         assert!(i.next().unwrap() == "migration_1.1.0_a");
     }
+
+    #[test]
+    fn test_add_delta() {
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let from = Version::parse("1.1.0").unwrap();
+        let deltas = DeltaImages {
+            boot: String::from("boot-delta"),
+            root: String::from("root-delta"),
+            hash: String::from("hash-delta"),
+        };
+
+        assert!(manifest
+            .add_delta(
+                String::from("bottlerocket"),
+                String::from("test"),
+                Version::parse("1.0.0").unwrap(),
+                &from,
+                &deltas,
+            )
+            .is_err());
+        manifest
+            .add_delta(
+                String::from("bottlerocket"),
+                String::from("test"),
+                Version::parse("1.1.1").unwrap(),
+                &from,
+                &deltas,
+            )
+            .unwrap();
+
+        // Deltas are keyed by version in the serialized manifest, and round-trip.
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(
+            json["updates"][0]["images"]["deltas"]["1.1.0"]["root"],
+            "root-delta"
+        );
+        let manifest: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(manifest.updates[0].images.deltas.get(&from), Some(&deltas));
+    }
+
+    #[test]
+    fn test_halt_update() {
+        let time = test_time();
+        let mut manifest = Manifest::default();
+        manifest.updates.push(test_update());
+        let variant = String::from("bottlerocket");
+        let arch = String::from("test");
+        let version = Version::parse("1.1.1").unwrap();
+
+        assert!(manifest
+            .halt_update(
+                variant.clone(),
+                arch.clone(),
+                Version::parse("1.0.0").unwrap(),
+                time,
+                None
+            )
+            .is_err());
+        manifest
+            .halt_update(
+                variant.clone(),
+                arch.clone(),
+                version.clone(),
+                time,
+                Some(String::from("bad release")),
+            )
+            .unwrap();
+
+        // The halt is part of the (signed) manifest, and round-trips.
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["updates"][0]["halt"]["reason"], "bad release");
+        let mut manifest: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            manifest.updates[0].halt,
+            Some(Halt {
+                since: time,
+                reason: Some(String::from("bad release")),
+            })
+        );
+
+        manifest.resume_update(variant, arch, version).unwrap();
+        assert!(manifest.updates[0].halt.is_none());
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert!(json["updates"][0].get("halt").is_none());
+    }
+
+    #[test]
+    fn test_no_deltas() {
+        // Manifests without deltas are unchanged, so older clients can still read them.
+        let update = test_update();
+        let json = serde_json::to_value(&update).unwrap();
+        assert!(json["images"].get("deltas").is_none());
+    }
 }
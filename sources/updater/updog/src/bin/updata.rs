@@ -1,6 +1,14 @@
 #![deny(rust_2018_idioms)]
 #![warn(clippy::pedantic)]
 
+/// updata only generates deltas; updog applies them.  The delta tests need both.
+#[path = "../delta"]
+mod delta {
+    #[cfg(test)]
+    pub(crate) mod apply;
+    pub(crate) mod format;
+    pub(crate) mod generate;
+}
 #[path = "../error.rs"]
 mod error;
 
@@ -8,14 +16,15 @@ mod error;
 extern crate log;
 
 use crate::error::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use semver::Version;
 use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
 use snafu::{ErrorCompat, OptionExt, ResultExt};
-use std::fs;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::path::PathBuf;
 use structopt::StructOpt;
-use update_metadata::{Images, Manifest, Release, UpdateWaves};
+use update_metadata::{DeltaImages, Images, Manifest, Release, UpdateWaves};
 
 #[derive(Debug, StructOpt)]
 struct GeneralArgs {
@@ -69,6 +78,60 @@ impl AddUpdateArgs {
                 root: self.root,
                 boot: self.boot,
                 hash: self.hash,
+                deltas: BTreeMap::new(),
+            },
+        )?;
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct AddDeltaArgs {
+    // metadata file to create/modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-k8s-1.17'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    // version the deltas were generated from
+    #[structopt(long = "from")]
+    from_version: Version,
+
+    // root image delta target name
+    #[structopt(short = "r", long = "root")]
+    root: String,
+
+    // boot image delta target name
+    #[structopt(short = "b", long = "boot")]
+    boot: String,
+
+    // verity "hash" image delta target name
+    #[structopt(short = "h", long = "hash")]
+    hash: String,
+}
+
+impl AddDeltaArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        manifest.add_delta(
+            self.variant,
+            self.arch,
+            self.image_version,
+            &self.from_version,
+            &DeltaImages {
+                root: self.root,
+                boot: self.boot,
+                hash: self.hash,
             },
         )?;
         update_metadata::write_file(&self.file, &manifest)?;
@@ -76,6 +139,56 @@ impl AddUpdateArgs {
     }
 }
 
+#[derive(Debug, StructOpt)]
+struct GenerateDeltaArgs {
+    // lz4-compressed image of the earlier version, as published in the repo
+    #[structopt(short = "s", long = "source")]
+    source: PathBuf,
+
+    // lz4-compressed image of the new version, as published in the repo
+    #[structopt(short = "t", long = "target")]
+    target: PathBuf,
+
+    // where to write the lz4-compressed delta
+    #[structopt(short = "o", long = "output")]
+    output: PathBuf,
+}
+
+impl GenerateDeltaArgs {
+    fn run(&self) -> Result<()> {
+        let open = |path: &PathBuf| -> Result<lz4::Decoder<File>> {
+            let file = File::open(path).context(error::ImageOpen { path })?;
+            lz4::Decoder::new(file).context(error::Lz4Decode {
+                target: path.display().to_string(),
+            })
+        };
+        let source = open(&self.source)?;
+        let target = open(&self.target)?;
+
+        let output =
+            File::create(&self.output).context(error::DeltaCreate { path: &self.output })?;
+        let mut encoder = lz4::EncoderBuilder::new()
+            .build(output)
+            .context(error::Lz4Encode { path: &self.output })?;
+        let summary = delta::generate::generate(
+            source,
+            target,
+            &mut encoder,
+            delta::generate::DEFAULT_BLOCK_SIZE,
+        )?;
+        let (_, result) = encoder.finish();
+        result.context(error::Lz4Encode { path: &self.output })?;
+
+        info!(
+            "Wrote delta to {}: {} bytes copied from source, {} bytes included",
+            self.output.display(),
+            summary.copied,
+            summary.included
+        );
+        Ok(())
+    }
+}
+
 #[derive(Debug, StructOpt)]
 struct RemoveUpdateArgs {
     // metadata file to create/modify
@@ -177,6 +290,154 @@ impl WaveArgs {
         if num_matching > 1 {
             warn!("Multiple matching updates for wave - this is weird but not a disaster");
         }
+        for problem in waves.wave_problems() {
+            warn!("{}", problem);
+        }
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct SimulateWavesArgs {
+    // metadata file to read
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-k8s-1.17'
+    #[structopt(short = "l", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    /// Number of hosts in the fleet
+    #[structopt(short = "n", long = "fleet-size", default_value = "2048")]
+    fleet_size: u64,
+
+    /// Start simulating at this RFC3339 datetime, instead of right now
+    #[structopt(long = "start-at")]
+    start_at: Option<DateTime<Utc>>,
+
+    /// Stop simulating at this RFC3339 datetime, instead of when the last wave starts
+    #[structopt(long = "end-at")]
+    end_at: Option<DateTime<Utc>>,
+
+    /// Also check the wave file the waves were set from, for problems the manifest can't show
+    #[structopt(short = "w", long = "wave-file")]
+    wave_file: Option<PathBuf>,
+}
+
+impl SimulateWavesArgs {
+    fn run(self) -> Result<()> {
+        let manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let update = manifest
+            .updates
+            .iter()
+            .find(|update| {
+                update.arch == self.arch
+                    && update.variant == self.variant
+                    && update.version == self.image_version
+            })
+            .context(error::UpdateNotFound {
+                variant: &self.variant,
+                arch: &self.arch,
+                version: self.image_version.clone(),
+            })?;
+
+        let start_at = self.start_at.unwrap_or_else(Utc::now);
+        let end_at = self
+            .end_at
+            .or_else(|| update.waves.values().max().copied())
+            .unwrap_or(start_at);
+
+        println!("{:<25} {:>6} {:>10}", "time", "seeds", "hosts");
+        for progress in update.simulate_waves(start_at, end_at, Duration::hours(1)) {
+            println!(
+                "{:<25} {:>6} {:>10}",
+                progress.time.to_rfc3339(),
+                progress.ready_seeds,
+                progress.ready_hosts(self.fleet_size)
+            );
+        }
+
+        let mut problems = update.wave_problems(end_at);
+        if let Some(wave_file) = &self.wave_file {
+            problems.extend(UpdateWaves::from_path(wave_file)?.wave_problems());
+        }
+        for problem in problems {
+            warn!("{}", problem);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct PauseArgs {
+    // metadata file to modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-k8s-1.17'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    /// Why the rollout is being halted, for the record
+    #[structopt(short = "r", long = "reason")]
+    reason: Option<String>,
+}
+
+impl PauseArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let num_matching = manifest.halt_update(
+            self.variant,
+            self.arch,
+            self.image_version,
+            Utc::now(),
+            self.reason,
+        )?;
+        if num_matching > 1 {
+            warn!("Multiple matching updates halted - this is weird but not a disaster");
+        }
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ResumeArgs {
+    // metadata file to modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-k8s-1.17'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+}
+
+impl ResumeArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        manifest.resume_update(self.variant, self.arch, self.image_version)?;
         update_metadata::write_file(&self.file, &manifest)?;
         Ok(())
     }
@@ -238,8 +499,18 @@ enum Command {
     Init(GeneralArgs),
     /// Add a new update to the manifest, not including wave information
     AddUpdate(AddUpdateArgs),
+    /// Add delta images for an update, for hosts updating from an earlier version
+    AddDelta(AddDeltaArgs),
+    /// Generate a delta between two versions of an image
+    GenerateDelta(GenerateDeltaArgs),
     /// Set waves for an update
     SetWaves(WaveArgs),
+    /// Halt the rollout of an update, so no more hosts update to it
+    Pause(PauseArgs),
+    /// Resume the halted rollout of an update, following its waves again
+    Resume(ResumeArgs),
+    /// Show how many hosts are eligible for an update each hour, and check its waves for problems
+    SimulateWaves(SimulateWavesArgs),
     /// Set the global maximum image version
     SetMaxVersion(MaxVersionArgs),
     /// Remove an update from the manifest, including wave information
@@ -263,7 +534,12 @@ fn main_inner() -> Result<()> {
             }
         }
         Command::AddUpdate(args) => args.run(),
+        Command::AddDelta(args) => args.run(),
+        Command::GenerateDelta(args) => args.run(),
         Command::SetWaves(args) => args.set(),
+        Command::SimulateWaves(args) => args.run(),
+        Command::Pause(args) => args.run(),
+        Command::Resume(args) => args.run(),
         Command::SetMaxVersion(args) => args.run(),
         Command::RemoveUpdate(args) => args.run(),
         Command::SetMigrations(args) => args.set(),
@@ -0,0 +1,147 @@
+//! The apply module builds an image from a delta and the image it was generated against; see
+//! the format module for the layout of a delta.
+
+use super::format::{MAGIC, MAX_BLOCK_SIZE, OP_COPY, OP_DATA, OP_END};
+use crate::error::{self, Result};
+use ring::digest::{Context, SHA256, SHA256_OUTPUT_LEN};
+use snafu::{ensure, ResultExt};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Applies a delta to `source`, writing the resulting image to `out`.  `source` may be longer
+/// than the image the delta was generated against, as with a partition holding a smaller image;
+/// only the length given in the delta is used.  Returns the length and SHA-256 digest of the image
+/// written.
+pub(crate) fn apply<S, D, W>(
+    mut source: S,
+    mut delta: D,
+    out: W,
+) -> Result<(u64, [u8; SHA256_OUTPUT_LEN])>
+where
+    S: Read + Seek,
+    D: Read,
+    W: Write,
+{
+    let mut magic = [0; 8];
+    delta.read_exact(&mut magic).context(error::DeltaRead)?;
+    ensure!(&magic == MAGIC, error::DeltaBadMagic);
+    let block_size = read_u32(&mut delta)?;
+    ensure!(
+        block_size > 0 && block_size <= MAX_BLOCK_SIZE,
+        error::DeltaBlockSize { block_size }
+    );
+    let source_len = read_u64(&mut delta)?;
+    let source_digest = read_digest(&mut delta)?;
+
+    // Make sure we have the source the delta expects before writing anything.
+    let mut digest = DigestWriter::new(io::sink());
+    let source_read = io::copy(&mut (&mut source).take(source_len), &mut digest)
+        .context(error::DeltaSourceRead)?;
+    ensure!(
+        source_read == source_len && digest.finish().as_ref() == source_digest,
+        error::DeltaSourceMismatch
+    );
+
+    let mut out = DigestWriter::new(out);
+    loop {
+        match read_u8(&mut delta)? {
+            OP_COPY => {
+                let start = read_u64(&mut delta)?;
+                let count = read_u32(&mut delta)?;
+                let len = u64::from(count) * u64::from(block_size);
+                let offset = start.checked_mul(u64::from(block_size));
+                let end = offset.and_then(|offset| offset.checked_add(len));
+                let offset = match (offset, end) {
+                    (Some(offset), Some(end)) if end <= source_len => offset,
+                    _ => return error::DeltaSourceRange { start, count }.fail(),
+                };
+                source
+                    .seek(SeekFrom::Start(offset))
+                    .context(error::DeltaSourceRead)?;
+                let copied =
+                    io::copy(&mut (&mut source).take(len), &mut out).context(error::DeltaWrite)?;
+                ensure!(copied == len, error::DeltaSourceRange { start, count });
+            }
+            OP_DATA => {
+                let len = u64::from(read_u32(&mut delta)?);
+                let copied =
+                    io::copy(&mut (&mut delta).take(len), &mut out).context(error::DeltaWrite)?;
+                ensure!(copied == len, error::DeltaTruncated);
+            }
+            OP_END => break,
+            op => return error::DeltaBadOp { op }.fail(),
+        }
+    }
+
+    let target_len = read_u64(&mut delta)?;
+    let target_digest = read_digest(&mut delta)?;
+    // Read to the end of the delta, so a reader that verifies its input when it reaches the end,
+    // like a TUF target, gets the chance to.
+    let trailing = io::copy(&mut delta, &mut io::sink()).context(error::DeltaRead)?;
+    ensure!(trailing == 0, error::DeltaTrailingData);
+    out.flush().context(error::DeltaWrite)?;
+    let written = out.len;
+    ensure!(
+        written == target_len && out.finish().as_ref() == target_digest,
+        error::DeltaTargetMismatch
+    );
+    Ok((written, target_digest))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf).context(error::DeltaRead)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf).context(error::DeltaRead)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf).context(error::DeltaRead)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_digest<R: Read>(reader: &mut R) -> Result<[u8; SHA256_OUTPUT_LEN]> {
+    let mut buf = [0; SHA256_OUTPUT_LEN];
+    reader.read_exact(&mut buf).context(error::DeltaRead)?;
+    Ok(buf)
+}
+
+/// `DigestWriter` passes writes through to another writer, keeping a digest and length of
+/// everything written.
+struct DigestWriter<W> {
+    inner: W,
+    digest: Context,
+    len: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            digest: Context::new(&SHA256),
+            len: 0,
+        }
+    }
+
+    fn finish(self) -> ring::digest::Digest {
+        self.digest.finish()
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.digest.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,48 @@
+//! The delta format module describes binary deltas between two versions of a partition
+//! image.  Most blocks of an image are unchanged between nearby releases, so a host that already
+//! has the older image in its active partition set can build the new image from those blocks plus
+//! the few that changed, rather than downloading the whole thing.
+//!
+//! A delta is a header, a series of operations, and a trailer.  All integers are little-endian.
+//!
+//! * Header: the magic bytes `BRDELTA1`, the block size (u32), and the length (u64) and SHA-256
+//!   digest (32 bytes) of the source image the delta was generated against.
+//! * Operations, each starting with a one-byte tag:
+//!   * `COPY`: a starting block number in the source (u64) and a count of blocks (u32) to copy to
+//!     the output.
+//!   * `DATA`: a length (u32) followed by that many bytes to write to the output.
+//!   * `END`: no further operations.
+//! * Trailer: the length (u64) and SHA-256 digest (32 bytes) of the resulting image.
+//!
+//! Before applying a delta, we check that the source matches the digest in the header, and after
+//! applying it we check the output against the trailer, so a delta that doesn't match the host
+//! results in an error rather than a bad image.  Deltas are published lz4-compressed, like other
+//! update images.
+//!
+//! Generating and applying deltas are in separate modules, so updog, which applies them, and
+//! updata, which generates them, each build only the one they use.
+
+use std::io::{self, Read};
+
+pub(crate) const MAGIC: &[u8; 8] = b"BRDELTA1";
+
+/// Limits the size of a single block, so a bad header can't make us allocate huge buffers.
+pub(crate) const MAX_BLOCK_SIZE: u32 = 1024 * 1024;
+
+pub(crate) const OP_END: u8 = 0;
+pub(crate) const OP_COPY: u8 = 1;
+pub(crate) const OP_DATA: u8 = 2;
+
+/// Fills `buf` from `reader`, returning less than its length only at the end of the input.
+pub(crate) fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,259 @@
+//! The generate module builds a delta that turns one image into another; see the format module
+//! for the layout of a delta.
+
+use super::format::{read_block, MAGIC, MAX_BLOCK_SIZE, OP_COPY, OP_DATA, OP_END};
+use crate::error::{self, Result};
+use ring::digest::{Context, SHA256, SHA256_OUTPUT_LEN};
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+/// The size of the blocks we compare; filesystem images are written in blocks of this size, so
+/// unchanged files stay aligned to it.
+pub(crate) const DEFAULT_BLOCK_SIZE: u32 = 4096;
+
+/// Limits the length of a single DATA operation when generating a delta.
+const MAX_DATA_LEN: usize = 1024 * 1024;
+
+/// `DeltaSummary` describes how a delta builds its target image.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DeltaSummary {
+    /// Bytes of the target copied from the source.
+    pub(crate) copied: u64,
+    /// Bytes of the target included in the delta.
+    pub(crate) included: u64,
+}
+
+/// An operation that hasn't been written yet, so that adjacent blocks can be merged into it.
+enum PendingOp {
+    None,
+    Copy { start: u64, count: u32 },
+    Data(Vec<u8>),
+}
+
+/// Generates a delta that turns `source` into `target`, writing it to `out`.
+///
+/// Both images are streamed.  The source is indexed by the SHA-256 digest of each of its blocks,
+/// so memory use is about 1% of the source image's size with the default block size.
+pub(crate) fn generate<S, T, W>(
+    mut source: S,
+    mut target: T,
+    mut out: W,
+    block_size: u32,
+) -> Result<DeltaSummary>
+where
+    S: Read,
+    T: Read,
+    W: Write,
+{
+    ensure!(
+        block_size > 0 && block_size <= MAX_BLOCK_SIZE,
+        error::DeltaBlockSize { block_size }
+    );
+    // Index the source by block contents; the first block with given contents is as good as any.
+    // Only whole blocks can be copied, so a partial block at the end isn't indexed.
+    let mut index: HashMap<[u8; SHA256_OUTPUT_LEN], u64> = HashMap::new();
+    let mut source_digest = Context::new(&SHA256);
+    let mut source_len = 0_u64;
+    let mut block = vec![0; block_size as usize];
+    for i in 0.. {
+        let len = read_block(&mut source, &mut block).context(error::DeltaSourceRead)?;
+        if len == 0 {
+            break;
+        }
+        source_digest.update(&block[..len]);
+        source_len += len as u64;
+        if len == block.len() {
+            index.entry(sha256(&block)).or_insert(i);
+        }
+    }
+
+    out.write_all(MAGIC).context(error::DeltaWrite)?;
+    out.write_all(&block_size.to_le_bytes())
+        .context(error::DeltaWrite)?;
+    out.write_all(&source_len.to_le_bytes())
+        .context(error::DeltaWrite)?;
+    out.write_all(source_digest.finish().as_ref())
+        .context(error::DeltaWrite)?;
+
+    let mut summary = DeltaSummary::default();
+    let mut target_digest = Context::new(&SHA256);
+    let mut target_len = 0_u64;
+    let mut pending = PendingOp::None;
+    loop {
+        let len = read_block(&mut target, &mut block).context(error::DeltaTargetRead)?;
+        if len == 0 {
+            break;
+        }
+        let block = &block[..len];
+        target_digest.update(block);
+        target_len += len as u64;
+
+        match (index.get(&sha256(block)), &mut pending) {
+            (Some(&found), PendingOp::Copy { start, count })
+                if *start + u64::from(*count) == found && *count < u32::MAX =>
+            {
+                *count += 1;
+            }
+            (Some(&found), _) => {
+                write_op(&mut out, &pending, block_size, &mut summary)?;
+                pending = PendingOp::Copy {
+                    start: found,
+                    count: 1,
+                };
+            }
+            (None, PendingOp::Data(data)) if data.len() + block.len() <= MAX_DATA_LEN => {
+                data.extend_from_slice(block);
+            }
+            (None, _) => {
+                write_op(&mut out, &pending, block_size, &mut summary)?;
+                pending = PendingOp::Data(block.to_vec());
+            }
+        }
+    }
+    write_op(&mut out, &pending, block_size, &mut summary)?;
+
+    out.write_all(&[OP_END]).context(error::DeltaWrite)?;
+    out.write_all(&target_len.to_le_bytes())
+        .context(error::DeltaWrite)?;
+    out.write_all(target_digest.finish().as_ref())
+        .context(error::DeltaWrite)?;
+    Ok(summary)
+}
+
+fn write_op<W: Write>(
+    out: &mut W,
+    op: &PendingOp,
+    block_size: u32,
+    summary: &mut DeltaSummary,
+) -> Result<()> {
+    match op {
+        PendingOp::None => {}
+        PendingOp::Copy { start, count } => {
+            out.write_all(&[OP_COPY]).context(error::DeltaWrite)?;
+            out.write_all(&start.to_le_bytes())
+                .context(error::DeltaWrite)?;
+            out.write_all(&count.to_le_bytes())
+                .context(error::DeltaWrite)?;
+            summary.copied += u64::from(*count) * u64::from(block_size);
+        }
+        PendingOp::Data(data) => {
+            out.write_all(&[OP_DATA]).context(error::DeltaWrite)?;
+            // Data is limited to MAX_DATA_LEN, which fits.
+            let len = u32::try_from(data.len()).expect("DATA operation longer than MAX_DATA_LEN");
+            out.write_all(&len.to_le_bytes())
+                .context(error::DeltaWrite)?;
+            out.write_all(data).context(error::DeltaWrite)?;
+            summary.included += data.len() as u64;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; SHA256_OUTPUT_LEN] {
+    let mut digest = [0; SHA256_OUTPUT_LEN];
+    digest.copy_from_slice(ring::digest::digest(&SHA256, data).as_ref());
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::apply::apply;
+    use std::io::{self, Cursor};
+
+    /// Returns an image of `blocks` blocks of 16 bytes, each filled with its block number.
+    fn image(blocks: u8) -> Vec<u8> {
+        (0..blocks).flat_map(|b| vec![b; 16]).collect()
+    }
+
+    fn round_trip(source: &[u8], target: &[u8]) -> DeltaSummary {
+        let mut delta = Vec::new();
+        let summary = generate(source, target, &mut delta, 16).unwrap();
+        let mut out = Vec::new();
+        let (len, digest) = apply(Cursor::new(source), &delta[..], &mut out).unwrap();
+        assert_eq!(len, target.len() as u64);
+        assert_eq!(digest, sha256(target));
+        assert_eq!(out, target);
+        summary
+    }
+
+    #[test]
+    fn unchanged_blocks_are_copied() {
+        let source = image(8);
+        let mut target = source.clone();
+        // Change one block, move two others, and add a partial block at the end.
+        target[40] = 0xff;
+        target[64..96].copy_from_slice(&source[0..32]);
+        target.extend_from_slice(b"tail");
+
+        let summary = round_trip(&source, &target);
+        assert_eq!(
+            summary,
+            DeltaSummary {
+                copied: 16 * 7,
+                included: 16 + 4,
+            }
+        );
+    }
+
+    #[test]
+    fn different_sizes() {
+        round_trip(&image(8), &image(3));
+        round_trip(&image(3), &image(8));
+        round_trip(&[], &image(2));
+        round_trip(&image(2), &[]);
+    }
+
+    #[test]
+    fn longer_source() {
+        // A partition can be bigger than the image written to it.
+        let source = image(4);
+        let mut delta = Vec::new();
+        generate(&source[..], &image(5)[..], &mut delta, 16).unwrap();
+
+        let mut partition = source;
+        partition.extend_from_slice(&[0xaa; 100]);
+        let mut out = Vec::new();
+        apply(Cursor::new(partition), &delta[..], &mut out).unwrap();
+        assert_eq!(out, image(5));
+    }
+
+    #[test]
+    fn wrong_source() {
+        let mut delta = Vec::new();
+        generate(&image(4)[..], &image(5)[..], &mut delta, 16).unwrap();
+
+        let mut source = image(4);
+        source[0] = 0xff;
+        let mut out = Vec::new();
+        assert!(apply(Cursor::new(source), &delta[..], &mut out).is_err());
+        assert!(out.is_empty(), "wrote output for the wrong source");
+    }
+
+    #[test]
+    fn corrupt_delta() {
+        let source = image(4);
+        let mut target = image(5);
+        target[70] = 0xee;
+        let mut delta = Vec::new();
+        generate(&source[..], &target[..], &mut delta, 16).unwrap();
+
+        // Corrupt the last byte of included data, just before the END op and trailer.
+        let data_end = delta.len() - 1 - 8 - SHA256_OUTPUT_LEN - 1;
+        delta[data_end] ^= 1;
+        assert!(apply(Cursor::new(&source), &delta[..], io::sink()).is_err());
+
+        // Deltas with extra data at the end fail.
+        delta[data_end] ^= 1;
+        apply(Cursor::new(&source), &delta[..], io::sink()).unwrap();
+        delta.push(0);
+        assert!(apply(Cursor::new(&source), &delta[..], io::sink()).is_err());
+        delta.pop();
+
+        // Truncated deltas fail too.
+        delta.truncate(delta.len() - 1);
+        assert!(apply(Cursor::new(&source), &delta[..], io::sink()).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! The download module saves update image targets to disk before they're written to partitions,
+//! so an interrupted download can pick up where it left off rather than starting over.
+//!
+//! A target is downloaded to a file named for its SHA-256 digest, as listed in the repository's
+//! signed targets metadata.  We read the target through the repository like any other, so tough
+//! checks its length and digest against the metadata, but the transport saves it to the file as
+//! it's fetched; see `HttpQueryTransport::save_download`.  If the file is already there from an
+//! earlier attempt, the transport gives the bytes it holds first and only fetches the rest.  A
+//! download that ends early is kept to be resumed, but if the complete file doesn't verify, it's
+//! removed, so the next attempt starts over.
+//!
+//! Downloads are kept in a directory for the version being updated to, and removed once the
+//! update is written, or when we start an update to a different version or find none is needed.
+
+use crate::error::{self, Result};
+use crate::progress::hex;
+use crate::transport::{HttpQueryRepo, HttpQueryTransport};
+use semver::Version;
+use snafu::{OptionExt, ResultExt};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Downloads `target` from the repository into `dir`, resuming any earlier attempt.  Returns the
+/// verified target, opened for reading from the start, and its path, so the caller can remove it
+/// once it's no longer needed.
+pub(crate) fn download_target(
+    repository: &HttpQueryRepo<'_>,
+    transport: &HttpQueryTransport,
+    targets_base_url: &str,
+    target: &str,
+    dir: &Path,
+) -> Result<(File, PathBuf)> {
+    let target_info = repository
+        .targets()
+        .signed
+        .targets
+        .get(target)
+        .context(error::TargetNotFound { target })?;
+    let sha256 = hex(target_info.hashes.sha256.as_ref());
+    // With consistent snapshots, targets are stored under names prefixed with their digest.
+    let filename = if repository.root().signed.consistent_snapshot {
+        format!("{}.{}", sha256, target)
+    } else {
+        target.to_string()
+    };
+    // tough treats the base URL as a directory, so we do too, so the transport sees the same URL
+    // from tough that we ask it to save.
+    let mut base_url = targets_base_url.to_string();
+    if !base_url.ends_with('/') {
+        base_url.push('/');
+    }
+    let url = Url::parse(&base_url)
+        .and_then(|base| base.join(&filename))
+        .context(error::TargetUrl { target })?;
+
+    fs::create_dir_all(dir).context(error::DirCreate { path: dir })?;
+    let path = dir.join(&sha256);
+    transport.save_download(url, path.clone());
+    if let Err(e) = read_target(repository, target) {
+        discard_if_complete(&path, target_info.length)?;
+        return Err(e);
+    }
+
+    let file = File::open(&path).context(error::DownloadWrite { path: &path })?;
+    Ok((file, path))
+}
+
+/// Reads the target through the repository, which verifies it against the metadata.
+fn read_target(repository: &HttpQueryRepo<'_>, target: &str) -> Result<()> {
+    let mut reader = repository
+        .read_target(target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound { target })?;
+    io::copy(&mut reader, &mut io::sink()).context(error::DownloadRead { target })?;
+    Ok(())
+}
+
+/// Removes the download at `path` after it failed to verify, if it holds at least `length`
+/// bytes; it's complete but wrong, so the next attempt has to start over.  A shorter download
+/// is kept to be resumed.
+fn discard_if_complete(path: &Path, length: u64) -> Result<()> {
+    let saved = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(error::DownloadWrite { path }),
+    };
+    if saved >= length {
+        fs::remove_file(path).context(error::DownloadWrite { path })?;
+    }
+    Ok(())
+}
+
+/// Returns the directory under `base` for downloads of the given version.
+pub(crate) fn version_dir(base: &Path, version: &Version) -> PathBuf {
+    base.join(version.to_string())
+}
+
+/// Removes the downloads under `base`, except those for the version given in `keep`.  Downloads
+/// for other versions can't be used once we've moved on from them.
+pub(crate) fn remove_downloads(base: &Path, keep: Option<&Version>) -> Result<()> {
+    let entries = match fs::read_dir(base) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(error::DownloadWrite { path: base }),
+    };
+    let keep = keep.map(|version| version_dir(base, version));
+    for entry in entries {
+        let path = entry.context(error::DownloadWrite { path: base })?.path();
+        if Some(&path) == keep.as_ref() {
+            continue;
+        }
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.context(error::DownloadWrite { path: &path })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn bad_download_removed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("download");
+
+        // A download that ended early is kept to be resumed.
+        fs::write(&path, vec![0; 1000]).unwrap();
+        discard_if_complete(&path, 2000).unwrap();
+        assert!(path.exists());
+
+        // A complete one that doesn't verify is removed.
+        discard_if_complete(&path, 1000).unwrap();
+        assert!(!path.exists());
+        discard_if_complete(&path, 1000).unwrap();
+    }
+
+    #[test]
+    fn old_downloads_removed() {
+        let dir = TempDir::new().unwrap();
+        let old = Version::new(1, 0, 0);
+        let new = Version::new(1, 1, 0);
+        for version in &[&old, &new] {
+            let version_dir = version_dir(dir.path(), version);
+            fs::create_dir_all(&version_dir).unwrap();
+            fs::write(version_dir.join("target"), "partial").unwrap();
+        }
+        // Downloads from before they were kept by version
+        fs::write(dir.path().join("target"), "partial").unwrap();
+
+        remove_downloads(dir.path(), Some(&new)).unwrap();
+        let remaining: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(remaining, vec![version_dir(dir.path(), &new)]);
+
+        remove_downloads(dir.path(), None).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        remove_downloads(&dir.path().join("missing"), None).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(clippy::default_trait_access)]
 
+use chrono::{DateTime, Utc};
 use semver::Version;
 use snafu::{Backtrace, Snafu};
 use std::path::PathBuf;
@@ -60,6 +61,72 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Delta is not in a recognized format"))]
+    DeltaBadMagic { backtrace: Backtrace },
+
+    #[snafu(display("Delta contains unknown operation {}", op))]
+    DeltaBadOp { op: u8, backtrace: Backtrace },
+
+    #[snafu(display("Invalid delta block size {}", block_size))]
+    DeltaBlockSize {
+        block_size: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to create delta file {}: {}", path.display(), source))]
+    DeltaCreate {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read delta: {}", source))]
+    DeltaRead {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Source image does not match the one the delta was generated against"))]
+    DeltaSourceMismatch { backtrace: Backtrace },
+
+    #[snafu(display(
+        "Delta copies {} blocks starting at block {}, beyond the end of the source",
+        count,
+        start
+    ))]
+    DeltaSourceRange {
+        start: u64,
+        count: u32,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read delta source image: {}", source))]
+    DeltaSourceRead {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Image produced by delta does not match the expected image"))]
+    DeltaTargetMismatch { backtrace: Backtrace },
+
+    #[snafu(display("Failed to read delta target image: {}", source))]
+    DeltaTargetRead {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Delta has unexpected data after its end"))]
+    DeltaTrailingData { backtrace: Backtrace },
+
+    #[snafu(display("Delta ended unexpectedly"))]
+    DeltaTruncated { backtrace: Backtrace },
+
+    #[snafu(display("Failed to write delta output: {}", source))]
+    DeltaWrite {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to create directory: {:?}", path))]
     DirCreate {
         backtrace: Backtrace,
@@ -67,6 +134,50 @@ pub(crate) enum Error {
         path: PathBuf,
     },
 
+    #[snafu(display("Failed to read {} while downloading it: {}", target, source))]
+    DownloadRead {
+        target: String,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to save download to {}: {}", path.display(), source))]
+    DownloadWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read GRUB configuration {}: {}", path.display(), source))]
+    GrubConfigRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Image in partition {} was not completely written", path.display()))]
+    ImageIncomplete { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Failed to open image {}: {}", path.display(), source))]
+    ImageOpen {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Image in partition {} does not match what was written; expected SHA-256 {}, found {}",
+        path.display(),
+        expected,
+        found
+    ))]
+    ImageVerify {
+        path: PathBuf,
+        expected: String,
+        found: String,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Logger setup error: {}", source))]
     Logger { source: simplelog::TermLogError },
 
@@ -94,6 +205,13 @@ pub(crate) enum Error {
     #[snafu(display("Could not determine loop device path"))]
     LoopNameFailed { backtrace: Backtrace },
 
+    #[snafu(display("Failed to write LZ4-compressed file {}: {}", path.display(), source))]
+    Lz4Encode {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to decode LZ4-compressed target {}: {}", target, source))]
     Lz4Decode {
         target: String,
@@ -136,6 +254,13 @@ pub(crate) enum Error {
         version: String,
     },
 
+    #[snafu(display("Failed to mount {}: {}", device.display(), stderr))]
+    Mount {
+        device: PathBuf,
+        stderr: String,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Temporary image mount failed"))]
     MountFailed {
         backtrace: Backtrace,
@@ -175,6 +300,57 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to read write progress from {}: {}", path.display(), source))]
+    ProgressRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to serialize write progress: {}", source))]
+    ProgressSerialize {
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to save write progress to {}: {}", path.display(), source))]
+    ProgressWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read partition {}: {}", path.display(), source))]
+    ReadPartition {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to find repository directory {}: {}", path.display(), source))]
+    RepoDir {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to build a URL for repository directory {}", path.display()))]
+    RepoDirUrl { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display(
+        "Outside of update maintenance windows; the next one opens at {}",
+        next
+    ))]
+    OutsideMaintenanceWindow {
+        next: DateTime<Utc>,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to check update maintenance windows: {}", source))]
+    MaintenanceWindow {
+        source: model::modeled_types::error::Error,
+    },
+
     #[snafu(display("Failed to reboot: {}", source))]
     RebootFailure {
         source: std::io::Error,
@@ -197,12 +373,49 @@ pub(crate) enum Error {
         source: std::io::Error,
     },
 
+    #[snafu(display("Failed to parse staged images from {}: {}", path.display(), source))]
+    StagedParse {
+        path: PathBuf,
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read staged images from {}: {}", path.display(), source))]
+    StagedRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Unable to read staged os-release: {}", source))]
+    StagedRelease { source: bottlerocket_release::Error },
+
+    #[snafu(display("Failed to serialize staged images: {}", source))]
+    StagedSerialize {
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to save staged images to {}: {}", path.display(), source))]
+    StagedWrite {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Target not found: {}", target))]
     TargetNotFound {
         target: String,
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to build URL for target {}: {}", target, source))]
+    TargetUrl {
+        target: String,
+        source: url::ParseError,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Failed to create tmpfile for root mount"))]
     TmpFileCreate {
         backtrace: Backtrace,
@@ -218,6 +431,14 @@ pub(crate) enum Error {
     #[snafu(display("No update available"))]
     UpdateNotAvailable { backtrace: Backtrace },
 
+    #[snafu(display("No update for {} {} {} in manifest", variant, arch, version))]
+    UpdateNotFound {
+        backtrace: Backtrace,
+        variant: String,
+        arch: String,
+        version: Version,
+    },
+
     #[snafu(display("Update {} exists but wave in the future", version))]
     UpdateNotReady {
         backtrace: Backtrace,
@@ -239,6 +460,9 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("No dm-verity root hash found in {}", path.display()))]
+    VerityRootHashMissing { path: PathBuf, backtrace: Backtrace },
+
     #[snafu(display("--wave-file <path> required to add waves to update"))]
     WaveFileArg { backtrace: Backtrace },
 
@@ -0,0 +1,297 @@
+//! The inspect module describes the update staged in the inactive partition set, so it can be
+//! checked before the update is applied.
+//!
+//! The inactive boot and root partitions are mounted read-only, without replaying their journals,
+//! to read the staged os-release and the dm-verity root hash the staged image will boot with.
+//! The partitions are then checked against the staged images recorded when they were written: each
+//! must hold a target that the manifest lists for the staged version, whose hash matches the one
+//! in the repository, and the partition must still hold the image exactly as it was written.
+
+use crate::error::{self, Result};
+use crate::progress::StagedImages;
+use crate::transport::HttpQueryRepo;
+use crate::{target_sha256, TARGET_ARCH};
+use bottlerocket_release::BottlerocketRelease;
+use serde::Serialize;
+use signpost::PartitionSet;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+use update_metadata::Manifest;
+
+/// The path of the GRUB configuration in a boot partition.
+const GRUB_CONFIG: &str = "grub/grub.cfg";
+
+/// `Inspection` describes the update staged in the inactive partition set.
+#[derive(Debug, Serialize)]
+pub(crate) struct Inspection {
+    /// The staged os-release, if it could be read.
+    pub(crate) os_release: Option<BottlerocketRelease>,
+    /// The dm-verity root hash the staged image boots with, if it could be read.
+    pub(crate) verity_root_hash: Option<String>,
+    /// Whether every check passed; if not, `problems` says why.
+    pub(crate) verified: bool,
+    pub(crate) problems: Vec<String>,
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.os_release {
+            Some(release) => writeln!(
+                f,
+                "Staged image: {} ({}, build {})",
+                release.pretty_name, release.variant_id, release.build_id
+            )?,
+            None => writeln!(f, "Staged image: unknown")?,
+        }
+        writeln!(
+            f,
+            "Verity root hash: {}",
+            self.verity_root_hash.as_deref().unwrap_or("unknown")
+        )?;
+        if self.verified {
+            write!(f, "Verified against the manifest")
+        } else {
+            write!(f, "Not verified:")?;
+            for problem in &self.problems {
+                write!(f, "\n  {}", problem)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Inspects the update staged in the `inactive` partition set, checking its images against the
+/// `manifest` and the record of staged images in `staged_path`.
+pub(crate) fn inspect(
+    repository: &HttpQueryRepo<'_>,
+    manifest: &Manifest,
+    inactive: &PartitionSet,
+    staged_path: &Path,
+) -> Inspection {
+    let mut problems = Vec::new();
+    let verity_root_hash = read_verity_root_hash(&inactive.boot)
+        .map_err(|e| problems.push(e.to_string()))
+        .ok();
+    let os_release = read_os_release(&inactive.root)
+        .map_err(|e| problems.push(e.to_string()))
+        .ok();
+    if let Some(os_release) = &os_release {
+        match check_images(repository, manifest, inactive, os_release, staged_path) {
+            Ok(found) => problems.extend(found),
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+
+    Inspection {
+        os_release,
+        verity_root_hash,
+        verified: problems.is_empty(),
+        problems,
+    }
+}
+
+/// Returns descriptions of any problems found checking the partitions of the `inactive` set
+/// against the manifest's images for the staged version.
+fn check_images(
+    repository: &HttpQueryRepo<'_>,
+    manifest: &Manifest,
+    inactive: &PartitionSet,
+    os_release: &BottlerocketRelease,
+    staged_path: &Path,
+) -> Result<Vec<String>> {
+    let staged = match StagedImages::load(staged_path)? {
+        Some(staged) => staged,
+        None => return Ok(vec!["No images were recorded as staged".to_string()]),
+    };
+    let update = manifest.updates.iter().find(|u| {
+        u.variant == os_release.variant_id
+            && u.arch == TARGET_ARCH
+            && u.version == os_release.version_id
+    });
+    let update = match update {
+        Some(update) => update,
+        None => {
+            return Ok(vec![format!(
+                "Staged version {} of {} is not in the manifest",
+                os_release.version_id, os_release.variant_id
+            )])
+        }
+    };
+
+    let mut problems = Vec::new();
+    if staged.version != os_release.version_id {
+        problems.push(format!(
+            "Images were staged for version {}, but os-release says {}",
+            staged.version, os_release.version_id
+        ));
+    }
+
+    // A partition may hold the full image, or the result of applying any of the update's deltas.
+    let deltas = update.images.deltas.values();
+    let partitions = [
+        (
+            &inactive.root,
+            &update.images.root,
+            deltas.clone().map(|d| &d.root).collect::<Vec<_>>(),
+        ),
+        (
+            &inactive.boot,
+            &update.images.boot,
+            deltas.clone().map(|d| &d.boot).collect(),
+        ),
+        (
+            &inactive.hash,
+            &update.images.hash,
+            deltas.map(|d| &d.hash).collect(),
+        ),
+    ];
+    for (partition, full, deltas) in &partitions {
+        let image = if let Some(image) = staged.partitions.get(*partition) {
+            image
+        } else {
+            problems.push(format!(
+                "No image was recorded as staged in {}",
+                partition.display()
+            ));
+            continue;
+        };
+        if image.target != **full && !deltas.contains(&&image.target) {
+            problems.push(format!(
+                "{} holds {}, which the manifest doesn't list for version {}",
+                partition.display(),
+                image.target,
+                update.version
+            ));
+            continue;
+        }
+        match target_sha256(repository, &image.target) {
+            Ok(sha256) if sha256 == image.target_sha256 => {}
+            Ok(sha256) => problems.push(format!(
+                "{} was written from {} with hash {}, but the repository lists {}",
+                partition.display(),
+                image.target,
+                image.target_sha256,
+                sha256
+            )),
+            Err(e) => problems.push(e.to_string()),
+        }
+        if let Err(e) = staged.verify(partition) {
+            problems.push(e.to_string());
+        }
+    }
+    Ok(problems)
+}
+
+/// Reads the dm-verity root hash from the GRUB configuration in the given boot partition.
+fn read_verity_root_hash(boot: &Path) -> Result<String> {
+    let mount = Mount::new(boot)?;
+    let path = mount.path().join(GRUB_CONFIG);
+    let grub_config = fs::read_to_string(&path).context(error::GrubConfigRead { path: &path })?;
+    verity_root_hash(&grub_config).context(error::VerityRootHashMissing { path })
+}
+
+/// Reads os-release from the given root partition.
+fn read_os_release(root: &Path) -> Result<BottlerocketRelease> {
+    let mount = Mount::new(root)?;
+    let path = mount
+        .path()
+        .join(format!("{}-bottlerocket-linux-gnu", TARGET_ARCH))
+        .join("sys-root/usr/lib/os-release");
+    BottlerocketRelease::from_file(path).context(error::StagedRelease)
+}
+
+/// Finds the dm-verity root hash in a GRUB configuration, in the `dm-mod.create` table that sets
+/// up the root device.
+fn verity_root_hash(grub_config: &str) -> Option<String> {
+    // The kernel command line is split over lines with backslash continuations.
+    let grub_config = grub_config.replace("\\\n", " ");
+    // The verity target's arguments are its version, data device, hash device, data block size,
+    // hash block size, number of data blocks, hash start block, and algorithm, then the root hash.
+    grub_config
+        .split_whitespace()
+        .skip_while(|token| *token != "verity")
+        .nth(9)
+        .filter(|hash| !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_string)
+}
+
+/// Mount is a read-only mount of a partition in a temporary directory, unmounted when dropped.
+/// The journal isn't loaded, because replaying it would write to the partition and break the
+/// dm-verity hash of the staged root.
+struct Mount {
+    dir: TempDir,
+}
+
+impl Mount {
+    fn new(device: &Path) -> Result<Self> {
+        let dir = TempDir::new().context(error::CreateTempDir)?;
+        let output = Command::new("mount")
+            .args(&["-t", "ext4", "-o", "ro,noload"])
+            .arg(device)
+            .arg(dir.path())
+            .output()
+            .context(error::MountFailed)?;
+        ensure!(
+            output.status.success(),
+            error::Mount {
+                device,
+                stderr: String::from_utf8_lossy(&output.stderr).trim(),
+            }
+        );
+        Ok(Self { dir })
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.path().to_owned()
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        match Command::new("umount").arg(self.dir.path()).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "Failed to unmount {}: {}",
+                self.dir.path().display(),
+                status
+            ),
+            Err(e) => eprintln!("Failed to unmount {}: {}", self.dir.path().display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grub_root_hash() {
+        let grub_config = r#"set default="0"
+set timeout="0"
+
+menuentry "Bottlerocket OS 0.4.1" {
+   linux ($root)/vmlinuz root=/dev/dm-0 rootwait ro \
+       console=tty0 console=ttyS0 random.trust_cpu=on selinux=1 enforcing=1 \
+       systemd.log_target=journal-or-kmsg systemd.log_color=0 net.ifnames=0 \
+       biosdevname=0 dm_verity.max_bios=-1 dm_verity.dev_wait=1 \
+       dm-mod.create="root,,,ro,0 1884160 verity 1 PARTUUID=$boot_uuid/PARTNROFF=1 PARTUUID=$boot_uuid/PARTNROFF=2 \
+       4096 4096 235520 1 sha256 3f6a0be0d5c9fe1b4b4a0f2a6f3b6b53e8ea3c0e9f2a5e0c6cb5a4e9f2f0e1d2 58ba44e2bf2d4c6f 1 restart_on_corruption"
+}
+"#;
+        assert_eq!(
+            verity_root_hash(grub_config).unwrap(),
+            "3f6a0be0d5c9fe1b4b4a0f2a6f3b6b53e8ea3c0e9f2a5e0c6cb5a4e9f2f0e1d2"
+        );
+
+        assert!(verity_root_hash("linux ($root)/vmlinuz root=/dev/sda3 ro").is_none());
+        // A truncated table doesn't give us some other token instead.
+        assert!(verity_root_hash(
+            "dm-mod.create=\"root,,,ro,0 8 verity 1 a b 4096 4096 1 1 sha256\""
+        )
+        .is_none());
+    }
+}
@@ -1,30 +1,43 @@
 #![deny(rust_2018_idioms)]
 #![warn(clippy::pedantic)]
 
+/// updog only applies deltas; updata generates them.
+mod delta {
+    pub(crate) mod apply;
+    pub(crate) mod format;
+}
+mod download;
 mod error;
+mod inspect;
+mod progress;
 mod transport;
 
 use crate::error::Result;
-use crate::transport::{HttpQueryRepo, HttpQueryTransport};
+use crate::progress::{ImageDigest, Progress, StagedImages};
+use crate::transport::{DownloadLimits, HttpQueryRepo, HttpQueryTransport};
 use bottlerocket_release::BottlerocketRelease;
-use chrono::Utc;
-use model::modeled_types::FriendlyVersion;
+use chrono::{DateTime, Utc};
+use model::modeled_types::{
+    next_maintenance_window, ExactVersion, FriendlyVersion, MaintenanceWindow,
+};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use signal_hook::{iterator::Signals, SIGTERM};
 use signpost::State;
 use simplelog::{Config as LogConfig, LevelFilter, TermLogger, TerminalMode};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
 use std::convert::{TryFrom, TryInto};
 use std::fs::{self, File, OpenOptions};
-use std::io;
-use std::path::Path;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 use tough::{ExpirationEnforcement, Limits, Repository, Settings};
 use update_metadata::{find_migrations, load_manifest, Manifest, Update};
+use url::Url;
 
 #[cfg(target_arch = "x86_64")]
 const TARGET_ARCH: &str = "x86_64";
@@ -40,6 +53,17 @@ const MIGRATION_PATH: &str = "/var/lib/bottlerocket-migrations";
 /// This is where we store the TUF metadata used by migrator after reboot.
 const METADATA_PATH: &str = "/var/cache/bottlerocket-metadata";
 
+/// This is where we record progress writing update images, so an interrupted update can resume.
+const PROGRESS_PATH: &str = "/var/lib/updog/write-progress.json";
+
+/// This is where we keep update image targets while they're downloaded and written, so an
+/// interrupted download can resume.
+const DOWNLOAD_PATH: &str = "/var/lib/updog/downloads";
+
+/// This is where we record the images staged in the inactive partition set, so they can be
+/// inspected before the update is applied.
+const STAGED_PATH: &str = "/var/lib/updog/staged-images.json";
+
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 enum Command {
@@ -50,6 +74,7 @@ enum Command {
     UpdateImage,
     UpdateApply,
     UpdateRevert,
+    Inspect,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,6 +84,14 @@ struct Config {
     seed: u32,
     version_lock: String,
     ignore_waves: bool,
+    #[serde(default)]
+    maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    skip_versions: Vec<String>,
+    minimum_version: Option<String>,
+    max_download_bytes_per_second: Option<u64>,
+    #[serde(default)]
+    download_start_jitter_seconds: u64,
     // TODO API sourced configuration, eg.
     // blacklist: Option<Vec<Version>>,
     // mode: Option<{Automatic, Managed, Disabled}>
@@ -79,6 +112,8 @@ USAGE:
 SUBCOMMANDS:
     check-update            Show if an update is available
         [ -a | --all ]                Output all available updates, even if they're not upgrades
+        [ --skipped ]                 Output updates skipped because of skip-versions or
+                                      minimum-version, and why
         [ --ignore-waves ]            Ignore release schedule when checking
                                       for a new update
 
@@ -87,20 +122,32 @@ SUBCOMMANDS:
     update                  Perform an update if available
         [ -i | --image version ]      Update to a specfic image version
         [ -n | --now ]                Update immediately, ignoring any release schedule
+                                      and maintenance windows
+        [ --ignore-maintenance-windows ]
+                                      Update even outside of maintenance windows
         [ -r | --reboot ]             Reboot into new update on success
 
     update-image            Download & write an update but do not update flags
         [ -i | --image version ]      Update to a specfic image version
         [ -n | --now ]                Update immediately, ignoring wave limits
+                                      and maintenance windows
+        [ --ignore-maintenance-windows ]
+                                      Update even outside of maintenance windows
         [ -t | --timestamp time ]     The timestamp to execute an update from
 
     update-apply            Update boot flags (after having called update-image)
+        [ --ignore-maintenance-windows ]
+                                      Update even outside of maintenance windows
         [ -r | --reboot ]             Reboot after updating boot flags
 
     update-revert           Revert actions done by 'update-apply'
 
+    inspect                 Show and verify the update staged in the inactive partition set
+
 GLOBAL OPTIONS:
     [ -j | --json ]               JSON-formatted output
+    [ --repo-dir PATH ]           Use the repository in PATH, as written by
+                                  'pubsys repo', instead of the configured URLs
     [ --log-level trace|debug|info|warn|error ]  Set logging verbosity");
     std::process::exit(1)
 }
@@ -136,33 +183,131 @@ fn load_repository<'a>(
     .context(error::Metadata)
 }
 
+/// Parses a version from the config, which might be prefixed with 'v'.
+fn parse_config_version(version_str: &str) -> Result<Version> {
+    // Make sure the version string from the config is a valid version string that might be prefixed with 'v'
+    let friendly_version =
+        FriendlyVersion::try_from(version_str).context(error::BadVersionConfig { version_str })?;
+    // Convert back to semver::Version
+    friendly_version
+        .try_into()
+        .context(error::BadVersion { version_str })
+}
+
+/// Versions that we won't update to, no matter what else we're told.  This lets operators block
+/// a known-bad release, or a downgrade past some release, without locking to a specific version.
+#[derive(Debug, Default)]
+struct VersionPolicy {
+    skip_versions: Vec<Version>,
+    minimum_version: Option<Version>,
+}
+
+impl VersionPolicy {
+    /// Reads the policy from the config.  Only commands that choose an update need it, so they
+    /// build it themselves, and a bad policy doesn't stop us from applying or reverting an update
+    /// that was already chosen.
+    fn from_config(config: &Config) -> Result<Self> {
+        let exact_version = |version_str: &str| {
+            ExactVersion::try_from(version_str)
+                .map(Version::from)
+                .context(error::BadVersionConfig { version_str })
+        };
+        Ok(Self {
+            skip_versions: config
+                .skip_versions
+                .iter()
+                .map(|v| exact_version(v))
+                .collect::<Result<_>>()?,
+            minimum_version: config
+                .minimum_version
+                .as_deref()
+                .map(exact_version)
+                .transpose()?,
+        })
+    }
+
+    /// Returns why the policy doesn't allow updating to `version`, or None if it does.
+    fn denies(&self, version: &Version) -> Option<String> {
+        if self.skip_versions.contains(version) {
+            return Some("listed in settings.updates.skip-versions".to_string());
+        }
+        match &self.minimum_version {
+            Some(minimum) if version < minimum => Some(format!(
+                "older than settings.updates.minimum-version {}",
+                minimum
+            )),
+            _ => None,
+        }
+    }
+
+    fn allows(&self, version: &Version) -> bool {
+        self.denies(version).is_none()
+    }
+}
+
+/// An update that would be available, but that the version policy doesn't allow.
+#[derive(Debug, Serialize)]
+struct SkippedUpdate<'a> {
+    version: &'a Version,
+    reason: String,
+}
+
+/// Returns whether we could use the update, before applying the version policy.
+fn is_candidate(update: &Update, variant: &str, ignore_waves: bool, seed: u32) -> bool {
+    update.variant == *variant
+        && update.arch == TARGET_ARCH
+        && update.version <= update.max_version
+        // A halted rollout stops everywhere, even for hosts ignoring waves
+        && update.halt.is_none()
+        && (ignore_waves || update.update_ready(seed, Utc::now()))
+}
+
 fn applicable_updates<'a>(
     manifest: &'a Manifest,
     variant: &str,
     ignore_waves: bool,
     seed: u32,
+    policy: &VersionPolicy,
 ) -> Vec<&'a Update> {
     let mut updates: Vec<&Update> = manifest
         .updates
         .iter()
-        .filter(|u| {
-            u.variant == *variant
-                && u.arch == TARGET_ARCH
-                && u.version <= u.max_version
-                && (ignore_waves || u.update_ready(seed, Utc::now()))
-        })
+        .filter(|u| is_candidate(u, variant, ignore_waves, seed) && policy.allows(&u.version))
         .collect();
     // sort descending
     updates.sort_unstable_by(|a, b| b.version.cmp(&a.version));
     updates
 }
 
+/// Returns the updates the version policy doesn't allow, and why, newest first.
+fn skipped_updates<'a>(
+    manifest: &'a Manifest,
+    variant: &str,
+    ignore_waves: bool,
+    seed: u32,
+    policy: &VersionPolicy,
+) -> Vec<SkippedUpdate<'a>> {
+    let mut skipped: Vec<SkippedUpdate<'_>> = manifest
+        .updates
+        .iter()
+        .filter(|u| is_candidate(u, variant, ignore_waves, seed))
+        .filter_map(|u| {
+            policy.denies(&u.version).map(|reason| SkippedUpdate {
+                version: &u.version,
+                reason,
+            })
+        })
+        .collect();
+    skipped.sort_unstable_by(|a, b| b.version.cmp(a.version));
+    skipped
+}
+
 // TODO use config if there is api-sourced configuration that could affect this
 // TODO updog.toml may include settings that cause us to ignore/delay
 // certain/any updates;
-//  Ignore Specific Target Version
 //  Ignore Any Target
 //  ...
+#[allow(clippy::too_many_arguments)]
 fn update_required<'a>(
     manifest: &'a Manifest,
     version: &Version,
@@ -170,26 +315,17 @@ fn update_required<'a>(
     ignore_waves: bool,
     seed: u32,
     version_lock: &str,
+    policy: &VersionPolicy,
     force_version: Option<Version>,
 ) -> Result<Option<&'a Update>> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, policy);
 
     if let Some(forced_version) = force_version {
         return Ok(updates.into_iter().find(|u| u.version == forced_version));
     }
 
     if version_lock != "latest" {
-        // Make sure the version string from the config is a valid version string that might be prefixed with 'v'
-        let friendly_version_lock =
-            FriendlyVersion::try_from(version_lock).context(error::BadVersionConfig {
-                version_str: version_lock,
-            })?;
-        // Convert back to semver::Version
-        let semver_version_lock = friendly_version_lock
-            .try_into()
-            .context(error::BadVersion {
-                version_str: version_lock,
-            })?;
+        let semver_version_lock = parse_config_version(version_lock)?;
         // If the configured version-lock matches our current version, we won't update to the same version
         return if semver_version_lock == *version {
             Ok(None)
@@ -210,27 +346,120 @@ fn update_required<'a>(
     Ok(None)
 }
 
+/// Returns the hex-encoded SHA-256 digest listed for a target in the repository.
+fn target_sha256(repository: &HttpQueryRepo<'_>, target: &str) -> Result<String> {
+    let target_info = repository
+        .targets()
+        .signed
+        .targets
+        .get(target)
+        .context(error::TargetNotFound { target })?;
+    Ok(progress::hex(target_info.hashes.sha256.as_ref()))
+}
+
+/// Downloads a target to `download_dir`, resuming any earlier download, and writes the image it
+/// holds to `disk_path`, resuming any earlier write.  The downloaded target is removed once the
+/// image is written.
 fn write_target_to_disk<P: AsRef<Path>>(
     repository: &HttpQueryRepo<'_>,
+    transport: &HttpQueryTransport,
+    targets_base_url: &str,
+    download_dir: &Path,
     target: &str,
     disk_path: P,
+    progress: &mut Progress,
 ) -> Result<()> {
-    let reader = repository
-        .read_target(target)
-        .context(error::Metadata)?
-        .context(error::TargetNotFound { target })?;
+    let disk_path = disk_path.as_ref();
+    let earlier = progress.start(disk_path, target, &target_sha256(repository, target)?)?;
+    if earlier.image.is_some() && progress.verify(disk_path).is_ok() {
+        eprintln!("{} was already written to {}", target, disk_path.display());
+        return Ok(());
+    }
+    if earlier.written > 0 {
+        eprintln!(
+            "Resuming write of {} to {} after {} bytes",
+            target,
+            disk_path.display(),
+            earlier.written
+        );
+    }
+
+    let (reader, download_path) = download::download_target(
+        repository,
+        transport,
+        targets_base_url,
+        target,
+        download_dir,
+    )?;
     // Note: the file extension for the compression type we're using should be removed in
     // retrieve_migrations below.
-    let mut reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
+    let reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
     let mut f = OpenOptions::new()
+        .read(true)
         .write(true)
         .create(true)
-        .open(disk_path.as_ref())
-        .context(error::OpenPartition {
-            path: disk_path.as_ref(),
-        })?;
-    io::copy(&mut reader, &mut f).context(error::WriteUpdate)?;
-    Ok(())
+        .open(disk_path)
+        .context(error::OpenPartition { path: disk_path })?;
+    let image = progress::write_image(reader, &mut f, earlier.written, |written| {
+        progress.set_written(disk_path, written)
+    })?;
+    progress.set_complete(disk_path, image)?;
+    fs::remove_file(&download_path).context(error::DownloadWrite {
+        path: &download_path,
+    })
+}
+
+/// Builds an image by applying a delta target to the image in `source_path`, writing it to
+/// `disk_path`.  The delta is verified against both the source and the resulting image, so an
+/// error here means the caller should fall back to the full image.
+#[allow(clippy::too_many_arguments)]
+fn write_delta_to_disk<P: AsRef<Path>>(
+    repository: &HttpQueryRepo<'_>,
+    transport: &HttpQueryTransport,
+    targets_base_url: &str,
+    download_dir: &Path,
+    target: &str,
+    source_path: P,
+    disk_path: P,
+    progress: &mut Progress,
+) -> Result<()> {
+    let disk_path = disk_path.as_ref();
+    // Deltas are applied in one pass, so we can skip one that was completed, but not resume one
+    // that was interrupted.  Downloading the delta can be resumed, like a full image.
+    let earlier = progress.start(disk_path, target, &target_sha256(repository, target)?)?;
+    if earlier.image.is_some() && progress.verify(disk_path).is_ok() {
+        eprintln!("{} was already applied to {}", target, disk_path.display());
+        return Ok(());
+    }
+
+    let (reader, download_path) = download::download_target(
+        repository,
+        transport,
+        targets_base_url,
+        target,
+        download_dir,
+    )?;
+    let reader = lz4::Decoder::new(reader).context(error::Lz4Decode { target })?;
+    let source = File::open(source_path.as_ref()).context(error::OpenPartition {
+        path: source_path.as_ref(),
+    })?;
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(disk_path)
+        .context(error::OpenPartition { path: disk_path })?;
+    let (length, digest) = delta::apply::apply(source, reader, &mut f)?;
+    f.sync_data().context(error::WriteUpdate)?;
+    progress.set_complete(
+        disk_path,
+        ImageDigest {
+            length,
+            sha256: progress::hex(&digest),
+        },
+    )?;
+    fs::remove_file(&download_path).context(error::DownloadWrite {
+        path: &download_path,
+    })
 }
 
 /// Store required migrations for an update in persistent storage. All intermediate migrations
@@ -271,24 +500,98 @@ fn retrieve_migrations(
     Ok(())
 }
 
-fn update_image(update: &Update, repository: &HttpQueryRepo<'_>) -> Result<()> {
+fn update_image(
+    update: &Update,
+    repository: &HttpQueryRepo<'_>,
+    transport: &HttpQueryTransport,
+    config: &Config,
+    current_version: &Version,
+) -> Result<()> {
     let mut gpt_state = State::load().context(error::PartitionTableRead)?;
     gpt_state.clear_inactive();
     // Write out the clearing of the inactive partition immediately, because we're about to
     // overwrite the partition set with update data and don't want it to be used until we
     // know we're done with all components.
     gpt_state.write().context(error::PartitionTableWrite)?;
+    StagedImages::clear(STAGED_PATH)?;
 
+    // Downloads for any other version were for an update we've abandoned.
+    let downloads = Path::new(DOWNLOAD_PATH);
+    download::remove_downloads(downloads, Some(&update.version))?;
+    let download_dir = download::version_dir(downloads, &update.version);
+
+    let mut progress = Progress::load(PROGRESS_PATH)?;
+    let active = gpt_state.active_set();
     let inactive = gpt_state.inactive_set();
+    // If the update has deltas from the version we're running, we can build its images from the
+    // ones in our active partition set rather than downloading them in full.
+    let deltas = update.images.deltas.get(current_version);
 
     // TODO Do we want to recover the inactive side on an error?
-    write_target_to_disk(repository, &update.images.root, &inactive.root)?;
-    write_target_to_disk(repository, &update.images.boot, &inactive.boot)?;
-    write_target_to_disk(repository, &update.images.hash, &inactive.hash)?;
+    let images = [
+        (
+            &update.images.root,
+            deltas.map(|d| &d.root),
+            &active.root,
+            &inactive.root,
+        ),
+        (
+            &update.images.boot,
+            deltas.map(|d| &d.boot),
+            &active.boot,
+            &inactive.boot,
+        ),
+        (
+            &update.images.hash,
+            deltas.map(|d| &d.hash),
+            &active.hash,
+            &inactive.hash,
+        ),
+    ];
+    for (full, delta, source, dest) in &images {
+        if let Some(delta) = delta {
+            match write_delta_to_disk(
+                repository,
+                transport,
+                &config.targets_base_url,
+                &download_dir,
+                delta,
+                source,
+                dest,
+                &mut progress,
+            ) {
+                Ok(()) => continue,
+                Err(e) => eprintln!(
+                    "Failed to apply delta {}, falling back to full image: {}",
+                    delta, e
+                ),
+            }
+        }
+        write_target_to_disk(
+            repository,
+            transport,
+            &config.targets_base_url,
+            &download_dir,
+            full,
+            dest,
+            &mut progress,
+        )?;
+    }
+
+    // Read back each image before marking the partition set valid.  If one doesn't match, forget
+    // it, so the next attempt writes it again.
+    for (_, _, _, dest) in &images {
+        if let Err(e) = progress.verify(dest) {
+            progress.forget(dest)?;
+            return Err(e);
+        }
+    }
 
     gpt_state.mark_inactive_valid();
     gpt_state.write().context(error::PartitionTableWrite)?;
-    Ok(())
+    progress.stage(&update.version, STAGED_PATH)?;
+    // Don't keep anything left over, like a delta we fell back from.
+    download::remove_downloads(downloads, None)
 }
 
 fn update_flags() -> Result<()> {
@@ -329,8 +632,9 @@ fn list_updates(
     json: bool,
     ignore_waves: bool,
     seed: u32,
+    policy: &VersionPolicy,
 ) -> Result<()> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, policy);
     if json {
         println!(
             "{}",
@@ -344,16 +648,42 @@ fn list_updates(
     Ok(())
 }
 
+/// List any update that matches the current variant but is skipped by the version policy
+fn list_skipped_updates(
+    manifest: &Manifest,
+    variant: &str,
+    json: bool,
+    ignore_waves: bool,
+    seed: u32,
+    policy: &VersionPolicy,
+) -> Result<()> {
+    let skipped = skipped_updates(manifest, variant, ignore_waves, seed, policy);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&skipped).context(error::UpdateSerialize)?
+        );
+    } else {
+        for s in skipped {
+            eprintln!("{}: {}", s.version, s.reason);
+        }
+    }
+    Ok(())
+}
+
 /// Struct to hold the specified command line argument values
 struct Arguments {
     subcommand: String,
     log_level: LevelFilter,
     json: bool,
     ignore_waves: bool,
+    ignore_windows: bool,
     force_version: Option<Version>,
     all: bool,
+    skipped: bool,
     reboot: bool,
     variant: Option<String>,
+    repo_dir: Option<PathBuf>,
 }
 
 /// Parse the command line arguments to get the user-specified values
@@ -362,10 +692,13 @@ fn parse_args(args: std::env::Args) -> Arguments {
     let mut log_level = None;
     let mut update_version = None;
     let mut ignore_waves = false;
+    let mut ignore_windows = false;
     let mut json = false;
     let mut all = false;
+    let mut skipped = false;
     let mut reboot = false;
     let mut variant = None;
+    let mut repo_dir = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -391,9 +724,22 @@ fn parse_args(args: std::env::Args) -> Arguments {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --variant")),
                 );
             }
-            "-n" | "--now" | "--ignore-waves" => {
+            "--repo-dir" => {
+                repo_dir =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --repo-dir")
+                    })));
+            }
+            "-n" | "--now" => {
+                ignore_waves = true;
+                ignore_windows = true;
+            }
+            "--ignore-waves" => {
                 ignore_waves = true;
             }
+            "--ignore-maintenance-windows" => {
+                ignore_windows = true;
+            }
             "-j" | "--json" => {
                 json = true;
             }
@@ -403,6 +749,9 @@ fn parse_args(args: std::env::Args) -> Arguments {
             "-a" | "--all" => {
                 all = true;
             }
+            "--skipped" => {
+                skipped = true;
+            }
             // Assume any arguments not prefixed with '-' is a subcommand
             s if !s.starts_with('-') => {
                 if subcommand.is_some() {
@@ -419,13 +768,58 @@ fn parse_args(args: std::env::Args) -> Arguments {
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         json,
         ignore_waves,
+        ignore_windows,
         force_version: update_version,
         all,
+        skipped,
         reboot,
         variant,
+        repo_dir,
     }
 }
 
+/// Points the config at a repository in a local directory, laid out as `pubsys repo` writes it:
+/// metadata for each variant and architecture in `<dir>/<variant>/<arch>`, and all targets in
+/// `<dir>/targets`.  The repository is still verified against our root.json as usual.
+fn use_repo_dir(config: &mut Config, dir: &Path, variant: &str) -> Result<()> {
+    // file:// URLs must be absolute
+    let dir = fs::canonicalize(dir).context(error::RepoDir { path: dir })?;
+    let metadata_dir = dir.join(variant).join(TARGET_ARCH);
+    let targets_dir = dir.join("targets");
+    config.metadata_base_url = Url::from_directory_path(&metadata_dir)
+        .ok()
+        .context(error::RepoDirUrl { path: metadata_dir })?
+        .into_string();
+    config.targets_base_url = Url::from_directory_path(&targets_dir)
+        .ok()
+        .context(error::RepoDirUrl { path: targets_dir })?
+        .into_string();
+    Ok(())
+}
+
+/// Returns the configured limits on downloading update images.  There's no random delay before
+/// starting if we were asked to ignore the release schedule, since that means updating right away.
+fn download_limits(config: &Config, ignore_schedule: bool) -> DownloadLimits {
+    DownloadLimits {
+        max_bytes_per_second: config
+            .max_download_bytes_per_second
+            .and_then(NonZeroU64::new),
+        start_jitter: if ignore_schedule {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs(config.download_start_jitter_seconds)
+        },
+    }
+}
+
+/// Makes sure we're inside one of the configured maintenance windows, if there are any.
+fn check_maintenance_windows(windows: &[MaintenanceWindow], now: DateTime<Utc>) -> Result<()> {
+    if let Some(next) = next_maintenance_window(windows, now).context(error::MaintenanceWindow)? {
+        ensure!(next <= now, error::OutsideMaintenanceWindow { next });
+    }
+    Ok(())
+}
+
 fn fmt_full_version(update: &Update) -> String {
     format!("{} {}", update.variant, update.version)
 }
@@ -481,9 +875,12 @@ fn main_inner() -> Result<()> {
     let command =
         serde_plain::from_str::<Command>(&arguments.subcommand).unwrap_or_else(|_| usage());
 
-    let config = load_config()?;
+    let mut config = load_config()?;
     let current_release = BottlerocketRelease::new().context(error::ReleaseVersion)?;
     let variant = arguments.variant.unwrap_or(current_release.variant_id);
+    if let Some(repo_dir) = &arguments.repo_dir {
+        use_repo_dir(&mut config, repo_dir, &variant)?;
+    }
     let transport = HttpQueryTransport::new();
     set_common_query_params(&transport, &current_release.version_id, &config)?;
     let tough_datastore = TempDir::new().context(error::CreateTempDir)?;
@@ -492,30 +889,54 @@ fn main_inner() -> Result<()> {
     let ignore_waves = arguments.ignore_waves || config.ignore_waves;
     match command {
         Command::CheckUpdate | Command::Whats => {
-            if arguments.all {
-                return list_updates(
+            let policy = VersionPolicy::from_config(&config)?;
+            if arguments.skipped {
+                list_skipped_updates(
                     &manifest,
                     &variant,
                     arguments.json,
                     ignore_waves,
                     config.seed,
-                );
-            }
+                    &policy,
+                )?;
+            } else if arguments.all {
+                list_updates(
+                    &manifest,
+                    &variant,
+                    arguments.json,
+                    ignore_waves,
+                    config.seed,
+                    &policy,
+                )?;
+            } else {
+                let update = update_required(
+                    &manifest,
+                    &current_release.version_id,
+                    &variant,
+                    ignore_waves,
+                    config.seed,
+                    &config.version_lock,
+                    &policy,
+                    arguments.force_version,
+                )?
+                .context(error::UpdateNotAvailable)?;
 
-            let update = update_required(
-                &manifest,
-                &current_release.version_id,
-                &variant,
-                ignore_waves,
-                config.seed,
-                &config.version_lock,
-                arguments.force_version,
-            )?
-            .context(error::UpdateNotAvailable)?;
+                output(arguments.json, &update, &fmt_full_version(&update))?;
+            }
 
-            output(arguments.json, &update, &fmt_full_version(&update))?;
+            // Let the caller know when an update could be applied; this goes to stderr so it
+            // doesn't mix with JSON output.
+            let now = Utc::now();
+            match next_maintenance_window(&config.maintenance_windows, now) {
+                Ok(Some(next)) if next > now => {
+                    eprintln!("Next maintenance window opens at {}", next);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("{}", e),
+            }
         }
         Command::Update | Command::UpdateImage => {
+            let policy = VersionPolicy::from_config(&config)?;
             if let Some(u) = update_required(
                 &manifest,
                 &current_release.version_id,
@@ -523,9 +944,14 @@ fn main_inner() -> Result<()> {
                 ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &policy,
                 arguments.force_version,
             )? {
+                if !arguments.ignore_windows {
+                    check_maintenance_windows(&config.maintenance_windows, Utc::now())?;
+                }
                 eprintln!("Starting update to {}", u.version);
+                transport.limit_downloads(download_limits(&config, arguments.ignore_waves));
 
                 transport
                     .queries_get_mut()
@@ -539,8 +965,19 @@ fn main_inner() -> Result<()> {
                     u,
                     &current_release.version_id,
                 )?;
-                update_image(u, &repository)?;
+                update_image(
+                    u,
+                    &repository,
+                    &transport,
+                    &config,
+                    &current_release.version_id,
+                )?;
                 if command == Command::Update {
+                    // Downloading can take long enough for the window to close, so check again
+                    // before switching partitions and rebooting.
+                    if !arguments.ignore_windows {
+                        check_maintenance_windows(&config.maintenance_windows, Utc::now())?;
+                    }
                     update_flags()?;
                     if arguments.reboot {
                         initiate_reboot()?;
@@ -552,10 +989,15 @@ fn main_inner() -> Result<()> {
                     &format!("Update applied: {}", fmt_full_version(&u)),
                 )?;
             } else {
+                // Anything downloaded for an earlier update is no longer needed.
+                download::remove_downloads(Path::new(DOWNLOAD_PATH), None)?;
                 eprintln!("No update required");
             }
         }
         Command::UpdateApply => {
+            if !arguments.ignore_windows {
+                check_maintenance_windows(&config.maintenance_windows, Utc::now())?;
+            }
             update_flags()?;
             if arguments.reboot {
                 initiate_reboot()?;
@@ -567,6 +1009,16 @@ fn main_inner() -> Result<()> {
         Command::Prepare => {
             // TODO unimplemented
         }
+        Command::Inspect => {
+            let gpt_state = State::load().context(error::PartitionTableRead)?;
+            let inspection = inspect::inspect(
+                &repository,
+                &manifest,
+                gpt_state.inactive_set(),
+                Path::new(STAGED_PATH),
+            );
+            output(arguments.json, &inspection, &inspection.to_string())?;
+        }
     }
 
     Ok(())
@@ -596,6 +1048,22 @@ mod tests {
     use std::collections::BTreeMap;
     use update_metadata::Images;
 
+    /// Returns a config with no optional settings, for tests to override as needed.
+    fn test_config() -> Config {
+        Config {
+            metadata_base_url: String::from("foo"),
+            targets_base_url: String::from("bar"),
+            seed: 123,
+            version_lock: "latest".to_string(),
+            ignore_waves: false,
+            maintenance_windows: Vec::new(),
+            skip_versions: Vec::new(),
+            minimum_version: None,
+            max_download_bytes_per_second: None,
+            download_start_jitter_seconds: 0,
+        }
+    }
+
     #[test]
     fn test_manifest_json() {
         // Loads a general example of a manifest that includes an update with waves,
@@ -638,13 +1106,7 @@ mod tests {
         // - max_version: 1.20.0
         let path = "tests/data/regret.json";
         let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
-        let config = Config {
-            metadata_base_url: String::from("foo"),
-            targets_base_url: String::from("bar"),
-            seed: 123,
-            version_lock: "latest".to_string(),
-            ignore_waves: false,
-        };
+        let config = test_config();
         let version = Version::parse("1.18.0").unwrap();
         let variant = String::from("bottlerocket-aws-eks");
 
@@ -656,6 +1118,7 @@ mod tests {
                 config.ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &VersionPolicy::default(),
                 None
             )
             .unwrap()
@@ -670,11 +1133,8 @@ mod tests {
         let path = "tests/data/example_3.json";
         let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
         let config = Config {
-            metadata_base_url: String::from("foo"),
-            targets_base_url: String::from("bar"),
             seed: 1487,
-            version_lock: "latest".to_string(),
-            ignore_waves: false,
+            ..test_config()
         };
 
         let version = Version::parse("0.1.3").unwrap();
@@ -686,6 +1146,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &VersionPolicy::default(),
             None,
         )
         .unwrap();
@@ -705,13 +1166,7 @@ mod tests {
         // instead of 1.13.0 (lower), 1.25.0 (too high), or 1.16.0 (wrong arch).
         let path = "tests/data/multiple.json";
         let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
-        let config = Config {
-            metadata_base_url: String::from("foo"),
-            targets_base_url: String::from("bar"),
-            seed: 123,
-            version_lock: "latest".to_string(),
-            ignore_waves: false,
-        };
+        let config = test_config();
 
         let version = Version::parse("1.10.0").unwrap();
         let variant = String::from("bottlerocket-aws-eks");
@@ -722,6 +1177,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &VersionPolicy::default(),
             None,
         )
         .unwrap();
@@ -745,13 +1201,7 @@ mod tests {
         // above test, test_multiple.
         let path = "tests/data/multiple.json";
         let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
-        let config = Config {
-            metadata_base_url: String::from("foo"),
-            targets_base_url: String::from("bar"),
-            seed: 123,
-            version_lock: "latest".to_string(),
-            ignore_waves: false,
-        };
+        let config = test_config();
 
         let version = Version::parse("1.10.0").unwrap();
         let forced = Version::parse("1.13.0").unwrap();
@@ -763,6 +1213,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &VersionPolicy::default(),
             Some(forced),
         )
         .unwrap();
@@ -820,18 +1271,17 @@ mod tests {
                 boot: String::from("boot"),
                 root: String::from("boot"),
                 hash: String::from("boot"),
+                deltas: BTreeMap::new(),
             },
+            halt: None,
         };
 
         let current_version = Version::parse("1.0.0").unwrap();
         let variant = String::from("aws-k8s-1.15");
         let first_wave_seed = 0;
         let config = Config {
-            metadata_base_url: String::from("foo"),
-            targets_base_url: String::from("bar"),
             seed: first_wave_seed,
-            version_lock: "latest".to_string(),
-            ignore_waves: false,
+            ..test_config()
         };
 
         // Two waves; the 1st wave that starts immediately, and the final wave which starts in one hour
@@ -849,6 +1299,7 @@ mod tests {
                 config.ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &VersionPolicy::default(),
                 None,
             )
             .unwrap()
@@ -864,6 +1315,7 @@ mod tests {
                 config.ignore_waves,
                 2000,
                 &config.version_lock,
+                &VersionPolicy::default(),
                 None,
             )
             .unwrap()
@@ -871,4 +1323,232 @@ mod tests {
             "Later wave incorrectly sees update"
         );
     }
+
+    #[test]
+    fn test_repo_dir() {
+        let dir = TempDir::new().unwrap();
+        let mut config = Config {
+            metadata_base_url: String::from("https://example.com/metadata/"),
+            targets_base_url: String::from("https://example.com/targets/"),
+            seed: 1,
+            ..test_config()
+        };
+        use_repo_dir(&mut config, dir.path(), "aws-k8s-1.17").unwrap();
+
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let metadata_url = Url::parse(&config.metadata_base_url).unwrap();
+        let targets_url = Url::parse(&config.targets_base_url).unwrap();
+        assert_eq!(metadata_url.scheme(), "file");
+        assert_eq!(
+            metadata_url.to_file_path().unwrap(),
+            root.join("aws-k8s-1.17").join(TARGET_ARCH)
+        );
+        assert!(config.metadata_base_url.ends_with('/'));
+        assert_eq!(targets_url.to_file_path().unwrap(), root.join("targets"));
+        assert!(config.targets_base_url.ends_with('/'));
+
+        assert!(use_repo_dir(&mut config, &dir.path().join("missing"), "aws-k8s-1.17").is_err());
+    }
+
+    #[test]
+    fn test_maintenance_windows() {
+        let config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 1
+            version_lock = "latest"
+            ignore_waves = false
+            maintenance_windows = ["Sat,Sun 22:00-02:00 -07:00", ]
+            "#,
+        )
+        .unwrap();
+        // 2020-08-09 was a Sunday.
+        let inside = DateTime::parse_from_rfc3339("2020-08-09T06:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let outside = inside + TestDuration::hours(12);
+        assert!(check_maintenance_windows(&config.maintenance_windows, inside).is_ok());
+        assert!(check_maintenance_windows(&config.maintenance_windows, outside).is_err());
+
+        // Without any windows, updates can happen at any time.
+        let config: Config = toml::from_str(
+            r#"
+            metadata_base_url = "foo"
+            targets_base_url = "bar"
+            seed = 1
+            version_lock = "latest"
+            ignore_waves = false
+            "#,
+        )
+        .unwrap();
+        assert!(check_maintenance_windows(&config.maintenance_windows, outside).is_ok());
+    }
+
+    #[test]
+    fn test_version_policy() {
+        // A manifest with two updates, 0.1.1 and 0.1.2, both less than 0.1.3
+        let path = "tests/data/example_3.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let mut config = Config {
+            seed: 1487,
+            skip_versions: vec!["v0.1.2".to_string()],
+            ..test_config()
+        };
+        let version = Version::parse("0.1.3").unwrap();
+        let variant = String::from("aws-k8s-1.15");
+
+        let policy = VersionPolicy::from_config(&config).unwrap();
+        let update = update_required(
+            &manifest,
+            &version,
+            &variant,
+            config.ignore_waves,
+            config.seed,
+            &config.version_lock,
+            &policy,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            update.map(|u| &u.version),
+            Some(&Version::parse("0.1.1").unwrap()),
+            "Updog didn't skip the denied version"
+        );
+
+        config.minimum_version = Some("0.1.2".to_string());
+        let policy = VersionPolicy::from_config(&config).unwrap();
+        assert!(
+            update_required(
+                &manifest,
+                &version,
+                &variant,
+                config.ignore_waves,
+                config.seed,
+                &config.version_lock,
+                &policy,
+                None,
+            )
+            .unwrap()
+            .is_none(),
+            "Updog chose a version below the minimum"
+        );
+        assert!(
+            update_required(
+                &manifest,
+                &version,
+                &variant,
+                config.ignore_waves,
+                config.seed,
+                &config.version_lock,
+                &policy,
+                Some(Version::parse("0.1.1").unwrap()),
+            )
+            .unwrap()
+            .is_none(),
+            "Updog forced a version below the minimum"
+        );
+
+        // Both versions are reported as skipped, with the setting that skipped them.
+        let skipped = skipped_updates(
+            &manifest,
+            &variant,
+            config.ignore_waves,
+            config.seed,
+            &policy,
+        );
+        let skipped: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.version.to_string(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (
+                    "0.1.2".to_string(),
+                    "listed in settings.updates.skip-versions"
+                ),
+                (
+                    "0.1.1".to_string(),
+                    "older than settings.updates.minimum-version 0.1.2"
+                ),
+            ]
+        );
+
+        config.skip_versions.push("latest".to_string());
+        assert!(VersionPolicy::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_halted_update() {
+        // A manifest with two updates, 0.1.1 and 0.1.2, both less than 0.1.3
+        let path = "tests/data/example_3.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let version = Version::parse("0.1.3").unwrap();
+        let variant = String::from("aws-k8s-1.15");
+        let halted = Version::parse("0.1.2").unwrap();
+        let required = |manifest: &Manifest, force_version: Option<Version>| {
+            update_required(
+                manifest,
+                &version,
+                &variant,
+                true,
+                1487,
+                "latest",
+                &VersionPolicy::default(),
+                force_version,
+            )
+            .unwrap()
+            .map(|u| u.version.clone())
+        };
+
+        manifest
+            .halt_update(
+                variant.clone(),
+                TARGET_ARCH.to_string(),
+                halted.clone(),
+                Utc::now(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            required(&manifest, None),
+            Some(Version::parse("0.1.1").unwrap()),
+            "Updog chose a halted update"
+        );
+        assert_eq!(
+            required(&manifest, Some(halted.clone())),
+            None,
+            "Updog forced a halted update"
+        );
+
+        manifest
+            .resume_update(variant.clone(), TARGET_ARCH.to_string(), halted.clone())
+            .unwrap();
+        assert_eq!(required(&manifest, None), Some(halted));
+    }
+
+    #[test]
+    fn test_download_limits() {
+        let mut config = Config {
+            seed: 1487,
+            ..test_config()
+        };
+        assert_eq!(download_limits(&config, false), DownloadLimits::default());
+
+        config.max_download_bytes_per_second = Some(1_048_576);
+        config.download_start_jitter_seconds = 600;
+        let limits = download_limits(&config, false);
+        assert_eq!(limits.max_bytes_per_second, NonZeroU64::new(1_048_576));
+        assert_eq!(limits.start_jitter, Duration::from_secs(600));
+
+        // Updating right away skips the delay, but not the rate limit
+        let limits = download_limits(&config, true);
+        assert_eq!(limits.max_bytes_per_second, NonZeroU64::new(1_048_576));
+        assert_eq!(limits.start_jitter, Duration::from_secs(0));
+
+        // A zero rate means no limit
+        config.max_download_bytes_per_second = Some(0);
+        assert_eq!(download_limits(&config, false).max_bytes_per_second, None);
+    }
 }
@@ -0,0 +1,464 @@
+//! The progress module records how far we've gotten writing update images to the inactive
+//! partition set, so an interrupted update can pick up where it left off rather than starting
+//! over.
+//!
+//! Images are written in chunks, and after each chunk is synced to disk, the number of bytes
+//! written is saved in a progress file.  Targets are downloaded to disk before they're written, and
+//! their downloads resume too (see the download module), so a resumed write reads the image from
+//! the start of the local copy; chunks before the saved offset are compared with what's on disk
+//! rather than being written again.  Once an image is complete, its length and digest are saved,
+//! and a later attempt that finds the image intact on disk skips it entirely.
+//!
+//! Before the partition set is marked valid, each image is read back from disk and checked against
+//! the digest computed while writing it.  That digest was computed from the same data that was
+//! verified against the target's hash in the repository metadata.
+//!
+//! Once the partition set is complete, the record of its images is kept as the staged images, so
+//! the update can be inspected before it's applied.
+
+use crate::delta::format::read_block;
+use crate::error::{self, Result};
+use ring::digest::{Context, SHA256};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The amount of an image written between saves of the progress file.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// `ImageDigest` identifies the contents of a complete image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ImageDigest {
+    pub(crate) length: u64,
+    /// Hex-encoded SHA-256 digest of the (uncompressed) image.
+    pub(crate) sha256: String,
+}
+
+/// `ImageProgress` describes the writing of one target to one partition.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ImageProgress {
+    pub(crate) target: String,
+    /// Hex-encoded SHA-256 digest of the target, as listed in the repository.
+    pub(crate) target_sha256: String,
+    /// Bytes of the image written and synced to the partition.
+    pub(crate) written: u64,
+    /// Set once the whole image has been written.
+    pub(crate) image: Option<ImageDigest>,
+}
+
+/// Progress tracks the images being written to partitions, and saves it to a file.
+#[derive(Debug)]
+pub(crate) struct Progress {
+    path: PathBuf,
+    partitions: BTreeMap<PathBuf, ImageProgress>,
+}
+
+impl Progress {
+    /// Loads progress from the given file.  If there isn't one, or it can't be understood, we
+    /// start over.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let partitions = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                eprintln!(
+                    "Ignoring unreadable write progress in {}: {}",
+                    path.display(),
+                    e
+                );
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).context(error::ProgressRead { path }),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            partitions,
+        })
+    }
+
+    /// Starts writing `target` to `partition`, returning the progress made by any earlier attempt
+    /// to write the same target there.
+    pub(crate) fn start(
+        &mut self,
+        partition: &Path,
+        target: &str,
+        target_sha256: &str,
+    ) -> Result<ImageProgress> {
+        if let Some(progress) = self.partitions.get(partition) {
+            if progress.target == target && progress.target_sha256 == target_sha256 {
+                return Ok(progress.clone());
+            }
+        }
+        let progress = ImageProgress {
+            target: target.to_string(),
+            target_sha256: target_sha256.to_string(),
+            written: 0,
+            image: None,
+        };
+        self.partitions
+            .insert(partition.to_owned(), progress.clone());
+        self.save()?;
+        Ok(progress)
+    }
+
+    /// Records that the first `written` bytes of the image have been synced to `partition`.
+    pub(crate) fn set_written(&mut self, partition: &Path, written: u64) -> Result<()> {
+        if let Some(progress) = self.partitions.get_mut(partition) {
+            progress.written = written;
+        }
+        self.save()
+    }
+
+    /// Records that the whole image has been written to `partition`.
+    pub(crate) fn set_complete(&mut self, partition: &Path, image: ImageDigest) -> Result<()> {
+        if let Some(progress) = self.partitions.get_mut(partition) {
+            progress.written = image.length;
+            progress.image = Some(image);
+        }
+        self.save()
+    }
+
+    /// Checks that `partition` holds the complete image recorded for it.
+    pub(crate) fn verify(&self, partition: &Path) -> Result<()> {
+        verify_image(partition, self.partitions.get(partition))
+    }
+
+    /// Forgets any progress writing to `partition`, so the next attempt starts over.
+    pub(crate) fn forget(&mut self, partition: &Path) -> Result<()> {
+        self.partitions.remove(partition);
+        self.save()
+    }
+
+    /// Removes the progress file, once the images are no longer needed.
+    pub(crate) fn clear(self) -> Result<()> {
+        remove_file(&self.path).context(error::ProgressWrite { path: &self.path })
+    }
+
+    /// Saves the record of the completed images as the staged images for `version`, and removes
+    /// the progress file.
+    pub(crate) fn stage<P: AsRef<Path>>(self, version: &Version, staged_path: P) -> Result<()> {
+        let staged_path = staged_path.as_ref();
+        let staged = StagedImages {
+            version: version.clone(),
+            partitions: self.partitions.clone(),
+        };
+        let data = serde_json::to_vec(&staged).context(error::StagedSerialize)?;
+        write_atomic(staged_path, &data).context(error::StagedWrite { path: staged_path })?;
+        self.clear()
+    }
+
+    /// Saves progress.
+    fn save(&self) -> Result<()> {
+        let data = serde_json::to_vec(&self.partitions).context(error::ProgressSerialize)?;
+        write_atomic(&self.path, &data).context(error::ProgressWrite { path: &self.path })
+    }
+}
+
+/// `StagedImages` records the images written to the inactive partition set by the last complete
+/// update.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StagedImages {
+    pub(crate) version: Version,
+    pub(crate) partitions: BTreeMap<PathBuf, ImageProgress>,
+}
+
+impl StagedImages {
+    /// Loads the staged images from the given file, if there is one.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .context(error::StagedParse { path }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(error::StagedRead { path }),
+        }
+    }
+
+    /// Removes the record of staged images, before the inactive partition set is overwritten.
+    pub(crate) fn clear<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        remove_file(path).context(error::StagedWrite { path })
+    }
+
+    /// Checks that `partition` still holds the image recorded for it.
+    pub(crate) fn verify(&self, partition: &Path) -> Result<()> {
+        verify_image(partition, self.partitions.get(partition))
+    }
+}
+
+/// Checks that `partition` holds the complete image described by `progress`.
+fn verify_image(partition: &Path, progress: Option<&ImageProgress>) -> Result<()> {
+    let expected = match progress.and_then(|progress| progress.image.as_ref()) {
+        Some(expected) => expected,
+        None => return error::ImageIncomplete { path: partition }.fail(),
+    };
+    let mut f = File::open(partition).context(error::OpenPartition { path: partition })?;
+    let found =
+        image_digest(&mut f, expected.length).context(error::ReadPartition { path: partition })?;
+    ensure!(
+        found == *expected,
+        error::ImageVerify {
+            path: partition,
+            expected: &expected.sha256,
+            found: found.sha256,
+        }
+    );
+    Ok(())
+}
+
+/// Removes a file, if it exists.
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a file, replacing it atomically so it's never seen half-written.
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.to_owned().into_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(data)?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Writes the image from `reader` to `partition` in chunks, calling `checkpoint` with the number
+/// of bytes written after each chunk is synced.  The first `resume` bytes are assumed to have
+/// been written already; they're compared with the image instead, and written only if they
+/// differ.  Returns the length and digest of the image.
+pub(crate) fn write_image<R, F>(
+    mut reader: R,
+    partition: &mut File,
+    mut resume: u64,
+    mut checkpoint: F,
+) -> Result<ImageDigest>
+where
+    R: Read,
+    F: FnMut(u64) -> Result<()>,
+{
+    let mut digest = Context::new(&SHA256);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut existing = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let len = read_block(&mut reader, &mut chunk).context(error::WriteUpdate)?;
+        let chunk = &chunk[..len];
+        digest.update(chunk);
+
+        // Until we reach the saved offset, only write chunks that don't match the partition.
+        let mut matches = false;
+        if len > 0 && offset + len as u64 <= resume {
+            partition
+                .seek(SeekFrom::Start(offset))
+                .context(error::WriteUpdate)?;
+            let existing = &mut existing[..len];
+            matches = read_block(partition, existing).context(error::WriteUpdate)? == len
+                && existing == chunk;
+            if !matches {
+                resume = offset;
+            }
+        }
+        if !matches && len > 0 {
+            partition
+                .seek(SeekFrom::Start(offset))
+                .context(error::WriteUpdate)?;
+            partition.write_all(chunk).context(error::WriteUpdate)?;
+            partition.sync_data().context(error::WriteUpdate)?;
+        }
+        offset += len as u64;
+        if len < CHUNK_SIZE {
+            break;
+        }
+        if !matches {
+            checkpoint(offset)?;
+        }
+    }
+
+    Ok(ImageDigest {
+        length: offset,
+        sha256: hex(digest.finish().as_ref()),
+    })
+}
+
+/// Reads back the first `length` bytes of an image, returning their digest.
+pub(crate) fn image_digest<R: Read>(reader: R, length: u64) -> io::Result<ImageDigest> {
+    let mut digest = DigestSink(Context::new(&SHA256));
+    let read = io::copy(&mut reader.take(length), &mut digest)?;
+    Ok(ImageDigest {
+        length: read,
+        sha256: hex(digest.0.finish().as_ref()),
+    })
+}
+
+/// Returns the bytes as a lowercase hex string, as digests are listed in the repository.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct DigestSink(Context);
+
+impl Write for DigestSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::fs::OpenOptions;
+    use tempfile::TempDir;
+
+    fn open(path: &Path) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+            .unwrap()
+    }
+
+    /// An image of a little more than three chunks.
+    fn image() -> Vec<u8> {
+        (0..CHUNK_SIZE * 3 + 100)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resume_write() {
+        let dir = TempDir::new().unwrap();
+        let partition_path = dir.path().join("partition");
+        let mut partition = open(&partition_path);
+        let mut progress = Progress::load(dir.path().join("progress.json")).unwrap();
+        let image = image();
+
+        // Fail after the first chunk, as if we were interrupted.
+        progress.start(&partition_path, "root", "abc").unwrap();
+        let result = write_image(&image[..], &mut partition, 0, |written| {
+            progress.set_written(&partition_path, written)?;
+            error::UpdateState.fail()
+        });
+        assert!(result.is_err());
+
+        // The progress file shows where to resume.
+        let mut progress = Progress::load(dir.path().join("progress.json")).unwrap();
+        let resumed = progress.start(&partition_path, "root", "abc").unwrap();
+        assert_eq!(resumed.written, CHUNK_SIZE as u64);
+
+        let mut checkpoints = Vec::new();
+        let digest = write_image(&image[..], &mut partition, resumed.written, |written| {
+            checkpoints.push(written);
+            Ok(())
+        })
+        .unwrap();
+        // The chunk that was already written isn't checkpointed again.
+        assert_eq!(
+            checkpoints,
+            vec![CHUNK_SIZE as u64 * 2, CHUNK_SIZE as u64 * 3]
+        );
+        assert_eq!(fs::read(&partition_path).unwrap(), image);
+
+        progress.set_complete(&partition_path, digest).unwrap();
+        progress.verify(&partition_path).unwrap();
+
+        // A different target starts over.
+        let other = progress.start(&partition_path, "root", "def").unwrap();
+        assert_eq!(other.written, 0);
+        assert!(progress.verify(&partition_path).is_err());
+    }
+
+    #[test]
+    fn resume_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let partition_path = dir.path().join("partition");
+        let mut partition = open(&partition_path);
+        let image = image();
+
+        // The partition doesn't hold what the progress file claims; everything is rewritten.
+        partition.write_all(&vec![0; image.len()]).unwrap();
+        let mut checkpoints = Vec::new();
+        write_image(
+            &image[..],
+            &mut partition,
+            CHUNK_SIZE as u64 * 2,
+            |written| {
+                checkpoints.push(written);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(checkpoints.len(), 3);
+        assert_eq!(fs::read(&partition_path).unwrap(), image);
+    }
+
+    #[test]
+    fn verify_detects_changes() {
+        let dir = TempDir::new().unwrap();
+        let partition_path = dir.path().join("partition");
+        // The partition is bigger than the image.
+        let mut data = image();
+        let digest = image_digest(&data[..], data.len() as u64).unwrap();
+        data.extend_from_slice(&[1, 2, 3]);
+        fs::write(&partition_path, &data).unwrap();
+
+        let mut progress = Progress::load(dir.path().join("progress.json")).unwrap();
+        progress.start(&partition_path, "root", "abc").unwrap();
+        assert!(progress.verify(&partition_path).is_err());
+        progress.set_complete(&partition_path, digest).unwrap();
+        progress.verify(&partition_path).unwrap();
+
+        data[10] ^= 1;
+        fs::write(&partition_path, &data).unwrap();
+        assert!(progress.verify(&partition_path).is_err());
+
+        progress.clear().unwrap();
+        assert!(!dir.path().join("progress.json").exists());
+    }
+
+    #[test]
+    fn stage_images() {
+        let dir = TempDir::new().unwrap();
+        let partition_path = dir.path().join("partition");
+        let progress_path = dir.path().join("progress.json");
+        let staged_path = dir.path().join("staged.json");
+        let data = image();
+        let digest = image_digest(&data[..], data.len() as u64).unwrap();
+        fs::write(&partition_path, &data).unwrap();
+
+        let mut progress = Progress::load(&progress_path).unwrap();
+        progress.start(&partition_path, "root", "abc").unwrap();
+        progress.set_complete(&partition_path, digest).unwrap();
+        let version = Version::parse("1.2.3").unwrap();
+        progress.stage(&version, &staged_path).unwrap();
+        assert!(!progress_path.exists());
+
+        let staged = StagedImages::load(&staged_path).unwrap().unwrap();
+        assert_eq!(staged.version, version);
+        assert_eq!(staged.partitions[&partition_path].target, "root");
+        staged.verify(&partition_path).unwrap();
+        assert!(staged.verify(&dir.path().join("other")).is_err());
+
+        StagedImages::clear(&staged_path).unwrap();
+        assert!(StagedImages::load(&staged_path).unwrap().is_none());
+        // Clearing again is fine.
+        StagedImages::clear(&staged_path).unwrap();
+    }
+}
@@ -1,22 +1,74 @@
-use std::cell::{BorrowMutError, RefCell};
-use tough::{HttpTransport, Repository, Transport};
+use rand::Rng;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use snafu::{ensure, ResultExt, Snafu};
+use std::cell::{BorrowMutError, Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Take, Write};
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tough::http::ClientSettings;
+use tough::{FilesystemTransport, HttpTransport, Repository, RetryRead, Transport};
 use url::Url;
 
+/// The amount of a saved download fetched between syncs to disk.
+const SYNC_SIZE: u64 = 8 * 1024 * 1024;
+
+/// `HttpQueryTransport` fetches repository files over HTTP, adding our query parameters to each
+/// request.  It also fetches `file://` URLs from the local filesystem, without parameters, so
+/// hosts without network access can update from a repository on a local path or attached volume.
 #[derive(Debug)]
 #[allow(clippy::module_name_repetitions)]
 pub struct HttpQueryTransport {
     pub inner: HttpTransport,
+    settings: ClientSettings,
+    // tough's HttpTransport doesn't share its client, so we keep one for the fetches we make
+    // ourselves, built with the same settings.
+    client: RefCell<Option<Client>>,
     parameters: RefCell<Vec<(String, String)>>,
+    start_delay: Cell<Option<Duration>>,
+    max_bytes_per_second: Cell<Option<NonZeroU64>>,
+    saved_downloads: RefCell<HashMap<Url, PathBuf>>,
 }
 
 impl HttpQueryTransport {
     pub fn new() -> Self {
+        let settings = ClientSettings::default();
         Self {
-            inner: HttpTransport::new(),
+            inner: HttpTransport::from_settings(settings),
+            settings,
+            client: RefCell::new(None),
             parameters: RefCell::new(vec![]),
+            start_delay: Cell::new(None),
+            max_bytes_per_second: Cell::new(None),
+            saved_downloads: RefCell::new(HashMap::new()),
         }
     }
 
+    /// Applies the given limits to HTTP fetches from now on.  The next fetch waits for a random
+    /// delay of up to `limits.start_jitter` first, and each fetch is read no faster than
+    /// `limits.max_bytes_per_second`.
+    pub fn limit_downloads(&self, limits: DownloadLimits) {
+        let jitter_millis = u64::try_from(limits.start_jitter.as_millis()).unwrap_or(u64::MAX);
+        let delay = rand::thread_rng().gen_range(0, jitter_millis.saturating_add(1));
+        self.start_delay.set(Some(Duration::from_millis(delay)));
+        self.max_bytes_per_second.set(limits.max_bytes_per_second);
+    }
+
+    /// Saves the contents of `url` to the file at `path` whenever it's fetched, so an interrupted
+    /// download can be resumed rather than started over.  A fetch of `url` gives the bytes already
+    /// in the file first, then requests only the rest.  Whoever reads the stream is responsible
+    /// for verifying it, as with any other fetch, and for removing the file if its complete
+    /// contents don't verify.
+    pub fn save_download(&self, url: Url, path: PathBuf) {
+        self.saved_downloads.borrow_mut().insert(url, path);
+    }
+
     /// Try to borrow a mutable reference to parameters; returns an error if
     /// a borrow is already active
     pub fn queries_get_mut(
@@ -47,15 +99,611 @@ impl HttpQueryTransport {
 
         url
     }
+
+    /// Returns our HTTP client, building it on first use.
+    fn client(&self, url: &Url) -> Result<Client, TransportError> {
+        if let Some(client) = self.client.borrow().as_ref() {
+            // Clients share their connection pool when cloned.
+            return Ok(client.clone());
+        }
+        // The timeout applies to each read, like tough's.
+        let client = Client::builder()
+            .timeout(self.settings.timeout)
+            .connect_timeout(self.settings.connect_timeout)
+            .build()
+            .context(RangeFetch { url: url.clone() })?;
+        self.client.replace(Some(client.clone()));
+        Ok(client)
+    }
+
+    /// Fetches `url`, saving it to `path` as it's read and resuming from whatever the file
+    /// already holds.
+    fn fetch_saved(&self, url: Url, path: PathBuf) -> Result<QueryStream, TransportError> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .context(SavedFile { path: &path })?;
+        let saved = file.metadata().context(SavedFile { path: &path })?.len();
+
+        let fetcher = if url.scheme() == "file" {
+            RangeFetcher {
+                url,
+                client: None,
+                settings: self.settings,
+                max_bytes_per_second: None,
+            }
+        } else {
+            self.wait_to_start();
+            RangeFetcher {
+                client: Some(self.client(&url)?),
+                url: self.set_query_string(url),
+                settings: self.settings,
+                max_bytes_per_second: self.max_bytes_per_second.get(),
+            }
+        };
+        let (stream, start) = fetcher.fetch_with_retries(saved)?;
+        if start != saved {
+            // We got the whole file instead of the rest of it.
+            file.set_len(0).context(SavedFile { path: &path })?;
+        }
+        if start > 0 {
+            eprintln!(
+                "Resuming download of {} after {} bytes",
+                path.display(),
+                start
+            );
+        }
+        let saved = File::open(&path)
+            .context(SavedFile { path: &path })?
+            .take(start);
+
+        Ok(QueryStream::Saved(Box::new(SavedDownload {
+            saved,
+            stream,
+            file,
+            fetcher,
+            offset: start,
+            unsynced: 0,
+            tries: 0,
+        })))
+    }
+
+    /// Waits for the random delay chosen by `limit_downloads` before the first fetch.
+    fn wait_to_start(&self) {
+        if let Some(delay) = self.start_delay.take() {
+            if delay > Duration::from_secs(0) {
+                eprintln!("Waiting {:?} before downloading", delay);
+                thread::sleep(delay);
+            }
+        }
+    }
 }
 
 pub type HttpQueryRepo<'a> = Repository<'a, HttpQueryTransport>;
 
 impl Transport for HttpQueryTransport {
-    type Stream = <HttpTransport as Transport>::Stream;
-    type Error = <HttpTransport as Transport>::Error;
+    type Stream = QueryStream;
+    type Error = TransportError;
 
     fn fetch(&self, url: Url) -> Result<Self::Stream, Self::Error> {
-        self.inner.fetch(self.set_query_string(url))
+        let saved_path = self.saved_downloads.borrow().get(&url).cloned();
+        if let Some(path) = saved_path {
+            return self.fetch_saved(url, path);
+        }
+        if url.scheme() == "file" {
+            return FilesystemTransport
+                .fetch(url.clone())
+                .map(QueryStream::File)
+                .context(FileFetch { url });
+        }
+        self.wait_to_start();
+        let stream = self
+            .inner
+            .fetch(self.set_query_string(url))
+            .map(QueryStream::Http)
+            .context(HttpFetch)?;
+        Ok(limit(stream, self.max_bytes_per_second.get()))
+    }
+}
+
+/// Limits the rate at which the stream is read, if we were asked to.
+fn limit(stream: QueryStream, max_bytes_per_second: Option<NonZeroU64>) -> QueryStream {
+    match max_bytes_per_second {
+        Some(rate) => QueryStream::Limited(Box::new(RateLimited::new(stream, rate))),
+        None => stream,
+    }
+}
+
+/// Returns how long to wait after the given number of failed tries.  Like tough's
+/// `HttpTransport`, we start at the initial backoff and grow by the backoff factor after each
+/// try, up to the maximum backoff.
+fn backoff(settings: &ClientSettings, tries: u32) -> Duration {
+    let mut wait = settings.initial_backoff;
+    for _ in 1..tries {
+        wait = wait
+            .mul_f32(settings.backoff_factor)
+            .min(settings.max_backoff);
+    }
+    wait
+}
+
+/// `RangeFetcher` fetches a file starting partway through, for downloads that are resumed.
+#[derive(Debug)]
+struct RangeFetcher {
+    url: Url,
+    // None for file URLs, which are read from the filesystem.
+    client: Option<Client>,
+    settings: ClientSettings,
+    max_bytes_per_second: Option<NonZeroU64>,
+}
+
+impl RangeFetcher {
+    /// Fetches the file starting `offset` bytes in.  Returns the stream and the offset it
+    /// actually starts at; that's 0 if the server doesn't support range requests and sends the
+    /// whole file instead.
+    fn fetch(&self, offset: u64) -> Result<(QueryStream, u64), TransportError> {
+        let url = self.url.clone();
+        let client = match &self.client {
+            Some(client) => client,
+            None => {
+                let mut file = FilesystemTransport
+                    .fetch(url.clone())
+                    .context(FileFetch { url: url.clone() })?;
+                file.seek(SeekFrom::Start(offset))
+                    .context(FileFetch { url })?;
+                return Ok((QueryStream::File(file), offset));
+            }
+        };
+
+        let mut request = client.get(url.clone());
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let response = request
+            .send()
+            .and_then(Response::error_for_status)
+            .context(RangeFetch { url: url.clone() })?;
+
+        let start = if response.status() == StatusCode::PARTIAL_CONTENT {
+            // Make sure we got the range we asked for, like "bytes 100-199/200".
+            let content_range = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            ensure!(
+                content_range.starts_with(&format!("bytes {}-", offset)),
+                RangeResponse { url, content_range }
+            );
+            offset
+        } else {
+            0
+        };
+        let stream = limit(QueryStream::Range(response), self.max_bytes_per_second);
+        Ok((stream, start))
+    }
+
+    /// Calls `fetch`, trying again after connection and server errors as tough's `HttpTransport`
+    /// would.
+    fn fetch_with_retries(&self, offset: u64) -> Result<(QueryStream, u64), TransportError> {
+        let mut tries = 1;
+        loop {
+            let result = self.fetch(offset);
+            match &result {
+                Err(e) if e.is_retryable() && tries < self.settings.tries => {
+                    thread::sleep(backoff(&self.settings, tries));
+                    tries += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+}
+
+/// `SavedDownload` is a file being fetched and saved to disk.  Reads give the bytes saved by an
+/// earlier attempt, then the rest of the file as it's fetched.  Fetched bytes are written to disk
+/// before they're returned, and synced regularly, so what's been read survives an interruption.
+/// A fetch that fails partway is resumed where it stopped, with the same tries and backoff as
+/// tough's `HttpTransport`.
+#[derive(Debug)]
+pub struct SavedDownload {
+    saved: Take<File>,
+    stream: QueryStream,
+    file: File,
+    fetcher: RangeFetcher,
+    // The number of bytes written to the file.
+    offset: u64,
+    unsynced: u64,
+    // The number of failed tries since the last successful read.
+    tries: u32,
+}
+
+impl SavedDownload {
+    /// Appends the given bytes to the file.
+    fn save(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        self.unsynced += bytes.len() as u64;
+        if self.unsynced >= SYNC_SIZE {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Read for SavedDownload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.saved.read(buf)?;
+        if count > 0 {
+            return Ok(count);
+        }
+
+        loop {
+            let error = match self.stream.read(buf) {
+                Ok(0) => {
+                    self.file.sync_data()?;
+                    return Ok(0);
+                }
+                Ok(count) => {
+                    self.save(&buf[..count])?;
+                    self.tries = 0;
+                    return Ok(count);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => e,
+            };
+
+            // Keep what we have, in case we can't resume now.
+            self.file.sync_data()?;
+            self.tries += 1;
+            if self.tries >= self.fetcher.settings.tries {
+                return Err(error);
+            }
+            thread::sleep(backoff(&self.fetcher.settings, self.tries));
+            let (stream, start) = self
+                .fetcher
+                .fetch(self.offset)
+                .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+            if start != self.offset {
+                // The server sent the whole file, and we can't take back what we've returned.
+                return Err(error);
+            }
+            self.stream = stream;
+        }
+    }
+}
+
+/// `DownloadLimits` keep hosts from downloading update images all at once and as fast as they
+/// can, so that a wave of updates doesn't saturate the network or the repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DownloadLimits {
+    pub max_bytes_per_second: Option<NonZeroU64>,
+    pub start_jitter: Duration,
+}
+
+/// `QueryStream` is the contents of a file fetched by `HttpQueryTransport`.
+#[derive(Debug)]
+pub enum QueryStream {
+    Http(RetryRead),
+    File(File),
+    Range(Response),
+    Limited(Box<RateLimited<QueryStream>>),
+    Saved(Box<SavedDownload>),
+}
+
+impl Read for QueryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Http(stream) => stream.read(buf),
+            Self::File(file) => file.read(buf),
+            Self::Range(response) => response.read(buf),
+            Self::Limited(stream) => stream.read(buf),
+            Self::Saved(stream) => stream.read(buf),
+        }
+    }
+}
+
+/// `RateLimited` reads from a stream no faster than a given number of bytes per second, on
+/// average since the first read.
+#[derive(Debug)]
+pub struct RateLimited<R> {
+    inner: R,
+    bytes_per_second: NonZeroU64,
+    start: Option<Instant>,
+    read: u64,
+}
+
+impl<R> RateLimited<R> {
+    pub fn new(inner: R, bytes_per_second: NonZeroU64) -> Self {
+        Self {
+            inner,
+            bytes_per_second,
+            start: None,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for RateLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        // Read at most a tenth of a second's worth at a time, so the rate stays steady.
+        let chunk = usize::try_from(self.bytes_per_second.get() / 10)
+            .unwrap_or(usize::MAX)
+            .max(1)
+            .min(buf.len());
+        let count = self.inner.read(&mut buf[..chunk])?;
+        self.read += count as u64;
+
+        let due = Duration::from_micros(
+            self.read.saturating_mul(1_000_000) / self.bytes_per_second.get(),
+        );
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(count)
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[allow(clippy::module_name_repetitions)]
+pub enum TransportError {
+    #[snafu(display("{}", source))]
+    HttpFetch { source: tough::error::Error },
+
+    #[snafu(display("Failed to read {}: {}", url, source))]
+    FileFetch { url: Url, source: io::Error },
+
+    #[snafu(display("Failed to fetch {}: {}", url, source))]
+    RangeFetch { url: Url, source: reqwest::Error },
+
+    #[snafu(display("Fetching {} returned the wrong range: '{}'", url, content_range))]
+    RangeResponse { url: Url, content_range: String },
+
+    #[snafu(display("Failed to save download to {}: {}", path.display(), source))]
+    SavedFile { path: PathBuf, source: io::Error },
+}
+
+impl TransportError {
+    /// Returns whether a fetch that failed this way is worth trying again: the request got no
+    /// response, or the server had an error.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::RangeFetch { source, .. } => source
+                .status()
+                .map_or(true, |status| status.is_server_error()),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::thread::JoinHandle;
+    use tempfile::TempDir;
+
+    #[test]
+    fn fetch_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("manifest.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let transport = HttpQueryTransport::new();
+        transport
+            .queries_get_mut()
+            .unwrap()
+            .push(("seed".to_string(), "123".to_string()));
+        let url = Url::from_file_path(&path).unwrap();
+        let mut contents = String::new();
+        transport
+            .fetch(url)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "{}");
+
+        let missing = Url::from_file_path(dir.path().join("missing")).unwrap();
+        assert!(transport.fetch(missing).is_err());
+    }
+
+    #[test]
+    fn rate_limited() {
+        let data = vec![7; 3000];
+        let rate = NonZeroU64::new(10_000).unwrap();
+        let mut stream = RateLimited::new(data.as_slice(), rate);
+        let mut contents = Vec::new();
+        let start = Instant::now();
+        stream.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, data);
+        // 3000 bytes at 10000 bytes per second
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    /// How the test server answers a request.
+    #[derive(Clone, Copy)]
+    enum Reply {
+        /// Promise the whole target, but close the connection after this many bytes.
+        Cut(usize),
+        /// Send the range asked for, or the whole target.
+        Range,
+        /// Send the whole target, even if asked for a range.
+        Whole,
+    }
+
+    /// The Range header of each request the test server got, and the number of bytes it sent in
+    /// response.
+    type Requests = Vec<(Option<String>, usize)>;
+
+    /// Serves `data` over HTTP, answering one request with each reply in turn.  The server thread
+    /// returns the requests it answered.
+    fn serve(data: Vec<u8>, replies: Vec<Reply>) -> (Url, JoinHandle<Requests>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/target", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let len = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                }
+                let range = String::from_utf8(request)
+                    .unwrap()
+                    .lines()
+                    .find_map(|line| {
+                        if line.to_lowercase().starts_with("range:") {
+                            Some(line["range:".len()..].trim().to_string())
+                        } else {
+                            None
+                        }
+                    });
+                let start = match (reply, &range) {
+                    (Reply::Range, Some(range)) => Some(
+                        range
+                            .trim_start_matches("bytes=")
+                            .trim_end_matches('-')
+                            .parse::<usize>()
+                            .unwrap(),
+                    ),
+                    _ => None,
+                };
+
+                let body = match (reply, start) {
+                    (_, Some(start)) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                             Content-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                            data.len() - start,
+                            start,
+                            data.len() - 1,
+                            data.len()
+                        )
+                        .unwrap();
+                        &data[start..]
+                    }
+                    (reply, None) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            data.len()
+                        )
+                        .unwrap();
+                        match reply {
+                            Reply::Cut(len) => &data[..len],
+                            _ => &data[..],
+                        }
+                    }
+                };
+                stream.write_all(body).unwrap();
+                requests.push((range, body.len()));
+            }
+            requests
+        });
+        (Url::parse(&url).unwrap(), server)
+    }
+
+    fn target() -> Vec<u8> {
+        (0..300_000)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect()
+    }
+
+    /// Fetches `url` with a transport that saves it to `path`, returning what was read.
+    fn fetch_saved(url: &Url, path: &Path) -> io::Result<Vec<u8>> {
+        let transport = HttpQueryTransport::new();
+        transport.save_download(url.clone(), path.to_path_buf());
+        let mut contents = Vec::new();
+        transport
+            .fetch(url.clone())
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn saved_download_retried() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("download");
+        let data = target();
+        let (url, server) = serve(data.clone(), vec![Reply::Cut(100_000), Reply::Range]);
+
+        // The fetch is cut off, and picks up where it stopped.
+        assert_eq!(fetch_saved(&url, &path).unwrap(), data);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (None, 100_000),
+                (Some("bytes=100000-".to_string()), data.len() - 100_000)
+            ]
+        );
+    }
+
+    #[test]
+    fn saved_download_resumed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("download");
+        let data = target();
+        fs::write(&path, &data[..1000]).unwrap();
+        let (url, server) = serve(data.clone(), vec![Reply::Range]);
+
+        // What we saved earlier is read first, and only the rest is fetched.
+        assert_eq!(fetch_saved(&url, &path).unwrap(), data);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![(Some("bytes=1000-".to_string()), data.len() - 1000)]
+        );
+    }
+
+    #[test]
+    fn saved_download_without_ranges() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("download");
+        let data = target();
+        fs::write(&path, &data[..1000]).unwrap();
+        let replies = vec![Reply::Whole, Reply::Cut(1000), Reply::Cut(0)];
+        let (url, server) = serve(data.clone(), replies);
+
+        // The server sends the whole file, so we start over.
+        assert_eq!(fetch_saved(&url, &path).unwrap(), data);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // A fetch that's cut off can't continue if the server won't send the rest, but what we
+        // got is kept for next time.
+        fs::remove_file(&path).unwrap();
+        assert!(fetch_saved(&url, &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), &data[..1000]);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (Some("bytes=1000-".to_string()), data.len()),
+                (None, 1000),
+                (Some("bytes=1000-".to_string()), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn saved_download_file() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("target");
+        let path = dir.path().join("download");
+        let data = target();
+        fs::write(&source, &data).unwrap();
+        fs::write(&path, &data[..1000]).unwrap();
+
+        let url = Url::from_file_path(&source).unwrap();
+        assert_eq!(fetch_saved(&url, &path).unwrap(), data);
+        assert_eq!(fs::read(&path).unwrap(), data);
     }
 }
@@ -3,6 +3,7 @@
 
 Currently implemented:
 * building repos, whether starting from an existing repo or from scratch
+* halting (pausing) and resuming the rollout of an update in an existing repo
 * registering and copying EC2 AMIs
 * Marking EC2 AMIs public (or private again)
 * setting SSM parameters based on built AMIs
@@ -45,6 +46,12 @@ fn run() -> Result<()> {
 
     match args.subcommand {
         SubCommand::Repo(ref repo_args) => repo::run(&args, &repo_args).context(error::Repo),
+        SubCommand::PauseUpdate(ref pause_args) => {
+            repo::halt::pause(&args, &pause_args).context(error::PauseUpdate)
+        }
+        SubCommand::ResumeUpdate(ref halt_args) => {
+            repo::halt::resume(&args, &halt_args).context(error::ResumeUpdate)
+        }
         SubCommand::Ami(ref ami_args) => {
             let mut rt = Runtime::new().context(error::Runtime)?;
             rt.block_on(async { aws::ami::run(&args, &ami_args).await.context(error::Ami) })
@@ -98,6 +105,8 @@ struct Args {
 #[derive(Debug, StructOpt)]
 enum SubCommand {
     Repo(repo::RepoArgs),
+    PauseUpdate(repo::halt::PauseArgs),
+    ResumeUpdate(repo::halt::HaltArgs),
 
     Ami(aws::ami::AmiArgs),
     PublishAmi(aws::publish_ami::PublishArgs),
@@ -138,6 +147,9 @@ mod error {
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: simplelog::TermLogError },
 
+        #[snafu(display("Failed to halt update: {}", source))]
+        PauseUpdate { source: crate::repo::Error },
+
         #[snafu(display("Failed to publish AMI: {}", source))]
         PublishAmi {
             source: crate::aws::publish_ami::Error,
@@ -151,6 +163,9 @@ mod error {
         #[snafu(display("Failed to build repo: {}", source))]
         Repo { source: crate::repo::Error },
 
+        #[snafu(display("Failed to resume update: {}", source))]
+        ResumeUpdate { source: crate::repo::Error },
+
         #[snafu(display("Failed to create async runtime: {}", source))]
         Runtime { source: std::io::Error },
 
@@ -1,5 +1,6 @@
 //! The repo module owns the 'repo' subcommand and controls the process of building a repository.
 
+pub(crate) mod halt;
 mod transport;
 
 use crate::config::{InfraConfig, RepoExpirationPolicy, SigningKeyConfig};
@@ -10,6 +11,7 @@ use log::{debug, info, trace};
 use parse_datetime::parse_datetime;
 use semver::Version;
 use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fs::{self, File};
 use std::num::NonZeroU64;
@@ -26,7 +28,7 @@ use tough::{
 use tough_kms::{KmsKeySource, KmsSigningAlgorithm};
 use tough_ssm::SsmKeySource;
 use transport::RepoTransport;
-use update_metadata::{Images, Manifest, Release, UpdateWaves};
+use update_metadata::{DeltaImages, Images, Manifest, Release, UpdateWaves};
 use url::Url;
 
 lazy_static! {
@@ -62,6 +64,21 @@ pub(crate) struct RepoArgs {
     /// Path to the image containing the verity hashes
     hash_image: PathBuf,
 
+    // Optionally add deltas, generated with `updata generate-delta`, for hosts updating from an
+    // earlier version
+    #[structopt(long, parse(try_from_str=friendly_version))]
+    /// The version the delta images were generated from
+    delta_from: Option<Version>,
+    #[structopt(long, parse(from_os_str))]
+    /// Path to the delta for the boot image; required with --delta-from
+    delta_boot_image: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str))]
+    /// Path to the delta for the root image; required with --delta-from
+    delta_root_image: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str))]
+    /// Path to the delta for the verity hash image; required with --delta-from
+    delta_hash_image: Option<PathBuf>,
+
     // Optionally add other files to the repo
     #[structopt(long = "link-target", parse(from_os_str))]
     /// Optional paths to add as targets and symlink into repo
@@ -96,6 +113,26 @@ pub(crate) struct RepoArgs {
     outdir: PathBuf,
 }
 
+impl RepoArgs {
+    /// Returns the paths to the boot, root, and hash delta images, if deltas were requested.
+    fn delta_images(&self) -> Result<Option<[&PathBuf; 3]>> {
+        if self.delta_from.is_none() {
+            return Ok(None);
+        }
+        Ok(Some([
+            self.delta_boot_image
+                .as_ref()
+                .context(error::MissingDeltaImage { image: "boot" })?,
+            self.delta_root_image
+                .as_ref()
+                .context(error::MissingDeltaImage { image: "root" })?,
+            self.delta_hash_image
+                .as_ref()
+                .context(error::MissingDeltaImage { image: "hash" })?,
+        ]))
+    }
+}
+
 /// Adds update, migrations, and waves to the Manifest
 fn update_manifest(repo_args: &RepoArgs, manifest: &mut Manifest) -> Result<()> {
     // Add update   =^..^=   =^..^=   =^..^=   =^..^=
@@ -113,6 +150,7 @@ fn update_manifest(repo_args: &RepoArgs, manifest: &mut Manifest) -> Result<()>
         boot: filename(&repo_args.boot_image)?,
         root: filename(&repo_args.root_image)?,
         hash: filename(&repo_args.hash_image)?,
+        deltas: BTreeMap::new(),
     };
 
     info!(
@@ -129,6 +167,30 @@ fn update_manifest(repo_args: &RepoArgs, manifest: &mut Manifest) -> Result<()>
         )
         .context(error::AddUpdate)?;
 
+    // Add deltas   =^..^=   =^..^=   =^..^=   =^..^=
+
+    if let (Some(from), Some([boot, root, hash])) =
+        (&repo_args.delta_from, repo_args.delta_images()?)
+    {
+        info!(
+            "Adding deltas to manifest for updates from version: {}",
+            from
+        );
+        manifest
+            .add_delta(
+                repo_args.variant.clone(),
+                repo_args.arch.clone(),
+                repo_args.version.clone(),
+                from,
+                &DeltaImages {
+                    boot: filename(boot)?,
+                    root: filename(root)?,
+                    hash: filename(hash)?,
+                },
+            )
+            .context(error::AddDelta)?;
+    }
+
     // Add migrations   =^..^=   =^..^=   =^..^=   =^..^=
 
     info!(
@@ -205,16 +267,29 @@ where
     debug!("Adding target for manifest.json");
     editor.add_target("manifest.json", manifest_target).context(error::AddTarget { path: "manifest.json" })?;
 
+    let expiration_start_time = repo_args.release_start_time.unwrap_or(*DEFAULT_START_TIME);
+    update_editor_metadata(
+        editor,
+        &repo_args.repo_expiration_policy_path,
+        expiration_start_time,
+    )
+}
+
+/// Adds expirations, starting at the given time, and version to the RepositoryEditor
+fn update_editor_metadata(
+    editor: &mut RepositoryEditor<'_, RepoTransport>,
+    repo_expiration_policy_path: &Path,
+    expiration_start_time: DateTime<Utc>,
+) -> Result<()> {
     // Add expirations   =^..^=   =^..^=   =^..^=   =^..^=
 
     info!(
         "Using repo expiration policy from path: {}",
-        repo_args.repo_expiration_policy_path.display()
+        repo_expiration_policy_path.display()
     );
-    let expiration = RepoExpirationPolicy::from_path(&repo_args.repo_expiration_policy_path)
-        .context(error::Config)?;
+    let expiration =
+        RepoExpirationPolicy::from_path(repo_expiration_policy_path).context(error::Config)?;
 
-    let expiration_start_time = repo_args.release_start_time.unwrap_or(*DEFAULT_START_TIME);
     let snapshot_expiration = expiration_start_time + expiration.snapshot_expiration;
     let targets_expiration = expiration_start_time + expiration.targets_expiration;
     let timestamp_expiration = expiration_start_time + expiration.timestamp_expiration;
@@ -248,7 +323,9 @@ where
 /// If the infra config has a repo section defined for the given repo, and it has metadata base and
 /// targets URLs defined, returns those URLs, otherwise None.
 fn repo_urls<'a>(
-    repo_args: &RepoArgs,
+    repo: &str,
+    variant: &str,
+    arch: &str,
     infra_config: &'a InfraConfig,
 ) -> Result<Option<(Url, &'a Url)>> {
     let repo_config = infra_config
@@ -257,9 +334,9 @@ fn repo_urls<'a>(
         .context(error::MissingConfig {
             missing: "repo section",
         })?
-        .get(&repo_args.repo)
+        .get(repo)
         .context(error::MissingConfig {
-            missing: format!("definition for repo {}", &repo_args.repo),
+            missing: format!("definition for repo {}", repo),
         })?;
 
     // Check if both URLs are set
@@ -270,10 +347,8 @@ fn repo_urls<'a>(
             } else {
                 "/"
             };
-            let metadata_url_str = format!(
-                "{}{}{}/{}",
-                metadata_base_url, base_slash, repo_args.variant, repo_args.arch
-            );
+            let metadata_url_str =
+                format!("{}{}{}/{}", metadata_base_url, base_slash, variant, arch);
             let metadata_url = Url::parse(&metadata_url_str).context(error::ParseUrl {
                 input: &metadata_url_str,
             })?;
@@ -348,6 +423,35 @@ where
     }
 }
 
+/// Returns the source for the named signing key from the infra config.
+fn key_source(infra_config: &InfraConfig, signing_key: &str) -> Result<Box<dyn KeySource>> {
+    let signing_key_config = infra_config
+        .signing_keys
+        .as_ref()
+        .context(error::MissingConfig {
+            missing: "signing_keys",
+        })?
+        .get(signing_key)
+        .context(error::MissingConfig {
+            missing: format!("profile {} in signing_keys", signing_key),
+        })?;
+
+    Ok(match signing_key_config {
+        SigningKeyConfig::file { path } => Box::new(LocalKeySource { path: path.clone() }),
+        SigningKeyConfig::kms { key_id } => Box::new(KmsKeySource {
+            profile: None,
+            key_id: key_id.clone(),
+            client: None,
+            signing_algorithm: KmsSigningAlgorithm::RsassaPssSha256,
+        }),
+        SigningKeyConfig::ssm { parameter } => Box::new(SsmKeySource {
+            profile: None,
+            parameter_name: parameter.clone(),
+            key_id: None,
+        }),
+    })
+}
+
 /// Common entrypoint from main()
 pub(crate) fn run(args: &Args, repo_args: &RepoArgs) -> Result<()> {
     let metadata_out_dir = repo_args
@@ -381,7 +485,12 @@ pub(crate) fn run(args: &Args, repo_args: &RepoArgs) -> Result<()> {
         })?;
 
     // Build a repo editor and manifest, from an existing repo if available, otherwise fresh
-    let maybe_urls = repo_urls(&repo_args, &infra_config)?;
+    let maybe_urls = repo_urls(
+        &repo_args.repo,
+        &repo_args.variant,
+        &repo_args.arch,
+        &infra_config,
+    )?;
     let workdir = tempdir().context(error::TempDir)?;
     let transport = RepoTransport::default();
     let (mut editor, mut manifest) = if let Some((metadata_url, targets_url)) = maybe_urls.as_ref() {
@@ -419,43 +528,26 @@ pub(crate) fn run(args: &Args, repo_args: &RepoArgs) -> Result<()> {
 
     // Add manifest and targets to editor
     let copy_targets = &repo_args.copy_targets;
-    let link_targets = repo_args.link_targets.iter().chain(vec![
-        &repo_args.boot_image,
-        &repo_args.root_image,
-        &repo_args.hash_image,
-    ]);
+    let delta_targets = repo_args
+        .delta_images()?
+        .map(|images| images.to_vec())
+        .unwrap_or_default();
+    let link_targets = repo_args
+        .link_targets
+        .iter()
+        .chain(vec![
+            &repo_args.boot_image,
+            &repo_args.root_image,
+            &repo_args.hash_image,
+        ])
+        .chain(delta_targets);
     let all_targets = copy_targets.iter().chain(link_targets.clone());
 
     update_editor(&repo_args, &mut editor, all_targets, &manifest_path)?;
 
     // Sign repo   =^..^=   =^..^=   =^..^=   =^..^=
 
-    let signing_key_config = infra_config
-        .signing_keys
-        .as_ref()
-        .context(error::MissingConfig {
-            missing: "signing_keys",
-        })?
-        .get(&repo_args.signing_key)
-        .context(error::MissingConfig {
-            missing: format!("profile {} in signing_keys", &repo_args.signing_key),
-        })?;
-
-    let key_source: Box<dyn KeySource> = match signing_key_config {
-        SigningKeyConfig::file { path } => Box::new(LocalKeySource { path: path.clone() }),
-        SigningKeyConfig::kms { key_id } => Box::new(KmsKeySource {
-            profile: None,
-            key_id: key_id.clone(),
-            client: None,
-            signing_algorithm: KmsSigningAlgorithm::RsassaPssSha256,
-        }),
-        SigningKeyConfig::ssm { parameter } => Box::new(SsmKeySource {
-            profile: None,
-            parameter_name: parameter.clone(),
-            key_id: None,
-        }),
-    };
-
+    let key_source = key_source(&infra_config, &repo_args.signing_key)?;
     let signed_repo = editor.sign(&[key_source]).context(error::RepoSign)?;
 
     // Write repo   =^..^=   =^..^=   =^..^=   =^..^=
@@ -537,6 +629,11 @@ mod error {
             source: update_metadata::error::Error,
         },
 
+        #[snafu(display("Failed to add deltas to manifest: {}", source))]
+        AddDelta {
+            source: update_metadata::error::Error,
+        },
+
         #[snafu(display("Failed to add new target '{}' to repo: {}", path.display(), source))]
         AddTarget {
             path: PathBuf,
@@ -568,6 +665,11 @@ mod error {
         #[snafu(display("Failed to read '{}': {}", path.display(), source))]
         File { path: PathBuf, source: io::Error },
 
+        #[snafu(display("Failed to halt update in manifest: {}", source))]
+        HaltUpdate {
+            source: update_metadata::error::Error,
+        },
+
         #[snafu(display("Invalid path given for image file: '{}'", path.display()))]
         InvalidImagePath { path: PathBuf },
 
@@ -593,6 +695,9 @@ mod error {
         #[snafu(display("Infra.toml is missing {}", missing))]
         MissingConfig { missing: String },
 
+        #[snafu(display("--delta-from given without a {} delta image", image))]
+        MissingDeltaImage { image: String },
+
         #[snafu(display("Failed to create new repo editor: {}", source))]
         NewEditor { source: tough::error::Error },
 
@@ -642,6 +747,11 @@ mod error {
             source: tough::error::Error,
         },
 
+        #[snafu(display("Failed to resume update in manifest: {}", source))]
+        ResumeUpdate {
+            source: update_metadata::error::Error,
+        },
+
         #[snafu(display("Failed to set targets expiration to {}: {}", expiration, source))]
         SetTargetsExpiration {
             expiration: DateTime<Utc>,
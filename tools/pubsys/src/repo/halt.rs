@@ -0,0 +1,223 @@
+//! The halt module owns the 'pause-update' and 'resume-update' subcommands, which halt or resume
+//! the rollout of an update in an existing repo by signing a new manifest for it.
+
+use super::transport::RepoTransport;
+use super::{
+    error, key_source, load_editor_and_manifest, repo_urls, update_editor_metadata, Result,
+};
+use crate::config::InfraConfig;
+use crate::{friendly_version, Args};
+use chrono::Utc;
+use log::{debug, info, trace};
+use semver::Version;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use tempfile::{tempdir, NamedTempFile};
+use tough::{editor::signed::PathExists, schema::Target};
+use update_metadata::Manifest;
+
+/// Identifies the update to halt or resume, and the repo it's in
+#[derive(Debug, StructOpt)]
+#[structopt(setting = clap::AppSettings::DeriveDisplayOrder)]
+pub(crate) struct HaltArgs {
+    #[structopt(long)]
+    /// Use this named repo from Infra.toml
+    repo: String,
+    #[structopt(long)]
+    /// The architecture of the repo and the update
+    arch: String,
+    #[structopt(long, parse(try_from_str=friendly_version))]
+    /// The version of the update
+    version: Version,
+    #[structopt(long)]
+    /// The variant of the update
+    variant: String,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Path to file that defines when repo metadata should expire
+    repo_expiration_policy_path: PathBuf,
+
+    #[structopt(long)]
+    /// Use this named key from Infra.toml
+    signing_key: String,
+
+    #[structopt(long, parse(from_os_str))]
+    /// Where to store the new repo metadata and manifest
+    outdir: PathBuf,
+}
+
+/// Halts the rollout of an update
+#[derive(Debug, StructOpt)]
+#[structopt(setting = clap::AppSettings::DeriveDisplayOrder)]
+pub(crate) struct PauseArgs {
+    #[structopt(flatten)]
+    halt_args: HaltArgs,
+
+    #[structopt(long)]
+    /// Why the rollout is being halted, for the record
+    reason: Option<String>,
+}
+
+/// Entrypoint for 'pause-update' from main()
+pub(crate) fn pause(args: &Args, pause_args: &PauseArgs) -> Result<()> {
+    let halt_args = &pause_args.halt_args;
+    edit_manifest(args, halt_args, |manifest| {
+        info!(
+            "Halting update for version: {}, arch: {}, variant: {}",
+            halt_args.version, halt_args.arch, halt_args.variant
+        );
+        manifest
+            .halt_update(
+                halt_args.variant.clone(),
+                halt_args.arch.clone(),
+                halt_args.version.clone(),
+                Utc::now(),
+                pause_args.reason.clone(),
+            )
+            .context(error::HaltUpdate)?;
+        Ok(())
+    })
+}
+
+/// Entrypoint for 'resume-update' from main()
+pub(crate) fn resume(args: &Args, halt_args: &HaltArgs) -> Result<()> {
+    edit_manifest(args, halt_args, |manifest| {
+        info!(
+            "Resuming update for version: {}, arch: {}, variant: {}",
+            halt_args.version, halt_args.arch, halt_args.variant
+        );
+        manifest
+            .resume_update(
+                halt_args.variant.clone(),
+                halt_args.arch.clone(),
+                halt_args.version.clone(),
+            )
+            .context(error::ResumeUpdate)?;
+        Ok(())
+    })
+}
+
+/// Loads the existing repo, makes the given change to its manifest, and writes out newly signed
+/// repo metadata along with the new manifest.  The other targets are left where they are.
+fn edit_manifest<F>(args: &Args, halt_args: &HaltArgs, edit: F) -> Result<()>
+where
+    F: FnOnce(&mut Manifest) -> Result<()>,
+{
+    let metadata_out_dir = halt_args
+        .outdir
+        .join(&halt_args.variant)
+        .join(&halt_args.arch);
+    let targets_out_dir = halt_args.outdir.join("targets");
+
+    // If the given metadata directory exists, throw an error.  We dont want to overwrite a user's
+    // existing repository.
+    ensure!(
+        !Path::exists(&metadata_out_dir),
+        error::RepoExists {
+            path: metadata_out_dir
+        }
+    );
+
+    // Load repo   =^..^=   =^..^=   =^..^=   =^..^=
+
+    info!(
+        "Using infra config from path: {}",
+        args.infra_config_path.display()
+    );
+    let infra_config = InfraConfig::from_path(&args.infra_config_path).context(error::Config)?;
+    trace!("Parsed infra config: {:?}", infra_config);
+    let root_role_path = infra_config
+        .root_role_path
+        .as_ref()
+        .context(error::MissingConfig {
+            missing: "root_role_path",
+        })?;
+
+    // There's nothing to halt without an existing repo
+    let (metadata_url, targets_url) = repo_urls(
+        &halt_args.repo,
+        &halt_args.variant,
+        &halt_args.arch,
+        &infra_config,
+    )?
+    .context(error::MissingConfig {
+        missing: format!(
+            "metadata_base_url and targets_url for repo {}",
+            halt_args.repo
+        ),
+    })?;
+    let workdir = tempdir().context(error::TempDir)?;
+    let transport = RepoTransport::default();
+    let (mut editor, mut manifest) = load_editor_and_manifest(
+        root_role_path,
+        &transport,
+        workdir.path(),
+        &metadata_url,
+        targets_url,
+    )?
+    .context(error::RepoNotFound {
+        url: metadata_url.clone(),
+    })?;
+
+    // Update manifest   =^..^=   =^..^=   =^..^=   =^..^=
+
+    edit(&mut manifest)?;
+    let manifest_path = NamedTempFile::new()
+        .context(error::TempFile)?
+        .into_temp_path();
+    update_metadata::write_file(&manifest_path, &manifest).context(error::ManifestWrite {
+        path: &manifest_path,
+    })?;
+
+    let manifest_target = Target::from_path(&manifest_path).context(error::BuildTarget {
+        path: &manifest_path,
+    })?;
+    debug!("Replacing target for manifest.json");
+    editor
+        .add_target("manifest.json", manifest_target)
+        .context(error::AddTarget {
+            path: "manifest.json",
+        })?;
+    update_editor_metadata(
+        &mut editor,
+        &halt_args.repo_expiration_policy_path,
+        Utc::now(),
+    )?;
+
+    // Sign repo   =^..^=   =^..^=   =^..^=   =^..^=
+
+    let key_source = key_source(&infra_config, &halt_args.signing_key)?;
+    let signed_repo = editor.sign(&[key_source]).context(error::RepoSign)?;
+
+    // Write repo   =^..^=   =^..^=   =^..^=   =^..^=
+
+    info!("Writing manifest to: {}", targets_out_dir.display());
+    fs::create_dir_all(&targets_out_dir).context(error::CreateDir {
+        path: &targets_out_dir,
+    })?;
+    signed_repo
+        .copy_target(
+            &manifest_path,
+            &targets_out_dir,
+            PathExists::Fail,
+            Some("manifest.json"),
+        )
+        .context(error::CopyTarget {
+            target: &manifest_path,
+            path: &targets_out_dir,
+        })?;
+
+    info!("Writing repo metadata to: {}", metadata_out_dir.display());
+    fs::create_dir_all(&metadata_out_dir).context(error::CreateDir {
+        path: &metadata_out_dir,
+    })?;
+    signed_repo
+        .write(&metadata_out_dir)
+        .context(error::RepoWrite {
+            path: &halt_args.outdir,
+        })?;
+
+    Ok(())
+}